@@ -1,40 +1,157 @@
 //! Analyzes ClickHouse query and error logs streamed via channels.
-use crate::model::{Error, QueriesSortBy, QueryLog, QueryLogExtended, QueryLogTotal};
-use std::collections::HashMap;
+use crate::client::ImpactExpr;
+use crate::model::{
+    BelowMinCount, DatabaseImpact, Error, ErrorDelta, ErrorsSortBy, ImpactWeights, Merge,
+    MergesSortBy, Mutation, MutationsSortBy, NodeTotal, Part, ProfileEventsMetrics, QueriesSortBy,
+    QueryLog, QueryLogExtended, QueryLogTotal, QueryLogTotalBucket, QueryTextDivergence, Replica,
+    ReplicaLag, ReplicatedTable, Setting, SettingValues, SettingsDiff, TableImpact, TotalBucket,
+    UserImpact,
+};
+use futures::future::join_all;
+use futures::stream::{select_all, unfold, StreamExt};
+use rustc_hash::FxHashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use time::OffsetDateTime;
 use tokio::sync::mpsc::Receiver;
 
 struct Analyzer {
     total_queries: QueryLogTotal,
-    queries: HashMap<u64, QueryLog>,
+    // Keys are already well-distributed hashes and codes, SipHash would only slow merging down.
+    queries: FxHashMap<u64, QueryLog>,
     query_extended: Option<QueryLogExtended>,
-    errors: HashMap<i32, Error>,
+    errors: FxHashMap<i32, Error>,
+    hosts: BTreeSet<String>,
+    settings: BTreeMap<String, BTreeMap<String, String>>,
 }
 
-/// Aggregates ClickHouse queries from a stream and returns the top entries.
+/// Aggregates ClickHouse queries from streams and returns the top entries.
 ///
-/// This function receives a stream of [`QueryLog`] records via a channel and
-/// groups them by their `normalized_query_hash`. It then sorts and returns
-/// the top `limit` queries based on the specified [`QueriesSortBy`] criteria.
+/// This function receives streams of [`QueryLog`] records via channels, typically
+/// one per ClickHouse node, and groups them by their `normalized_query_hash`.
+/// It then sorts and returns the top `limit` queries based on the specified
+/// [`QueriesSortBy`] criteria.
+///
+/// With several streams and CPUs, every stream is aggregated into its own partial
+/// [`Analyzer`] on a separate task, then the partials are merged. With a single
+/// stream or CPU one consumer reads all streams, as the partials would only add
+/// the cost of the final merge.
 ///
 /// # Arguments
 ///
-/// - `receiver`: An asynchronous receiver stream of [`QueryLog`] entries.
+/// - `receivers`: Asynchronous receiver streams of [`QueryLog`] entries.
 /// - `limit`: The number of top queries to return.
 /// - `sort_by`: Metric to rank the queries by (e.g. impact, I/O, duration).
+/// - `check_query_texts`: Also compare the query texts a fingerprint has on each stream.
+/// - `weights`, `impact_expr`: Derive the impacts of the merged fingerprints, see
+///   [`QueryLog::compute_impacts`].
 ///
 /// # Returns
 ///
-/// A `Vec<QueryLog>` containing the top `limit` queries.
+/// The top `limit` queries, and with `check_query_texts` the fingerprints among
+/// them whose texts differ beyond literals, whitespace and case.
 pub async fn top_queries(
-    receiver: Receiver<QueryLog>,
+    receivers: Vec<Receiver<QueryLog>>,
     limit: usize,
     sort_by: QueriesSortBy,
-) -> Vec<QueryLog> {
+    check_query_texts: bool,
+    weights: ImpactWeights,
+    impact_expr: Option<ImpactExpr>,
+) -> (Vec<QueryLog>, Vec<QueryTextDivergence>) {
+    let parallel = receivers.len() > 1
+        && std::thread::available_parallelism().is_ok_and(|cpus| cpus.get() > 1);
+    let (mut analyzer, shapes) = if parallel {
+        collect_logs_parallel(receivers, check_query_texts).await
+    } else {
+        collect_logs_single(receivers, check_query_texts).await
+    };
+    // Показатели выводятся из объединённых сумм, а не складываются по узлам
+    for log in analyzer.queries.values_mut() {
+        log.compute_impacts(&weights, impact_expr.as_ref());
+    }
+
+    let top_queries = analyzer.top_queries(limit, sort_by);
+    let divergences = top_queries
+        .iter()
+        .filter_map(|query| {
+            let texts = shapes.get(&query.normalized_query_hash)?.len();
+            (texts > 1).then_some(QueryTextDivergence {
+                normalized_query_hash: query.normalized_query_hash,
+                texts,
+            })
+        })
+        .collect();
+    (top_queries, divergences)
+}
+
+// Один потребитель читает все потоки вперемешку; формы текста запоминаются до
+// объединения, так как на каждом потоке отпечаток встречается один раз
+async fn collect_logs_single(
+    receivers: Vec<Receiver<QueryLog>>,
+    check_query_texts: bool,
+) -> (Analyzer, FxHashMap<u64, BTreeSet<String>>) {
+    let mut logs = select_all(receivers.into_iter().map(|receiver| {
+        Box::pin(unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|log| (log, receiver))
+        }))
+    }));
+
     let mut analyzer = Analyzer::new();
+    let mut shapes: FxHashMap<u64, BTreeSet<String>> = FxHashMap::default();
+    while let Some(log) = logs.next().await {
+        if check_query_texts {
+            shapes
+                .entry(log.normalized_query_hash)
+                .or_default()
+                .insert(query_shape(&log.query));
+        }
+        analyzer.merge_query(log);
+    }
+
+    (analyzer, shapes)
+}
+
+// С `check_query_texts` также собирает различные формы текста запроса каждого
+// отпечатка: на каждом потоке отпечаток встречается один раз
+async fn collect_logs_parallel(
+    receivers: Vec<Receiver<QueryLog>>,
+    check_query_texts: bool,
+) -> (Analyzer, FxHashMap<u64, BTreeSet<String>>) {
+    let partials = receivers.into_iter().map(|receiver| {
+        tokio::spawn(async move {
+            let mut partial = Analyzer::new();
+            partial.collect_logs(receiver).await;
+            partial
+        })
+    });
+
+    let mut partials: Vec<Analyzer> = join_all(partials)
+        .await
+        .into_iter()
+        .map(|partial| partial.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic())))
+        .collect();
 
-    analyzer.collect_logs(receiver).await;
+    let mut shapes: FxHashMap<u64, BTreeSet<String>> = FxHashMap::default();
+    if check_query_texts {
+        for log in partials.iter().flat_map(|partial| partial.queries.values()) {
+            shapes
+                .entry(log.normalized_query_hash)
+                .or_default()
+                .insert(query_shape(&log.query));
+        }
+    }
 
-    analyzer.top_queries(limit, sort_by)
+    // Merge into the largest partial, so its entries don't need to be re-inserted.
+    partials.sort_by_key(|partial| std::cmp::Reverse(partial.queries.len()));
+    let mut partials = partials.into_iter();
+    let mut analyzer = partials.next().unwrap_or_else(Analyzer::new);
+    for partial in partials {
+        for log in partial.queries.into_values() {
+            analyzer.merge_query(log);
+        }
+    }
+
+    (analyzer, shapes)
 }
 
 /// Aggregates extended ClickHouse query metrics for a single fingerprint.
@@ -83,26 +200,451 @@ pub async fn total_queries(receiver: Receiver<QueryLogTotal>) -> QueryLogTotal {
     analyzer.total_queries.clone()
 }
 
+/// Merges the per-node totals of `total --breakdown-by` by bucket start.
+///
+/// Returns one [`TotalBucket`] per bucket seen on any node, oldest first.
+pub async fn total_queries_by_bucket(
+    mut receiver: Receiver<QueryLogTotalBucket>,
+) -> Vec<TotalBucket> {
+    let mut buckets: BTreeMap<OffsetDateTime, Analyzer> = BTreeMap::new();
+    while let Some(row) = receiver.recv().await {
+        let row = TotalBucket::from(row);
+        buckets
+            .entry(row.bucket)
+            .or_insert_with(Analyzer::new)
+            .merge_query_total(row.total);
+    }
+
+    buckets
+        .into_iter()
+        .map(|(bucket, analyzer)| TotalBucket {
+            bucket,
+            total: analyzer.total_queries,
+        })
+        .collect()
+}
+
+/// Compares the totals of every node with the median node.
+///
+/// `totals[i]` belongs to `nodes[i]`. A node is flagged as an outlier when its query
+/// count or total impact is more than `factor` times above or below the median.
+/// The result keeps the order of `nodes`.
+pub fn compare_nodes(
+    nodes: Vec<String>,
+    totals: Vec<QueryLogTotal>,
+    factor: f64,
+) -> Vec<NodeTotal> {
+    let queries_median = median(totals.iter().map(|total| total.queries_count));
+    let impact_median = median(totals.iter().map(|total| total.total_impact));
+    let ratio = |value: u64, median: f64| (median > 0.0).then(|| value as f64 / median);
+    let deviates = |value: u64, median: f64| match ratio(value, median) {
+        Some(ratio) => ratio > factor || ratio < 1.0 / factor,
+        None => value > 0,
+    };
+
+    nodes
+        .into_iter()
+        .zip(totals)
+        .map(|(node, total)| NodeTotal {
+            node,
+            queries_count: total.queries_count,
+            total_impact: total.total_impact,
+            queries_ratio: ratio(total.queries_count, queries_median),
+            impact_ratio: ratio(total.total_impact, impact_median),
+            outlier: deviates(total.queries_count, queries_median)
+                || deviates(total.total_impact, impact_median),
+        })
+        .collect()
+}
+
+fn median(values: impl Iterator<Item = u64>) -> f64 {
+    let mut values: Vec<u64> = values.collect();
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_unstable();
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] as f64 + values[mid] as f64) / 2.0
+    } else {
+        values[mid] as f64
+    }
+}
+
 /// Aggregates ClickHouse error logs from a stream and returns the top entries.
 ///
 /// This function receives a stream of [`Error`] records via a channel and
 /// groups them by error code. It returns the top `limit` error types sorted
-/// by their frequency (and then by code).
+/// by `sort_by`, ties broken by code.
 ///
 /// # Arguments
 ///
 /// - `receiver`: An asynchronous receiver stream of [`Error`] entries.
 /// - `limit`: The number of top errors to return.
+/// - `sort_by`: The order of the errors, see [`ErrorsSortBy`].
+/// - `min_count`: Drops codes seen fewer times than this, summed across all nodes.
+///   Applied after merging, a per-node `HAVING` would drop codes spread over nodes.
+/// - `new_since`: Drops codes first seen before this time on any node, or with an
+///   unknown first-seen time. Also applied after merging, for the same reason.
 ///
 /// # Returns
 ///
-/// A `Vec<Error>` containing the top `limit` errors.
-pub async fn top_errors(receiver: Receiver<Error>, limit: usize) -> Vec<Error> {
+/// A `Vec<Error>` containing the top `limit` errors and, with `min_count`,
+/// the totals of the codes it dropped.
+pub async fn top_errors(
+    receiver: Receiver<Error>,
+    limit: usize,
+    sort_by: ErrorsSortBy,
+    min_count: Option<usize>,
+    new_since: Option<OffsetDateTime>,
+) -> (Vec<Error>, Option<BelowMinCount>) {
     let mut analyzer = Analyzer::new();
 
     analyzer.collect_errors(receiver).await;
 
-    analyzer.top_errors(limit)
+    analyzer.top_errors(limit, sort_by, min_count, new_since)
+}
+
+/// State of `errors --watch --diff` kept between polls.
+#[derive(Debug, Default)]
+pub struct ErrorWatch {
+    /// Cumulative count and delta of every code of the previous poll, `None`
+    /// before the first one.
+    previous: Option<FxHashMap<i32, (u64, u64)>>,
+}
+
+impl ErrorWatch {
+    /// Records a poll of merged `errors` and returns the top `limit` codes by
+    /// occurrences since the previous poll, codes without new occurrences left out.
+    ///
+    /// Returns `None` for the first poll, which only records the baseline.
+    pub fn diff(&mut self, errors: Vec<Error>, limit: usize) -> Option<Vec<ErrorDelta>> {
+        let previous = self.previous.take();
+        let mut current = FxHashMap::default();
+        let mut deltas = Vec::new();
+        for error in errors {
+            let (delta, new, spike) = match previous.as_ref().map(|p| p.get(&error.code)) {
+                None => (0, false, false),
+                Some(None) => (error.count, true, false),
+                Some(Some(&(count, previous_delta))) => {
+                    // Счётчики system.errors обнуляются при перезапуске узла
+                    let delta = error.count.checked_sub(count).unwrap_or(error.count);
+                    (delta, false, delta > previous_delta.saturating_mul(2))
+                }
+            };
+            current.insert(error.code, (error.count, delta));
+            if delta > 0 {
+                deltas.push(ErrorDelta {
+                    code: error.code,
+                    name: error.name,
+                    delta,
+                    count: error.count,
+                    new,
+                    spike,
+                });
+            }
+        }
+        self.previous = Some(current);
+
+        previous?;
+        deltas.sort_by(|a, b| b.delta.cmp(&a.delta).then(a.code.cmp(&b.code)));
+        deltas.truncate(limit);
+        Some(deltas)
+    }
+}
+
+/// Compares ClickHouse settings reported by several nodes.
+///
+/// This function receives a stream of [`Setting`] rows from every node and groups
+/// them by setting name. A setting is considered drifted if its value differs
+/// between nodes or it is missing on some of them.
+///
+/// # Arguments
+///
+/// - `receiver`: An asynchronous receiver stream of [`Setting`] entries.
+/// - `changed_only`: Keep only drifted settings, hiding the uniform ones.
+///
+/// # Returns
+///
+/// A [`SettingsDiff`] with the list of hosts and per-setting values, sorted by name.
+pub async fn settings_diff(receiver: Receiver<Setting>, changed_only: bool) -> SettingsDiff {
+    let mut analyzer = Analyzer::new();
+
+    analyzer.collect_settings(receiver).await;
+
+    analyzer.settings_diff(changed_only)
+}
+
+/// Collects the merges of every node and returns the top `limit` by `sort_by`, descending.
+///
+/// Merges are not aggregated: each one runs on a single node.
+pub async fn top_merges(
+    mut receiver: Receiver<Merge>,
+    limit: usize,
+    sort_by: MergesSortBy,
+) -> Vec<Merge> {
+    let mut merges = Vec::new();
+    while let Some(merge) = receiver.recv().await {
+        merges.push(merge);
+    }
+
+    let key = |merge: &Merge| match sort_by {
+        MergesSortBy::Memory => merge.memory_usage as f64,
+        MergesSortBy::Size => merge.total_size_bytes_compressed as f64,
+        MergesSortBy::Progress => merge.progress,
+        MergesSortBy::Elapsed => merge.elapsed,
+    };
+    merges.sort_by(|a, b| key(b).total_cmp(&key(a)));
+    merges.truncate(limit);
+
+    merges
+}
+
+/// Collects the unfinished mutations of every node and returns the top `limit`
+/// by `sort_by`, descending.
+///
+/// Mutations are not aggregated: every replica reports its own progress.
+pub async fn top_mutations(
+    mut receiver: Receiver<Mutation>,
+    limit: usize,
+    sort_by: MutationsSortBy,
+) -> Vec<Mutation> {
+    let mut mutations = Vec::new();
+    while let Some(mutation) = receiver.recv().await {
+        mutations.push(mutation);
+    }
+
+    match sort_by {
+        // Самые старые мутации первыми
+        MutationsSortBy::Age => mutations.sort_by_key(|m| m.create_time),
+        MutationsSortBy::Parts => mutations.sort_by_key(|m| std::cmp::Reverse(m.parts_to_do)),
+    }
+    mutations.truncate(limit);
+
+    mutations
+}
+
+/// Collects the replicas of every node, marks those delayed more than `max_lag`
+/// and sorts them by delay, descending.
+pub async fn replica_lags(mut receiver: Receiver<Replica>, max_lag: Duration) -> Vec<ReplicaLag> {
+    let mut replicas = Vec::new();
+    while let Some(replica) = receiver.recv().await {
+        let lagging = replica.absolute_delay > max_lag.as_secs();
+        replicas.push(ReplicaLag { replica, lagging });
+    }
+
+    replicas.sort_by(|a, b| {
+        let (a, b) = (&a.replica, &b.replica);
+        b.absolute_delay
+            .cmp(&a.absolute_delay)
+            .then_with(|| b.queue_size.cmp(&a.queue_size))
+            .then_with(|| (&a.database, &a.table, &a.host).cmp(&(&b.database, &b.table, &b.host)))
+    });
+
+    replicas
+}
+
+/// Aggregates replicas sorted by [`replica_lags`] into one row per table,
+/// keeping the order of each table's most delayed replica.
+pub fn replicated_tables(replicas: &[ReplicaLag]) -> Vec<ReplicatedTable> {
+    let mut tables: Vec<ReplicatedTable> = Vec::new();
+    let mut index: FxHashMap<(&str, &str), usize> = FxHashMap::default();
+    for ReplicaLag { replica, lagging } in replicas {
+        let key = (replica.database.as_str(), replica.table.as_str());
+        let i = *index.entry(key).or_insert_with(|| {
+            tables.push(ReplicatedTable {
+                database: replica.database.clone(),
+                table: replica.table.clone(),
+                replicas: 0,
+                readonly_replicas: 0,
+                max_delay: 0,
+                queue_size: 0,
+                inserts_in_queue: 0,
+                lagging_replicas: 0,
+            });
+            tables.len() - 1
+        });
+
+        let table = &mut tables[i];
+        table.replicas += 1;
+        table.readonly_replicas += u64::from(replica.is_readonly);
+        table.max_delay = table.max_delay.max(replica.absolute_delay);
+        table.queue_size += replica.queue_size;
+        table.inserts_in_queue += replica.inserts_in_queue;
+        table.lagging_replicas += u64::from(*lagging);
+    }
+
+    tables
+}
+
+/// Sums the storage of every table across nodes and returns the top `limit` tables
+/// by compressed size, descending.
+pub async fn top_parts(mut receiver: Receiver<Part>, limit: usize) -> Vec<Part> {
+    let mut tables: FxHashMap<(String, String), Part> = FxHashMap::default();
+    while let Some(part) = receiver.recv().await {
+        tables
+            .entry((part.database.clone(), part.table.clone()))
+            .and_modify(|existing| {
+                existing.parts += part.parts;
+                existing.rows += part.rows;
+                existing.bytes_compressed += part.bytes_compressed;
+                existing.bytes_uncompressed += part.bytes_uncompressed;
+            })
+            .or_insert(part);
+    }
+
+    let mut parts: Vec<_> = tables.into_values().collect();
+    // Степень сжатия пересчитывается по суммам, а не усредняется по узлам
+    parts.iter_mut().for_each(Part::update_compression_ratio);
+    parts.sort_by(|a, b| {
+        b.bytes_compressed
+            .cmp(&a.bytes_compressed)
+            .then_with(|| (&a.database, &a.table).cmp(&(&b.database, &b.table)))
+    });
+    parts.truncate(limit);
+
+    parts
+}
+
+/// Sorts already aggregated queries by the [`QueriesSortBy`] metric, descending,
+/// and returns the top `limit` of them.
+///
+/// Used to re-rank a collected set without streaming it again.
+pub fn rank_queries<'a>(
+    queries: impl IntoIterator<Item = &'a QueryLog>,
+    limit: usize,
+    sort_by: QueriesSortBy,
+) -> Vec<QueryLog> {
+    let mut top_queries: Vec<_> = queries.into_iter().cloned().collect();
+
+    top_queries.sort_by_key(|q| std::cmp::Reverse(sort_value(q, &sort_by)));
+    top_queries.truncate(limit);
+
+    top_queries
+}
+
+/// Ranks users by the `sort_by` impact of the queries they ran.
+///
+/// Pass every collected fingerprint, not only the top ones. The impact of a
+/// fingerprint is split evenly between its users (see [`UserImpact`]).
+pub fn rank_users<'a>(
+    queries: impl IntoIterator<Item = &'a QueryLog>,
+    limit: usize,
+    sort_by: QueriesSortBy,
+) -> Vec<UserImpact> {
+    split_impact(queries, limit, &sort_by, |query| &query.users)
+        .into_iter()
+        .map(|(user, fingerprints, impact)| UserImpact {
+            user,
+            fingerprints,
+            impact,
+        })
+        .collect()
+}
+
+/// Ranks tables by the `sort_by` impact of the queries that read them.
+///
+/// Pass every collected fingerprint, not only the top ones. The impact of a
+/// fingerprint is split evenly between its tables (see [`TableImpact`]).
+pub fn rank_tables<'a>(
+    queries: impl IntoIterator<Item = &'a QueryLog>,
+    limit: usize,
+    sort_by: QueriesSortBy,
+) -> Vec<TableImpact> {
+    split_impact(queries, limit, &sort_by, |query| &query.tables)
+        .into_iter()
+        .map(|(table, fingerprints, impact)| TableImpact {
+            table,
+            fingerprints,
+            impact,
+        })
+        .collect()
+}
+
+/// Ranks databases by the `sort_by` impact of the queries that read them.
+///
+/// Pass every collected fingerprint, not only the top ones. The impact of a
+/// fingerprint is split evenly between its databases (see [`DatabaseImpact`]).
+pub fn rank_databases<'a>(
+    queries: impl IntoIterator<Item = &'a QueryLog>,
+    limit: usize,
+    sort_by: QueriesSortBy,
+) -> Vec<DatabaseImpact> {
+    split_impact(queries, limit, &sort_by, |query| &query.databases)
+        .into_iter()
+        .map(|(database, fingerprints, impact)| DatabaseImpact {
+            database,
+            fingerprints,
+            impact,
+        })
+        .collect()
+}
+
+// Делит вес каждого отпечатка поровну между его ключами (пользователями, таблицами)
+// и возвращает top `limit` ключей как (ключ, число отпечатков, вес)
+fn split_impact<'a>(
+    queries: impl IntoIterator<Item = &'a QueryLog>,
+    limit: usize,
+    sort_by: &QueriesSortBy,
+    keys: impl Fn(&'a QueryLog) -> &'a [String],
+) -> Vec<(String, u64, u64)> {
+    let mut totals: FxHashMap<&str, (u64, u64)> = FxHashMap::default();
+    for query in queries {
+        let keys = keys(query);
+        if keys.is_empty() {
+            continue;
+        }
+        let share = sort_value(query, sort_by) / keys.len() as u64;
+        for key in keys {
+            let (fingerprints, impact) = totals.entry(key).or_default();
+            *fingerprints += 1;
+            *impact += share;
+        }
+    }
+
+    let mut top: Vec<_> = totals
+        .into_iter()
+        .map(|(key, (fingerprints, impact))| (key.to_string(), fingerprints, impact))
+        .collect();
+    top.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+    top.truncate(limit);
+
+    top
+}
+
+/// Returns the value of the [`QueriesSortBy`] metric of `query`.
+pub fn sort_value(query: &QueryLog, sort_by: &QueriesSortBy) -> u64 {
+    match sort_by {
+        QueriesSortBy::TotalImpact => query.total_impact,
+        QueriesSortBy::IOImpact => query.io_impact,
+        QueriesSortBy::CPUImpact => query.cpu_impact,
+        QueriesSortBy::MemoryImpact => query.memory_impact,
+        QueriesSortBy::TimeImpact => query.time_impact,
+        QueriesSortBy::NetworkImpact => query.network_impact,
+    }
+}
+
+/// Detects `ProfileEvents` metric categories that are zero across all entries.
+///
+/// A missing `ProfileEvents` key reads as `0`, so a category that never reports
+/// a value is likely unavailable on the server rather than truly unused.
+///
+/// # Returns
+///
+/// Names of the suspicious categories, or nothing if `entries` is empty.
+pub fn unavailable_profile_events<T: ProfileEventsMetrics>(entries: &[T]) -> Vec<&'static str> {
+    if entries.is_empty() {
+        return Vec::new();
+    }
+
+    let mut unavailable = Vec::new();
+    if entries.iter().all(|e| e.cpu_metric() == 0) {
+        unavailable.push("CPU");
+    }
+    if entries.iter().all(|e| e.network_metric() == 0) {
+        unavailable.push("network");
+    }
+    unavailable
 }
 
 impl Analyzer {
@@ -110,9 +652,11 @@ impl Analyzer {
     fn new() -> Self {
         Self {
             total_queries: QueryLogTotal::default(),
-            queries: HashMap::new(),
+            queries: FxHashMap::default(),
             query_extended: None,
-            errors: HashMap::new(),
+            errors: FxHashMap::default(),
+            hosts: BTreeSet::new(),
+            settings: BTreeMap::new(),
         }
     }
 
@@ -125,20 +669,36 @@ impl Analyzer {
         self.total_queries.time_impact += log.time_impact;
         self.total_queries.network_impact += log.network_impact;
         self.total_queries.total_impact += log.total_impact;
+        self.total_queries.total_cpu_time_us += log.total_cpu_time_us;
+        self.total_queries.total_network_bytes += log.total_network_bytes;
     }
 
     fn merge_query(&mut self, log: QueryLog) {
         self.queries
             .entry(log.normalized_query_hash)
             .and_modify(|existing| {
-                existing.io_impact += log.io_impact;
-                existing.cpu_impact += log.cpu_impact;
-                existing.memory_impact += log.memory_impact;
-                existing.time_impact += log.time_impact;
-                existing.network_impact += log.network_impact;
-                existing.total_impact += log.total_impact;
+                merge_string_vecs(&mut existing.users, &log.users);
+                merge_string_vecs(&mut existing.databases, &log.databases);
+                merge_string_vecs(&mut existing.tables, &log.tables);
+                existing.min_event_time = existing.min_event_time.min(log.min_event_time);
+                existing.max_event_time = existing.max_event_time.max(log.max_event_time);
+                existing.total_result_rows += log.total_result_rows;
+                existing.total_result_bytes += log.total_result_bytes;
+                // Только суммы: показатели выводятся из них в top_queries
+                existing.queries_count += log.queries_count;
+                existing.total_query_duration_ms += log.total_query_duration_ms;
+                existing.total_read_rows += log.total_read_rows;
+                existing.total_read_bytes += log.total_read_bytes;
+                existing.total_memory_usage += log.total_memory_usage;
+                existing.total_user_time_us += log.total_user_time_us;
+                existing.total_system_time_us += log.total_system_time_us;
+                existing.total_network_receive_bytes += log.total_network_receive_bytes;
+                existing.total_network_send_bytes += log.total_network_send_bytes;
+                if existing.exception_code == 0 {
+                    existing.exception_code = log.exception_code;
+                }
             })
-            .or_insert(log);
+            .or_insert_with(|| normalize_query(log));
     }
 
     fn merge_query_extended(&mut self, log: QueryLogExtended) {
@@ -148,6 +708,8 @@ impl Analyzer {
                 existing.total_query_duration_ms += log.total_query_duration_ms;
                 existing.total_read_rows += log.total_read_rows;
                 existing.total_read_bytes += log.total_read_bytes;
+                existing.total_result_rows += log.total_result_rows;
+                existing.total_result_bytes += log.total_result_bytes;
                 existing.total_memory_usage += log.total_memory_usage;
                 existing.total_user_time_us += log.total_user_time_us;
                 existing.total_system_time_us += log.total_system_time_us;
@@ -161,18 +723,44 @@ impl Analyzer {
                 merge_string_vecs(&mut existing.users, &log.users);
                 merge_string_vecs(&mut existing.databases, &log.databases);
                 merge_string_vecs(&mut existing.tables, &log.tables);
+
+                // Representative query: the slowest execution across nodes
+                if log.sample_query_duration_ms > existing.sample_query_duration_ms {
+                    existing.sample_query_id = log.sample_query_id;
+                    existing.sample_query_duration_ms = log.sample_query_duration_ms;
+                }
+                if existing.exception_code == 0 {
+                    existing.exception_code = log.exception_code;
+                }
             }
             None => {
+                let mut log = log;
+                normalize_string_vec(&mut log.users);
+                normalize_string_vec(&mut log.databases);
+                normalize_string_vec(&mut log.tables);
                 self.query_extended = Some(log);
             }
         }
     }
 
+    fn merge_setting(&mut self, setting: Setting) {
+        self.hosts.insert(setting.host.clone());
+        self.settings
+            .entry(setting.name)
+            .or_default()
+            .insert(setting.host, setting.value);
+    }
+
     fn merge_error(&mut self, err: Error) {
         self.errors
             .entry(err.code)
             .and_modify(|existing| {
                 existing.count += err.count;
+                existing.first_error_time = match (existing.first_error_time, err.first_error_time)
+                {
+                    (Some(existing), Some(first)) => Some(existing.min(first)),
+                    (existing, first) => existing.or(first),
+                };
                 if err.last_error_time > existing.last_error_time {
                     existing.last_error_time = err.last_error_time;
                 }
@@ -204,36 +792,386 @@ impl Analyzer {
         }
     }
 
-    fn top_queries(&self, limit: usize, sort_by: QueriesSortBy) -> Vec<QueryLog> {
-        let mut top_queries: Vec<_> = self.queries.values().cloned().collect();
-
-        top_queries.sort_by_key(|q| {
-            std::cmp::Reverse(match sort_by {
-                QueriesSortBy::TotalImpact => q.total_impact,
-                QueriesSortBy::IOImpact => q.io_impact,
-                QueriesSortBy::CPUImpact => q.cpu_impact,
-                QueriesSortBy::MemoryImpact => q.memory_impact,
-                QueriesSortBy::TimeImpact => q.time_impact,
-                QueriesSortBy::NetworkImpact => q.network_impact,
+    async fn collect_settings(&mut self, mut rx: Receiver<Setting>) {
+        while let Some(setting) = rx.recv().await {
+            self.merge_setting(setting);
+        }
+    }
+
+    fn settings_diff(&self, changed_only: bool) -> SettingsDiff {
+        let settings = self
+            .settings
+            .iter()
+            .filter(|(_, values)| {
+                let missing = values.len() != self.hosts.len();
+                let mut distinct = values.values();
+                let first = distinct.next();
+                let differs = distinct.any(|v| Some(v) != first);
+                !changed_only || missing || differs
             })
-        });
-        top_queries.truncate(limit);
+            .map(|(name, values)| SettingValues {
+                name: name.clone(),
+                values: values.clone(),
+            })
+            .collect();
 
-        top_queries
+        SettingsDiff {
+            hosts: self.hosts.iter().cloned().collect(),
+            settings,
+        }
     }
 
-    fn top_errors(&self, limit: usize) -> Vec<Error> {
-        let mut top_errors: Vec<Error> = self.errors.values().cloned().collect();
+    fn top_queries(&self, limit: usize, sort_by: QueriesSortBy) -> Vec<QueryLog> {
+        rank_queries(self.queries.values(), limit, sort_by)
+    }
+
+    fn top_errors(
+        &self,
+        limit: usize,
+        sort_by: ErrorsSortBy,
+        min_count: Option<usize>,
+        new_since: Option<OffsetDateTime>,
+    ) -> (Vec<Error>, Option<BelowMinCount>) {
+        let mut below = min_count.map(|min_count| BelowMinCount {
+            min_count: min_count as u64,
+            ..Default::default()
+        });
+        let mut top_errors = Vec::new();
+        let is_new = |err: &Error| match new_since {
+            Some(since) => err.first_error_time.is_some_and(|first| first >= since),
+            None => true,
+        };
+        for err in self.errors.values().filter(|err| is_new(err)) {
+            match below.as_mut() {
+                Some(below) if err.count < below.min_count => {
+                    below.codes += 1;
+                    below.occurrences += err.count;
+                }
+                _ => top_errors.push(err.clone()),
+            }
+        }
+        // Частота считается по окну, уже объединённому по всем узлам
+        top_errors
+            .iter_mut()
+            .for_each(Error::update_errors_per_hour);
 
-        top_errors.sort_by_key(|e| (std::cmp::Reverse(e.count), e.code));
+        match sort_by {
+            ErrorsSortBy::Count => top_errors.sort_by_key(|e| (std::cmp::Reverse(e.count), e.code)),
+            ErrorsSortBy::LastSeen => {
+                top_errors.sort_by_key(|e| (std::cmp::Reverse(e.last_error_time), e.code))
+            }
+            ErrorsSortBy::Code => top_errors.sort_by_key(|e| e.code),
+        }
         top_errors.truncate(limit);
 
-        top_errors
+        (top_errors, below)
     }
 }
 
 fn merge_string_vecs(target: &mut Vec<String>, source: &[String]) {
     target.extend_from_slice(source);
-    target.sort_unstable();
-    target.dedup();
+    normalize_string_vec(target);
+}
+
+// Сортирует и убирает дубликаты, чтобы порядок не зависел от числа узлов
+fn normalize_string_vec(values: &mut Vec<String>) {
+    values.sort_unstable();
+    values.dedup();
+}
+
+/// Reduces a query text to its shape, so texts of one fingerprint only differ when
+/// more than literals, whitespace or case changed: string and number literals
+/// become `?`, whitespace runs a single space and letters lowercase.
+fn query_shape(query: &str) -> String {
+    let mut shape = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // Литерал строки до закрывающей кавычки, с учётом экранирования
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '\'' => break,
+                        _ => {}
+                    }
+                }
+                shape.push('?');
+            }
+            c if c.is_ascii_digit()
+                && !shape.ends_with(|p: char| p.is_alphanumeric() || p == '_') =>
+            {
+                while chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || *c == '.')
+                    .is_some()
+                {}
+                shape.push('?');
+            }
+            c if c.is_whitespace() => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                if !shape.is_empty() {
+                    shape.push(' ');
+                }
+            }
+            c => shape.extend(c.to_lowercase()),
+        }
+    }
+    shape.truncate(shape.trim_end().len());
+    shape
+}
+
+fn normalize_query(mut log: QueryLog) -> QueryLog {
+    normalize_string_vec(&mut log.users);
+    normalize_string_vec(&mut log.databases);
+    normalize_string_vec(&mut log.tables);
+    log
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::tests::query_log;
+    use tokio::sync::mpsc;
+
+    // Каждый узел отдаёт свою часть одного отпечатка через свой канал
+    async fn merge_nodes(nodes: Vec<QueryLog>, impact_expr: Option<ImpactExpr>) -> Vec<QueryLog> {
+        let receivers = nodes
+            .into_iter()
+            .map(|log| {
+                let (tx, rx) = mpsc::channel(1);
+                tx.try_send(log).unwrap();
+                rx
+            })
+            .collect();
+        let (queries, _) = top_queries(
+            receivers,
+            10,
+            QueriesSortBy::TotalImpact,
+            false,
+            ImpactWeights::default(),
+            impact_expr,
+        )
+        .await;
+        queries
+    }
+
+    #[tokio::test]
+    async fn merged_impacts_are_derived_from_merged_sums() {
+        let mut first = query_log(1);
+        first.queries_count = 1;
+        first.total_read_rows = 10;
+        first.total_read_bytes = 100;
+        first.total_query_duration_ms = 1;
+        let mut second = first.clone();
+        second.total_query_duration_ms = 3;
+        // Показатели, пришедшие с узлов, не должны попасть в итог
+        for log in [&mut first, &mut second] {
+            log.io_impact = 999_999;
+            log.total_impact = 999_999;
+        }
+
+        let queries = merge_nodes(vec![first, second], None).await;
+        assert_eq!(queries.len(), 1);
+        let query = &queries[0];
+        assert_eq!(query.queries_count, 2);
+        assert_eq!(query.total_read_bytes, 200);
+        assert_eq!(query.io_impact, 20 * 100 + 200);
+        assert_eq!(query.time_impact, 4 * 1_000_000);
+        assert_eq!(query.total_impact, 2200 + 4_000_000);
+    }
+
+    #[tokio::test]
+    async fn merged_ratio_impact_is_ratio_of_sums() {
+        let mut first = query_log(1);
+        first.total_read_bytes = 100;
+        first.total_query_duration_ms = 1;
+        let mut second = query_log(1);
+        second.total_read_bytes = 100;
+        second.total_query_duration_ms = 99;
+
+        let expr = "read_bytes / query_duration_ms".parse().unwrap();
+        let queries = merge_nodes(vec![first, second], Some(expr)).await;
+        // 200 / 100, а не 100 / 1 + 100 / 99
+        assert_eq!(queries[0].total_impact, 2);
+    }
+
+    #[tokio::test]
+    async fn merged_event_times_are_min_and_max_across_nodes() {
+        let at = |secs| OffsetDateTime::from_unix_timestamp(secs).unwrap();
+        let mut first = query_log(1);
+        first.min_event_time = at(200);
+        first.max_event_time = at(500);
+        let mut second = query_log(1);
+        second.min_event_time = at(100);
+        second.max_event_time = at(400);
+
+        // Порядок узлов не влияет на результат
+        for nodes in [
+            vec![first.clone(), second.clone()],
+            vec![second.clone(), first.clone()],
+        ] {
+            let queries = merge_nodes(nodes, None).await;
+            assert_eq!(queries[0].min_event_time, at(100));
+            assert_eq!(queries[0].max_event_time, at(500));
+        }
+    }
+
+    #[tokio::test]
+    async fn single_and_parallel_collectors_agree() {
+        let log = |hash, query: &str, rows| {
+            let mut log = query_log(hash);
+            log.query = query.to_string();
+            log.queries_count = 1;
+            log.total_read_rows = rows;
+            log
+        };
+        let nodes = || {
+            vec![
+                vec![log(1, "SELECT 1", 10), log(2, "SELECT a", 5)],
+                vec![log(1, "SELECT b FROM t", 20)],
+            ]
+            .into_iter()
+            .map(|logs| {
+                let (tx, rx) = mpsc::channel(logs.len());
+                logs.into_iter().for_each(|log| tx.try_send(log).unwrap());
+                rx
+            })
+            .collect()
+        };
+        let summary = |(analyzer, shapes): (Analyzer, FxHashMap<u64, BTreeSet<String>>)| {
+            let queries: BTreeMap<_, _> = analyzer
+                .queries
+                .values()
+                .map(|q| {
+                    (
+                        q.normalized_query_hash,
+                        (q.queries_count, q.total_read_rows),
+                    )
+                })
+                .collect();
+            (queries, shapes.into_iter().collect::<BTreeMap<_, _>>())
+        };
+
+        let single = summary(collect_logs_single(nodes(), true).await);
+        let parallel = summary(collect_logs_parallel(nodes(), true).await);
+        assert_eq!(single, parallel);
+        assert_eq!(single.0[&1], (2, 30));
+        assert_eq!(single.1[&1].len(), 2);
+    }
+
+    #[tokio::test]
+    async fn arrays_are_ordered_the_same_on_one_and_two_nodes() {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        let mut single = query_log(1);
+        single.users = strings(&["bob", "alice", "bob"]);
+        single.databases = strings(&["logs", "default"]);
+        single.tables = strings(&["visits", "hits"]);
+        let mut first = query_log(1);
+        first.users = strings(&["bob"]);
+        first.databases = strings(&["logs"]);
+        first.tables = strings(&["visits", "hits"]);
+        let mut second = query_log(1);
+        second.users = strings(&["bob", "alice"]);
+        second.databases = strings(&["logs", "default"]);
+        second.tables = strings(&["hits"]);
+
+        let one = merge_nodes(vec![single], None).await.remove(0);
+        let two = merge_nodes(vec![first, second], None).await.remove(0);
+        for query in [&one, &two] {
+            assert_eq!(query.users, ["alice", "bob"]);
+            assert_eq!(query.databases, ["default", "logs"]);
+            assert_eq!(query.tables, ["hits", "visits"]);
+        }
+    }
+
+    async fn merge_errors(
+        nodes: Vec<Error>,
+        min_count: Option<usize>,
+        new_since: Option<OffsetDateTime>,
+    ) -> (Vec<i32>, Option<BelowMinCount>) {
+        let (tx, rx) = mpsc::channel(nodes.len());
+        for err in nodes {
+            tx.try_send(err).unwrap();
+        }
+        drop(tx);
+        let (errors, below) = top_errors(rx, 10, ErrorsSortBy::Code, min_count, new_since).await;
+        (errors.iter().map(|e| e.code).collect(), below)
+    }
+
+    #[tokio::test]
+    async fn min_count_and_new_since_apply_to_merged_codes() {
+        let deploy = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let error = |code, count, first_error_time| Error {
+            code,
+            name: String::new(),
+            count,
+            first_error_time,
+            last_error_time: deploy + time::Duration::days(1),
+            error_message: String::new(),
+            errors_per_hour: None,
+        };
+        let hour = time::Duration::hours(1);
+        let nodes = || {
+            vec![
+                // Код 1 новый на обоих узлах и набирает порог только в сумме
+                error(1, 2, Some(deploy + hour)),
+                error(1, 2, Some(deploy + hour)),
+                // Код 2 на одном из узлов появился до выкладки
+                error(2, 10, Some(deploy + hour)),
+                error(2, 10, Some(deploy - hour)),
+                // Код 3 без first_error_time и редкий
+                error(3, 1, None),
+            ]
+        };
+
+        let (codes, below) = merge_errors(nodes(), None, None).await;
+        assert_eq!(codes, [1, 2, 3]);
+        assert!(below.is_none());
+
+        let (codes, below) = merge_errors(nodes(), Some(3), None).await;
+        assert_eq!(codes, [1, 2]);
+        let below = below.unwrap();
+        assert_eq!((below.codes, below.occurrences), (1, 1));
+
+        let (codes, _) = merge_errors(nodes(), None, Some(deploy)).await;
+        assert_eq!(codes, [1]);
+
+        // Отброшенные по времени коды не учитываются в сводке порога
+        let (codes, below) = merge_errors(nodes(), Some(5), Some(deploy)).await;
+        assert!(codes.is_empty());
+        let below = below.unwrap();
+        assert_eq!((below.codes, below.occurrences), (1, 4));
+    }
+
+    #[tokio::test]
+    async fn zero_weight_totals_keep_profile_events_available() {
+        // `--weight-cpu 0 --weight-network 0`: импакты нулевые, исходные суммы нет
+        let node = || QueryLogTotal {
+            queries_count: 1,
+            io_impact: 10,
+            total_impact: 10,
+            total_cpu_time_us: 5,
+            total_network_bytes: 7,
+            ..QueryLogTotal::default()
+        };
+        let (tx, rx) = mpsc::channel(2);
+        tx.try_send(node()).unwrap();
+        tx.try_send(node()).unwrap();
+        drop(tx);
+        let total = total_queries(rx).await;
+        assert_eq!(
+            (total.total_cpu_time_us, total.total_network_bytes),
+            (10, 14)
+        );
+        assert!(unavailable_profile_events(&[total]).is_empty());
+
+        let missing = QueryLogTotal {
+            total_cpu_time_us: 0,
+            total_network_bytes: 0,
+            ..node()
+        };
+        assert_eq!(unavailable_profile_events(&[missing]), ["CPU", "network"]);
+    }
 }
@@ -0,0 +1,153 @@
+//! On-disk cache of the query groups collected by `queries`.
+//!
+//! `queries --cache <path>` stores every collected [`QueryLog`] together with the
+//! filter and the time of the run. `queries --cache <path> --use-cache` reads them back
+//! and ranks them locally (e.g. with another `--sort-by` or `--limit`) without
+//! querying ClickHouse. A cache made for another filter, by another version of the
+//! format, or an open-ended window older than [`MAX_AGE`] is refused.
+use crate::model::{ImpactWeights, QueriesFilter, QueryLog, QueryStatus};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// Bumped on every incompatible change of the cache layout.
+const VERSION: u32 = 12;
+
+/// How long a cache of a window ending "now" (`--last`, or `--from` without `--to`)
+/// is reused, later it no longer reflects the current state of the cluster.
+pub const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct Cache {
+    version: u32,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    created_at: OffsetDateTime,
+    filter: CacheKey,
+    logs: Vec<QueryLog>,
+}
+
+/// Filter the cache was collected with, as given on the command line.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct CacheKey {
+    from: Option<i64>,
+    to: Option<i64>,
+    last: Option<Duration>,
+    users: Vec<String>,
+    exclude_users: Vec<String>,
+    status: QueryStatus,
+    exception_codes: Vec<i32>,
+    databases: Vec<String>,
+    tables: Vec<String>,
+    min_query_duration: Option<Duration>,
+    min_read_rows: Option<u64>,
+    min_read_data: Option<u64>,
+    min_distinct_users: Option<usize>,
+    normalize_query: bool,
+    log_sampling_factor: Option<f64>,
+    impact_expr: Option<String>,
+    impact_weights: ImpactWeights,
+}
+
+impl From<&QueriesFilter> for CacheKey {
+    fn from(filter: &QueriesFilter) -> Self {
+        Self {
+            from: filter.from.map(OffsetDateTime::unix_timestamp),
+            to: filter.to.map(OffsetDateTime::unix_timestamp),
+            last: filter.last,
+            users: filter.users.clone(),
+            exclude_users: filter.exclude_users.clone(),
+            status: filter.status,
+            exception_codes: filter.exception_codes.clone(),
+            databases: filter.databases.clone(),
+            tables: filter.tables.clone(),
+            min_query_duration: filter.min_query_duration,
+            min_read_rows: filter.min_read_rows,
+            min_read_data: filter.min_read_data.map(|data| data.as_u64()),
+            min_distinct_users: filter.min_distinct_users,
+            normalize_query: filter.normalize_query,
+            log_sampling_factor: filter.log_sampling_factor,
+            impact_expr: filter
+                .impact_expr
+                .as_ref()
+                .map(|expr| expr.source().to_string()),
+            impact_weights: filter.impact_weights,
+        }
+    }
+}
+
+/// Writes `logs` collected with `filter` (as given, before resolving `--last`) to `path`.
+///
+/// `created_at` is the time the `--last` window was resolved at.
+/// The file is replaced atomically, so a failed write never leaves a truncated cache.
+pub fn write(
+    path: &Path,
+    filter: &QueriesFilter,
+    created_at: OffsetDateTime,
+    logs: &[QueryLog],
+) -> Result<(), String> {
+    let cache = Cache {
+        version: VERSION,
+        created_at,
+        filter: filter.into(),
+        logs: logs.to_vec(),
+    };
+    let serialized =
+        serde_json::to_vec(&cache).map_err(|e| format!("serialize cache error: {e}"))?;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut tmp_file = tempfile::NamedTempFile::new_in(dir)
+        .map_err(|e| format!("create cache {} error: {e}", path.display()))?;
+    tmp_file
+        .write_all(&serialized)
+        .map_err(|e| format!("write cache {} error: {e}", path.display()))?;
+    tmp_file
+        .persist(path)
+        .map_err(|e| format!("write cache {} error: {e}", path.display()))?;
+    Ok(())
+}
+
+/// Reads the logs cached at `path`, checking they were collected with `filter`.
+///
+/// Returns the logs and `filter` resolved as of the time the cache was made, so
+/// `--last` reports the window that was actually scanned.
+pub fn read(path: &Path, filter: &QueriesFilter) -> Result<(Vec<QueryLog>, QueriesFilter), String> {
+    let content =
+        std::fs::read(path).map_err(|e| format!("read cache {} error: {e}", path.display()))?;
+    let cache: Cache = serde_json::from_slice(&content)
+        .map_err(|e| format!("parse cache {} error: {e}", path.display()))?;
+
+    if cache.version != VERSION {
+        return Err(format!(
+            "cache {} has format version {}, expected {VERSION}: rerun without `--use-cache`",
+            path.display(),
+            cache.version
+        ));
+    }
+    if cache.filter != CacheKey::from(filter) {
+        return Err(format!(
+            "cache {} was collected with another filter: rerun without `--use-cache`",
+            path.display()
+        ));
+    }
+    let age = OffsetDateTime::now_utc() - cache.created_at;
+    if filter.to.is_none() && age > MAX_AGE {
+        return Err(format!(
+            "cache {} is {} old, older than {} for a window ending now: rerun without `--use-cache`",
+            path.display(),
+            humantime::format_duration(Duration::from_secs(age.whole_seconds().max(0) as u64)),
+            humantime::format_duration(MAX_AGE),
+        ));
+    }
+
+    // Показатели в кэш не читаются, а выводятся заново из сумм
+    let mut logs = cache.logs;
+    for log in &mut logs {
+        log.compute_impacts(&filter.impact_weights, filter.impact_expr.as_ref());
+    }
+    Ok((logs, filter.clone().resolved_at(cache.created_at)))
+}
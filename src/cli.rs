@@ -6,6 +6,8 @@
 //! - Subcommands:
 //!   - `queries`: Analyze and group normalized ClickHouse queries with filtering.
 //!   - `errors`: Display frequent ClickHouse query errors with filtering.
+//!   - `health`: One-shot overview of top queries, errors and totals.
+//!   - `settings-diff`: Compare ClickHouse settings across cluster nodes.
 //!   - `context`: Manage named connection profiles (contexts).
 //!
 //! This module also includes utility functions to parse human-friendly inputs
@@ -13,8 +15,15 @@
 //!
 //! The structure is designed to separate configuration parsing from execution logic,
 //! making it easier to test and extend.
-use crate::model::{OutputFormat, QueriesSortBy};
-use clap::{ArgGroup, Args, Parser, Subcommand};
+use crate::client::ImpactExpr;
+#[cfg(feature = "schema")]
+use crate::model::SchemaKind;
+use crate::model::{
+    ErrorsSortBy, FingerprintFormat, ImpactWeights, MergesSortBy, MutationsSortBy, OutputFormat,
+    QueriesSortBy, QueryColumn, QueryStatus, TimeBucket, TlsVersion, DEFAULT_TRUNCATE_QUERY_BYTES,
+};
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand};
+use std::io::IsTerminal;
 use std::path::PathBuf;
 use std::str::FromStr;
 use time::format_description::well_known::Rfc3339;
@@ -34,7 +43,7 @@ pub struct CliArgs {
     #[command(subcommand)]
     pub command: Command,
 
-    /// Path to a context configuration TOML file.
+    /// Path to a context configuration TOML file, or `-` to read it from stdin (read-only).
     #[arg(long, global = true)]
     pub config: Option<PathBuf>,
 
@@ -43,9 +52,45 @@ pub struct CliArgs {
     #[arg(long, global = true)]
     pub context: Option<String>,
 
-    /// Output format for results: text (default), json, or yaml.
+    /// Keyring service name used to store profile passwords (default `clickcheck`).
+    /// Overrides the `keyring_service` key of the config file.
+    #[arg(long, global = true)]
+    pub keyring_service: Option<String>,
+
+    /// Output format for results: text (default), json, yaml, csv, prometheus (`queries`,
+    /// `total` and `errors` only) or, with the `xml` feature, xml.
+    /// Can be specified multiple times, e.g. `--out text --out json`.
     #[clap(long, global = true, default_value = "text")]
-    pub out: OutputFormat,
+    pub out: Vec<OutputFormat>,
+
+    /// Write structured (json, yaml, csv, prometheus, xml) output to this file instead of stdout.
+    /// Text output is always printed to stdout. With several structured formats,
+    /// each one is written to this path with its own extension.
+    #[arg(long, global = true)]
+    pub output_file: Option<PathBuf>,
+
+    /// Show impacts in text output as plain grouped integers (e.g. `1,234,567`)
+    /// instead of abbreviated with k/M/B suffixes.
+    #[arg(long, global = true)]
+    pub raw_impact: bool,
+
+    /// Show query fingerprints in text output as hex (default) or as decimal, the way
+    /// ClickHouse stores `normalized_query_hash`. Structured output always has decimal.
+    #[arg(long, global = true, default_value = "hex")]
+    pub fingerprint_format: FingerprintFormat,
+
+    /// Never pipe long text output through `$PAGER` (default `less -RFX`).
+    #[arg(long, global = true)]
+    pub no_pager: bool,
+
+    /// Suppress warnings and notes on stderr. Results and errors are still printed.
+    #[arg(long, short = 'q', global = true)]
+    pub quiet: bool,
+
+    /// Print elapsed time of client construction, per-node streaming and analysis to stderr.
+    /// Streaming stages also show how many rows waited for the analyzer and for how long.
+    #[arg(long, global = true)]
+    pub timings: bool,
 }
 
 /// Subcommands for different analysis modes.
@@ -63,21 +108,191 @@ pub enum Command {
         #[clap(flatten)]
         filter: QueriesFilterArgs,
 
+        #[clap(flatten)]
+        weights: ImpactWeightArgs,
+
         /// number of output queries
         #[arg(long, default_value_t = 5)]
         limit: usize,
+
+        /// Comma-separated columns of the text table, in display order, e.g.
+        /// fingerprint,query,total-impact,cpu-impact. Defaults to all columns
+        /// but first-seen and last-seen, the time range of the fingerprint.
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<QueryColumn>,
+
+        /// Show the normalizeQuery() form of the query text, with literals replaced by `?`.
+        #[arg(long)]
+        show_normalized: bool,
+
+        /// Compute total-impact with a custom formula instead of the built-in weights,
+        /// e.g. `read_bytes + memory_usage * 10` or `query_duration_ms / queries`.
+        ///
+        /// Supports numbers, `+ - * /`, parentheses and the per-fingerprint sums
+        /// query_duration_ms, read_rows, read_bytes, result_rows, result_bytes,
+        /// memory_usage, user_time_us, system_time_us, network_receive_bytes,
+        /// network_send_bytes, plus the query count `queries`. Negative or non-finite
+        /// results count as 0. The per-resource impact columns are unchanged.
+        #[arg(long, value_name = "EXPR")]
+        impact_expr: Option<ImpactExpr>,
+
+        /// Show impacts per hour of each fingerprint's observed span (first to last
+        /// execution), so fingerprints are comparable across windows of any length.
+        /// Spans under an hour count as one hour. Ranking still uses the totals.
+        #[arg(long)]
+        rate: bool,
+
+        /// Only show fingerprints run by at least N distinct users, e.g. candidates
+        /// for a shared materialized view or cache. Users are counted across all nodes.
+        #[arg(long, value_name = "N")]
+        min_distinct_users: Option<usize>,
+
+        /// Note fingerprints whose query texts differ across nodes beyond literals,
+        /// whitespace and case, which can reveal a normalization collision.
+        #[arg(long)]
+        check_query_texts: bool,
+
+        /// Also rank users by the impact of their queries (same --sort-by, --top rows).
+        ///
+        /// A fingerprint run by several users has its impact split evenly between them.
+        #[arg(long)]
+        top_users: bool,
+
+        /// Also rank tables by the impact of the queries that read them (same --sort-by,
+        /// --top rows).
+        ///
+        /// A fingerprint reading several tables has its impact split evenly between them.
+        #[arg(long)]
+        group_by_table: bool,
+
+        /// Also rank databases by the impact of the queries that read them (same
+        /// --sort-by, --top rows).
+        ///
+        /// A fingerprint reading several databases has its impact split evenly between them.
+        #[arg(long)]
+        group_by_database: bool,
+
+        /// Number of rows in the --top-users, --group-by-table and --group-by-database
+        /// rollups. Defaults to --limit.
+        #[arg(long, value_name = "N")]
+        top: Option<usize>,
+
+        /// Rank the queries of every node separately instead of merging them across
+        /// nodes. Structured formats print an object keyed by node URL.
+        #[arg(
+            long,
+            conflicts_with_all = [
+                "top_users",
+                "group_by_table",
+                "group_by_database",
+                "check_query_texts",
+                "cache",
+            ]
+        )]
+        by_node: bool,
+
+        /// With --by-node, rank the queries of all nodes in one table with a Node
+        /// column instead of a table per node, e.g. to spot a single hot replica.
+        /// Structured formats print rows with a `node` field.
+        #[arg(long, requires = "by_node")]
+        node_column: bool,
+
+        /// Save every collected query group to this file, for later `--use-cache` runs.
+        #[arg(long, value_name = "PATH")]
+        cache: Option<PathBuf>,
+
+        /// Rank the query groups saved in `--cache` instead of querying ClickHouse,
+        /// e.g. with another `--sort-by`, `--limit` or `--top-users`.
+        ///
+        /// The filter must match the one the cache was saved with. A window ending now
+        /// (`--last`, or `--from` without `--to`) is only reused for an hour.
+        #[arg(long, requires = "cache")]
+        use_cache: bool,
+
+        /// Run the analysis N times and print the distribution of its client and server
+        /// (`query_log`) execution times instead of the results, as a repeatable benchmark.
+        ///
+        /// Needs ClickHouse, so it can't be combined with `--use-cache`.
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::value_parser!(u32).range(1..),
+            conflicts_with = "use_cache"
+        )]
+        iterations: Option<u32>,
+
+        /// Browse queries interactively and inspect fingerprints with Enter.
+        #[cfg(feature = "tui")]
+        #[arg(long, conflicts_with_all = ["cache", "by_node", "iterations"])]
+        tui: bool,
     },
 
     /// Analyze total number of queries and aggregated statistics (e.g. read rows/data) in a time range.
     ///
     /// This command shows cumulative metrics over the specified filter window,
     /// helping track overall workload volume.
+    // `--history` reads the saved runs only, so it makes the time window optional
+    #[command(mut_group("from_or_last", |group| group.required(false)))]
+    #[command(mut_arg("from", |arg| arg.required_unless_present_any(["last", "history"])))]
     Total {
         #[clap(flatten)]
         conn: ConnectArgs,
 
         #[clap(flatten)]
         filter: QueriesFilterArgs,
+
+        #[clap(flatten)]
+        weights: ImpactWeightArgs,
+
+        /// Report query count and total impact of every node side by side instead of
+        /// summing them, flagging nodes that deviate from the median node.
+        ///
+        /// Each node logs its own queries, so a strong imbalance usually points to a
+        /// misrouted Distributed table or a hot shard.
+        #[arg(long)]
+        compare_nodes: bool,
+
+        /// Flag a node when its query count or total impact is more than this many
+        /// times above or below the median node.
+        #[arg(
+            long,
+            value_name = "FACTOR",
+            default_value_t = 2.0,
+            requires = "compare_nodes",
+            value_parser = parse_outlier_factor
+        )]
+        outlier_factor: f64,
+
+        /// Append the totals of this run to the history file next to the config file,
+        /// for `--history`. Only the latest 1000 runs are kept.
+        #[arg(long, conflicts_with = "compare_nodes")]
+        record: bool,
+
+        /// Report the totals per hour or day instead of a single total, oldest first.
+        ///
+        /// Hours or days without queries are left out.
+        #[arg(long, value_name = "BUCKET", conflicts_with_all = ["compare_nodes", "record"])]
+        breakdown_by: Option<TimeBucket>,
+
+        /// Print the latest N runs saved with `--record`, without querying ClickHouse.
+        #[arg(
+            long,
+            value_name = "N",
+            num_args = 0..=1,
+            default_missing_value = "10",
+            conflicts_with_all = ["compare_nodes", "record", "breakdown_by", "from", "last"]
+        )]
+        history: Option<usize>,
+
+        /// Run the analysis N times and print the distribution of its client and server
+        /// (`query_log`) execution times instead of the results, as a repeatable benchmark.
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::value_parser!(u32).range(1..),
+            conflicts_with = "history"
+        )]
+        iterations: Option<u32>,
     },
 
     /// Inspect a single query fingerprint with detailed info.
@@ -89,11 +304,37 @@ pub enum Command {
         #[clap(flatten)]
         conn: ConnectArgs,
 
-        #[arg(value_parser = parse_hex)]
+        /// Fingerprint as hex (`0x`-prefixed or with a-f digits) or decimal.
+        #[arg(value_parser = parse_fingerprint)]
         fingerprint: u64,
 
         #[clap(flatten)]
         filter: QueriesFilterArgs,
+
+        /// Show the normalizeQuery() form of the query text, with literals replaced by `?`.
+        #[arg(long)]
+        show_normalized: bool,
+
+        /// Fetch at most this much of the query text (supports units like KB, MiB),
+        /// so huge generated queries don't flood memory and the terminal.
+        /// Truncation is noted in the output.
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = bytesize::ByteSize(DEFAULT_TRUNCATE_QUERY_BYTES),
+            value_parser = parse_byte_size
+        )]
+        truncate_query_bytes: bytesize::ByteSize,
+
+        /// Itemize how every raw metric adds to the total impact: its value, weight,
+        /// weighted value and share, showing why the fingerprint ranks where it does.
+        #[arg(long)]
+        explain_impact: bool,
+
+        /// Run the analysis N times and print the distribution of its client and server
+        /// (`query_log`) execution times instead of the results, as a repeatable benchmark.
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        iterations: Option<u32>,
     },
 
     /// Show top ClickHouse query errors with filtering options.
@@ -107,32 +348,209 @@ pub enum Command {
         /// number of output queries
         #[arg(long, default_value_t = 5)]
         limit: usize,
+
+        /// Sort the errors by this field: most frequent, most recently seen or by code.
+        #[arg(long, default_value = "count")]
+        sort_by: ErrorsSortBy,
+
+        /// Don't select system.errors.last_error_message, leaving messages empty.
+        /// Skipped automatically on nodes where the column is unavailable.
+        #[arg(long)]
+        no_error_message: bool,
+
+        /// Poll again every INTERVAL (e.g. 30s) until interrupted.
+        #[arg(long, value_name = "INTERVAL", value_parser = humantime::parse_duration)]
+        watch: Option<std::time::Duration>,
+
+        /// With --watch, show occurrences since the previous poll instead of the
+        /// cumulative system.errors counts, flagging codes that are new or spiked.
+        #[arg(long, requires = "watch")]
+        diff: bool,
+    },
+
+    /// Show a one-shot overview: top queries, top errors and total query statistics.
+    ///
+    /// A good first command to run against an unfamiliar cluster. The time window
+    /// also limits errors, by their last occurrence.
+    Health {
+        #[clap(flatten)]
+        conn: ConnectArgs,
+
+        #[clap(flatten)]
+        filter: QueriesFilterArgs,
+
+        /// number of entries in the queries and errors sections
+        #[arg(long, default_value_t = 3)]
+        limit: usize,
+
+        /// Write each section to its own file in this directory, e.g. `queries.json`,
+        /// `errors.json` and `total.json`, one set per structured format. The directory
+        /// is created if missing. Text output is printed to stdout.
+        #[arg(long, conflicts_with = "output_file")]
+        output_dir: Option<PathBuf>,
+
+        /// Allow `--output-dir` to replace files that already exist.
+        #[arg(long, requires = "output_dir")]
+        overwrite: bool,
+    },
+
+    /// Show merges and mutations in progress on every node, from system.merges.
+    ///
+    /// Helps to spot runaway merges eating memory or merge pressure on a table.
+    Merges {
+        #[clap(flatten)]
+        conn: ConnectArgs,
+
+        /// number of output merges
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+
+        /// Sort the merges by this metric, descending.
+        #[arg(long, default_value = "memory")]
+        sort_by: MergesSortBy,
+    },
+
+    /// Show unfinished mutations (ALTER UPDATE/DELETE) on every node, from system.mutations.
+    ///
+    /// Helps to spot mutations stuck for days, e.g. on a failing part.
+    Mutations {
+        #[clap(flatten)]
+        conn: ConnectArgs,
+
+        #[clap(flatten)]
+        filter: MutationsFilterArgs,
+
+        /// number of output mutations
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+
+        /// Sort the mutations by this metric, descending.
+        #[arg(long, default_value = "age")]
+        sort_by: MutationsSortBy,
+    },
+
+    /// Show the lag of replicated tables, from system.replicas of every node.
+    ///
+    /// Replicas delayed more than --max-lag are marked as lagging.
+    Replication {
+        #[clap(flatten)]
+        conn: ConnectArgs,
+
+        /// Mark replicas whose absolute_delay exceeds this threshold as lagging.
+        /// Accepts human-readable durations like '5m', '1h 30m', etc
+        #[arg(long, default_value = "5m", value_parser = humantime::parse_duration)]
+        max_lag: std::time::Duration,
+
+        /// Show every replica with its node instead of one row per table.
+        #[arg(long)]
+        by_node: bool,
+    },
+
+    /// Show the largest tables by storage, from the active parts in system.parts.
+    ///
+    /// Helps to find tables that grow too fast or have too many parts.
+    Parts {
+        #[clap(flatten)]
+        conn: ConnectArgs,
+
+        #[clap(flatten)]
+        filter: PartsFilterArgs,
+
+        /// number of output tables
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+
+    /// Show ClickHouse settings whose values differ across cluster nodes.
+    ///
+    /// This command reads `system.settings` from every node and compares the values,
+    /// helping to catch configuration drift between replicas and shards.
+    SettingsDiff {
+        #[clap(flatten)]
+        conn: ConnectArgs,
+
+        /// Show only settings that differ between nodes (enabled by default).
+        #[arg(long, default_value_t = true, action = ArgAction::Set)]
+        changed_only: bool,
+    },
+
+    /// Check that every node answers, with its round-trip latency.
+    ///
+    /// Sends `SELECT 1` probes to every node and reports min/avg/p95 latency per node,
+    /// to spot an unreachable, flaky or slow replica before running heavy analysis.
+    Ping {
+        #[clap(flatten)]
+        conn: ConnectArgs,
+
+        /// Number of probes sent to each node, one after another.
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        count: u32,
     },
 
     /// Manage context profiles used for connecting to ClickHouse.
     Context {
+        /// Preview what `set`/`delete` would change without touching the config
+        /// file or the keyring.
+        #[arg(long, global = true)]
+        dry_run: bool,
+
         #[command(subcommand)]
         command: ContextCommand,
     },
+
+    /// Print the JSON Schema of a command's JSON output (`--out json`).
+    #[cfg(feature = "schema")]
+    Schema {
+        /// Command whose output to describe.
+        kind: SchemaKind,
+    },
+
+    /// Show the clickcheck version.
+    Version {
+        /// Also show the git commit, build date, rustc version and enabled features,
+        /// useful for bug reports.
+        #[arg(long)]
+        verbose: bool,
+    },
 }
 
 /// Connection-related arguments used in multiple commands.
 #[derive(Args, Clone, Debug)]
 pub struct ConnectArgs {
-    /// ClickHouse node URL (can be specified multiple times)
+    /// ClickHouse node URL of the HTTP interface (can be specified multiple times),
+    /// `[http[s]://]host[:port][/database][?secure=true&skip_verify=true]`.
+    /// `clickhouse://` and other native TCP URLs are not supported.
     #[arg(short = 'U', long = "url")]
     pub urls: Vec<String>,
 
+    /// Only query this node of the profile's URLs (can be specified multiple times).
+    /// Useful to isolate a single replica without editing the profile.
+    #[arg(long = "node", conflicts_with = "node_indexes")]
+    pub nodes: Vec<String>,
+
+    /// Only query the node at this 0-based position in the profile's URLs
+    /// (can be specified multiple times).
+    #[arg(long = "node-index")]
+    pub node_indexes: Vec<usize>,
+
     /// ClickHouse username
     #[arg(short = 'u', long)]
     pub user: Option<String>,
 
-    /// ClickHouse password
-    #[arg(short = 'p', long, value_parser = parse_secret_arg)]
+    /// ClickHouse password. With a context profile, given here or in the
+    /// environment, the system keyring is not read at all.
+    #[arg(
+        short = 'p',
+        long,
+        env = "CLICKCHECK_PASSWORD",
+        hide_env_values = true,
+        value_parser = parse_secret_arg
+    )]
     pub password: Option<secrecy::SecretString>,
 
-    /// ClickHouse password from interactive prompt
-    #[arg(short = 'i', long, conflicts_with = "password")]
+    /// ClickHouse password from interactive prompt, takes precedence over
+    /// `CLICKCHECK_PASSWORD`
+    #[arg(short = 'i', long)]
     pub interactive_password: bool,
 
     /// Accept invalid (e.g., self-signed) TLS certificates when connecting over HTTPS.
@@ -143,6 +561,89 @@ pub struct ConnectArgs {
     /// due to potential security risks.
     #[arg(long)]
     pub accept_invalid_certificate: Option<bool>,
+
+    /// Oldest TLS protocol version accepted from `https://` nodes. TLS 1.3 is
+    /// not supported by the macOS system TLS library.
+    #[arg(long, value_name = "VERSION", default_value = "1.2")]
+    pub tls_min_version: TlsVersion,
+
+    /// Verify TLS certificates against this host name, also sent as SNI, instead of
+    /// the host of the URL. Keeps verification on when connecting by IP, e.g. to a
+    /// load balancer. Overrides the profile's `tls_server_name`.
+    #[arg(long, value_name = "HOST")]
+    pub tls_server_name: Option<String>,
+
+    /// ClickHouse setting applied to every clickcheck query, as `key=value`
+    /// (e.g. `max_threads=2`). Can be specified multiple times.
+    #[arg(long = "setting", value_parser = parse_key_value)]
+    pub settings: Vec<(String, String)>,
+
+    /// File of ClickHouse settings applied to every clickcheck query, one `key=value`
+    /// per line. Blank lines and lines starting with `#` are ignored. `--setting`
+    /// overrides settings of the file.
+    #[arg(long, value_name = "PATH")]
+    pub settings_file: Option<PathBuf>,
+
+    /// Raw HTTP header sent with every request, as `key=value`
+    /// (e.g. `X-ClickHouse-Quota=reports`). Can be specified multiple times.
+    /// Values are treated as secrets and never printed.
+    #[arg(long = "header", value_parser = parse_secret_key_value)]
+    pub headers: Vec<(String, secrecy::SecretString)>,
+
+    /// Run clickcheck's queries in ClickHouse read-only mode (enabled by default).
+    ///
+    /// This only restricts clickcheck's own session, it does not change any server
+    /// or user setting. Pass `--readonly false` for accounts whose profile already
+    /// forbids changing the `readonly` setting.
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub readonly: bool,
+
+    /// Fail the whole command, naming the node, when any node is unreachable or a
+    /// query fails on it. This is the default, the flag makes the choice explicit.
+    #[arg(long, conflicts_with_all = ["allow_partial", "skip_unreachable"])]
+    pub all_nodes_required: bool,
+
+    /// Skip nodes that are unreachable when connecting, with a warning, and report
+    /// on the remaining ones. Aggregates then only cover part of the cluster.
+    /// A node failing later, in the middle of a query, still fails the command.
+    #[arg(long)]
+    pub allow_partial: bool,
+
+    /// Skip nodes whose query fails with a connection error or timeout (after
+    /// `--retries`), with a warning, and report on the remaining ones. Unlike
+    /// `--allow-partial` this also covers nodes going down after connecting, as long
+    /// as they streamed no rows yet. Exits with code 3 if every node was skipped.
+    #[arg(long)]
+    pub skip_unreachable: bool,
+
+    /// Fail instead of warning when a node runs a ClickHouse version older than
+    /// the minimum supported by clickcheck.
+    #[arg(long)]
+    pub strict_version: bool,
+
+    /// Cap the memory ClickHouse may use for each of clickcheck's own queries
+    /// (`max_memory_usage`), e.g. 10GiB. Queries over the limit fail instead of
+    /// straining the server. Unset by default, using the server default.
+    #[arg(long, value_parser = parse_byte_size)]
+    pub server_max_memory: Option<bytesize::ByteSize>,
+
+    /// Give up on a node's query after this long, e.g. 2min, and let the server stop
+    /// it too (`max_execution_time`, unless given with `--setting`). Also limits the
+    /// probes of `ping` and `--allow-partial` and the wait for `query_log` of `--iterations`.
+    /// 0 disables the limit. A timed out node counts as unreachable for `--retries`
+    /// and `--skip-unreachable`, as long as it streamed no rows yet.
+    #[arg(long, value_name = "DURATION", default_value = "30s", value_parser = parse_timeout)]
+    pub timeout: std::time::Duration,
+
+    /// Retry a node's query up to N times on connection errors and timeouts, e.g.
+    /// while a node restarts. SQL errors are never retried, nor is a node that
+    /// already streamed rows, as those would be counted twice.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub retries: u32,
+
+    /// Delay before the first retry, doubled for every further one.
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = humantime::parse_duration)]
+    pub retry_backoff: std::time::Duration,
 }
 
 /// Filters for narrowing down which queries to include in `queries` analysis.
@@ -179,6 +680,19 @@ pub struct QueriesFilterArgs {
     /// Filter by the user who executed the query. Can be specified multiple times.
     #[arg(long = "query-user")]
     pub query_user: Vec<String>,
+    /// Hide queries executed by this user, e.g. a known service account.
+    /// Can be specified multiple times. A user given to both `--query-user` and
+    /// `--exclude-user` is excluded.
+    #[arg(long)]
+    pub exclude_user: Vec<String>,
+    /// Filter by the exception code the query failed with, e.g. 241 for
+    /// MEMORY_LIMIT_EXCEEDED, 0 for successful queries. Can be specified multiple times.
+    #[arg(long)]
+    pub exception_code: Vec<i32>,
+    /// Only include finished (ok) or failed queries, e.g. `failed` to find the
+    /// fingerprints behind OOMs. `all` includes both.
+    #[arg(long, default_value = "all")]
+    pub status: QueryStatus,
     /// Filter by database name. Can be specified multiple times.
     #[arg(long)]
     pub database: Vec<String>,
@@ -193,14 +707,59 @@ pub struct QueriesFilterArgs {
     #[arg(long)]
     pub min_read_rows: Option<u64>,
     /// Filter by the minimum amount of data read (supports units like B, KB, MB, GiB)
-    #[arg(long, value_parser = bytesize::ByteSize::from_str)]
+    #[arg(long, value_parser = parse_byte_size)]
     pub min_read_data: Option<bytesize::ByteSize>,
+
+    /// Multiply the collected metrics by this factor to extrapolate true totals
+    /// from a sampled `query_log`, e.g. 10 for `log_queries_probability = 0.1`.
+    /// Extrapolated numbers are labeled as such in the output.
+    #[arg(long, value_parser = parse_sampling_factor)]
+    pub log_sampling_factor: Option<f64>,
+
+    /// Split the time range into K parts queried concurrently on every node and
+    /// merged here. Lowers the wall-clock time of a long scan on a node with spare
+    /// cores, at the cost of K times the concurrent queries on the server. Not
+    /// supported with `--skip-unreachable`, which could leave out a node from some
+    /// parts only: use `--allow-partial` to leave out unreachable nodes.
+    #[arg(
+        long,
+        value_name = "K",
+        default_value_t = 1,
+        conflicts_with = "skip_unreachable",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub concurrency_per_node: u32,
+}
+
+/// Multipliers of the impact categories, for `queries` and `total`.
+///
+/// The defaults are the built-in weights, e.g. `--weight-cpu 0` ranks by everything
+/// but CPU time.
+#[derive(Args, Debug, Clone)]
+pub struct ImpactWeightArgs {
+    /// Weight of `read_rows * 100 + read_bytes` in io-impact.
+    #[arg(long, value_name = "N", default_value_t = ImpactWeights::default().io)]
+    pub weight_io: u64,
+    /// Weight of received plus sent network bytes in network-impact.
+    #[arg(long, value_name = "N", default_value_t = ImpactWeights::default().network)]
+    pub weight_network: u64,
+    /// Weight of user plus system CPU microseconds in cpu-impact.
+    #[arg(long, value_name = "N", default_value_t = ImpactWeights::default().cpu)]
+    pub weight_cpu: u64,
+    /// Weight of memory_usage bytes in memory-impact.
+    #[arg(long, value_name = "N", default_value_t = ImpactWeights::default().memory)]
+    pub weight_memory: u64,
+    /// Weight of query_duration_ms in time-impact.
+    #[arg(long, value_name = "N", default_value_t = ImpactWeights::default().time)]
+    pub weight_time: u64,
 }
 
 /// Filters for the `errors` command.
 #[derive(Args, Debug, Clone)]
 pub struct ErrorFilterArgs {
-    /// Only include errors that occurred within the last specified time period.
+    /// Only include errors last seen within the specified time period.
+    /// A code passes if its latest occurrence is recent, even if it started long ago;
+    /// use `--new-since` to keep only errors first seen after a given time.
     /// Accepts human-readable durations like '15days 2min 2s', etc
     #[arg(long, value_parser = humantime::parse_duration)]
     pub last: Option<std::time::Duration>,
@@ -212,6 +771,40 @@ pub struct ErrorFilterArgs {
     /// Can be used multiple times to include multiple codes.
     #[arg(long)]
     pub code: Vec<i32>,
+    /// Hide errors with this ClickHouse error code, e.g. a known-benign `210`.
+    /// Can be used multiple times, applied after `--code`.
+    #[arg(long)]
+    pub exclude_code: Vec<i32>,
+    /// Only include errors first seen at or after this time on every node, e.g. since
+    /// a deploy. Supports RFC3339 or YYYY-MM-DD. Needs system.errors.first_error_time.
+    #[arg(long, value_parser = parse_datetime)]
+    pub new_since: Option<OffsetDateTime>,
+}
+
+/// Filters for the `mutations` command.
+#[derive(Args, Debug, Clone)]
+pub struct MutationsFilterArgs {
+    /// Only include mutations whose last attempt failed (non-empty latest_fail_reason).
+    /// They won't finish until the cause is fixed or they are killed.
+    #[arg(long)]
+    pub only_stuck: bool,
+    /// Only include mutations created at least this long ago.
+    /// Accepts human-readable durations like '1day 2h', etc
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub min_age: Option<std::time::Duration>,
+}
+
+/// Filters for the `parts` command.
+#[derive(Args, Debug, Clone)]
+pub struct PartsFilterArgs {
+    /// Only include tables of this database.
+    /// Can be used multiple times to include multiple databases.
+    #[arg(long)]
+    pub database: Vec<String>,
+    /// Only include tables with this name, in any of the included databases.
+    /// Can be used multiple times to include multiple tables.
+    #[arg(long)]
+    pub table: Vec<String>,
 }
 
 /// Subcommands for inspecting or modifying context profiles.
@@ -220,9 +813,26 @@ pub enum ContextCommand {
     /// Show config file which store context profiles
     ConfigPath,
     /// List all available context profiles
-    List,
+    List {
+        /// Only list profiles with this `key=value` label.
+        /// Can be specified multiple times, a profile must have all of them.
+        #[arg(long = "label", value_parser = parse_key_value)]
+        labels: Vec<(String, String)>,
+    },
     /// Show the active context (CLI override or stored default)
-    Current,
+    Current {
+        /// Show the effective connection profile instead of the name: the active
+        /// context with the connection flags given here applied, as analysis
+        /// commands would use it.
+        #[arg(long)]
+        resolve: bool,
+        /// Show sensitive information like passwords
+        #[arg(long, requires = "resolve")]
+        show_secrets: bool,
+        /// Connection flags, only used with `--resolve`.
+        #[clap(flatten)]
+        conn: ConnectArgs,
+    },
     /// Show details for a specific profile by name
     Show {
         name: String,
@@ -245,7 +855,13 @@ pub enum ContextSetCommand {
     /// Create or update a context profile
     Profile(SetProfileArgs),
     /// Set the stored default context to an existing profile
-    Current { name: String },
+    Current {
+        #[arg(required_unless_present = "unset")]
+        name: Option<String>,
+        /// Clear the stored default context instead, so commands rely on CLI flags or `--context`
+        #[arg(long, conflicts_with = "name")]
+        unset: bool,
+    },
 }
 
 /// Arguments for creating or updating a context profile.
@@ -256,7 +872,7 @@ pub struct SetProfileArgs {
     /// The name of the profile to create or update
     pub name: String,
 
-    /// ClickHouse node URLs
+    /// ClickHouse node URLs of the HTTP interface
     #[arg(short = 'U', long = "url", required = true)]
     pub urls: Vec<String>,
 
@@ -280,6 +896,16 @@ pub struct SetProfileArgs {
     /// due to potential security risks.
     #[arg(long, default_value_t = false)]
     pub accept_invalid_certificate: bool,
+
+    /// Verify TLS certificates against this host name, also sent as SNI, instead of
+    /// the host of the URL, e.g. when the profile connects to a load balancer by IP.
+    #[arg(long, value_name = "HOST")]
+    pub tls_server_name: Option<String>,
+
+    /// Label the profile as `key=value` (e.g. `env=prod`), used by `context list --label`.
+    /// Can be specified multiple times. Replaces the labels of an existing profile.
+    #[arg(long = "label", value_parser = parse_key_value)]
+    pub labels: Vec<(String, String)>,
 }
 
 /// Parses either a full RFC3339 timestamp or a YYYY-MM-DD date.
@@ -298,13 +924,243 @@ fn parse_datetime(s: &str) -> Result<OffsetDateTime, String> {
     Err("Invalid datetime format. Use RFC3339 (e.g. 2024-05-01T10:30:00Z) or YYYY-MM-DD.".into())
 }
 
+/// Prompts for the ClickHouse password of `user` on the terminal (`--interactive-password`).
+///
+/// Fails right away when stdin is not a terminal (e.g. in CI) instead of
+/// blocking on input that never comes.
+pub fn prompt_password(user: &str) -> Result<secrecy::SecretString, String> {
+    if !std::io::stdin().is_terminal() {
+        return Err(
+            "interactive password requested but no TTY available; use `--password` instead".into(),
+        );
+    }
+    let password = rpassword::prompt_password(format!("ClickHouse {user} password: "))
+        .map_err(|e| format!("read password from prompt: {e}"))?;
+    Ok(secrecy::SecretString::new(password.into()))
+}
+
+/// Parses `--timeout`: a human-readable duration, or a bare `0` for no limit.
+fn parse_timeout(s: &str) -> Result<std::time::Duration, String> {
+    match s.trim() {
+        "0" => Ok(std::time::Duration::ZERO),
+        s => humantime::parse_duration(s).map_err(|e| format!("Invalid timeout `{s}`: {e}")),
+    }
+}
+
+/// Parses `--log-sampling-factor`: the inverse of the logged fraction of queries, so at least 1.
+fn parse_sampling_factor(s: &str) -> Result<f64, String> {
+    let factor: f64 = s
+        .parse()
+        .map_err(|e| format!("Invalid sampling factor `{s}`: {e}"))?;
+    if !factor.is_finite() || factor < 1.0 {
+        return Err(format!(
+            "Invalid sampling factor `{s}`: expected a number >= 1, the inverse of the sampling probability"
+        ));
+    }
+    Ok(factor)
+}
+
+/// Parses `--outlier-factor`: a deviation from the median, so greater than 1.
+fn parse_outlier_factor(s: &str) -> Result<f64, String> {
+    let factor: f64 = s
+        .parse()
+        .map_err(|e| format!("Invalid outlier factor `{s}`: {e}"))?;
+    if !factor.is_finite() || factor <= 1.0 {
+        return Err(format!(
+            "Invalid outlier factor `{s}`: expected a number > 1"
+        ));
+    }
+    Ok(factor)
+}
+
+/// Parses a data size like `1.5GB`, also accepting a decimal comma (`1,5GB`) and a
+/// space before the unit (`1 GB`).
+///
+/// A comma followed by exactly three digits (`1,500MB`) reads as a thousands
+/// separator and is rejected rather than guessed.
+fn parse_byte_size(s: &str) -> Result<bytesize::ByteSize, String> {
+    let invalid = |reason: &str| {
+        format!(
+            "Invalid size `{s}`: {reason}, expected a number with an optional unit \
+             (B, KB, MB, GB, TB, KiB, MiB, GiB, TiB), e.g. 500MB, 1.5GiB or 1 GB"
+        )
+    };
+    let trimmed = s.trim();
+    let unit_start = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ','))
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(unit_start);
+    // Пробелы допустимы только между числом и единицей
+    let unit = unit.trim_start();
+    if !unit.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(invalid("unexpected characters"));
+    }
+
+    let number = match number.split_once(',') {
+        None => number.to_string(),
+        Some((_, fraction)) if number.contains('.') || fraction.contains(',') => {
+            return Err(invalid("more than one decimal separator"))
+        }
+        Some((_, fraction)) if fraction.len() == 3 => {
+            return Err(invalid("ambiguous comma, write 1.5MB or 1500MB"))
+        }
+        Some((whole, fraction)) => format!("{whole}.{fraction}"),
+    };
+    bytesize::ByteSize::from_str(&format!("{number}{unit}")).map_err(|_| invalid("not a size"))
+}
+
 /// Parses a password from a CLI argument into a `SecretString`.
 /// Used to avoid leaking secrets in logs or stack traces.
 fn parse_secret_arg(s: &str) -> Result<secrecy::SecretString, String> {
     Ok(secrecy::SecretString::new(s.to_string().into()))
 }
 
-fn parse_hex(s: &str) -> Result<u64, String> {
-    u64::from_str_radix(s.trim_start_matches("0x"), 16)
-        .map_err(|e| format!("Invalid hex value: {}", e))
+/// Parses a `key=value` pair, as used by `--setting`.
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid `key=value` pair: no `=` found in `{s}`"))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("Invalid `key=value` pair: empty key in `{s}`"));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parses the content of a `--settings-file`, reporting every malformed line.
+pub fn parse_settings_file(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut settings = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_key_value(line) {
+            Ok((key, value)) => settings.push((key, value.trim().to_string())),
+            Err(e) => errors.push(format!("line {}: {e}", i + 1)),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(settings),
+        false => Err(errors.join("\n")),
+    }
+}
+
+/// Parses a `key=value` pair whose value must not leak into logs, as used by `--header`.
+fn parse_secret_key_value(s: &str) -> Result<(String, secrecy::SecretString), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or("Invalid `key=value` pair: no `=` found")?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err("Invalid `key=value` pair: empty key".into());
+    }
+    Ok((key.to_string(), parse_secret_arg(value)?))
+}
+
+// Принимает отпечаток в обоих видах `--fingerprint-format`: с префиксом `0x`
+// или буквами a-f это hex, иначе десятичное число
+fn parse_fingerprint(s: &str) -> Result<u64, String> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16).map_err(|e| format!("Invalid hex value: {e}"));
+    }
+    if s.chars().any(|c| c.is_ascii_alphabetic()) {
+        return u64::from_str_radix(s, 16).map_err(|e| format!("Invalid hex value: {e}"));
+    }
+    s.parse().map_err(|e| format!("Invalid decimal value: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_byte_size_accepts() {
+        for (input, bytes) in [
+            ("1024", 1024),
+            ("500MB", 500_000_000),
+            ("1.5GB", 1_500_000_000),
+            ("1,5GB", 1_500_000_000),
+            ("1 GB", 1_000_000_000),
+            ("  2 MiB ", 2 * 1024 * 1024),
+            ("0,25KB", 250),
+            ("1,5000MB", 1_500_000),
+            ("1.5GiB", 3 * 512 * 1024 * 1024),
+        ] {
+            assert_eq!(
+                parse_byte_size(input),
+                Ok(bytesize::ByteSize(bytes)),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_byte_size_rejects() {
+        for input in [
+            "", "GB", "abc", "-1GB", "1,500MB", "1,000", "1 5GB", "1 G B", "1,5.0GB", "1,5,0GB",
+            "1.2.3GB", "1.5XB",
+        ] {
+            assert!(parse_byte_size(input).is_err(), "{input}");
+        }
+    }
+
+    fn parses(args: &str) -> bool {
+        CliArgs::try_parse_from(format!("clickcheck {args}").split_whitespace()).is_ok()
+    }
+
+    #[test]
+    fn dry_run_is_only_a_context_flag() {
+        assert!(parses("context --dry-run delete prod"));
+        assert!(parses("context delete prod --dry-run"));
+        assert!(parses("context set current prod --dry-run"));
+        assert!(!parses("errors --dry-run"));
+        assert!(!parses("--dry-run context delete prod"));
+    }
+
+    #[test]
+    fn output_dir_is_only_a_health_flag() {
+        assert!(parses("health --last 1h --output-dir out --overwrite"));
+        assert!(!parses("queries --last 1h --output-dir out"));
+        assert!(!parses("health --last 1h --overwrite"));
+        assert!(!parses(
+            "health --last 1h --output-dir out --output-file out.json"
+        ));
+        // Глобальный `--output-file` до подкоманды clap пропускает, его отвергает `run`
+        assert!(parses(
+            "--output-file out.json health --last 1h --output-dir out"
+        ));
+    }
+
+    #[test]
+    fn iterations_only_where_clickhouse_runs_the_analysis() {
+        assert!(parses("queries --last 1h --iterations 3"));
+        assert!(parses("total --last 1h --iterations 3"));
+        assert!(parses("inspect 0x1 --last 1h --iterations 3"));
+        assert!(!parses("errors --iterations 3"));
+        assert!(!parses("--iterations 3 queries --last 1h"));
+        assert!(!parses("queries --last 1h --iterations 0"));
+        assert!(!parses(
+            "queries --last 1h --cache c --use-cache --iterations 3"
+        ));
+        assert!(!parses("total --history --iterations 3"));
+    }
+
+    #[cfg(feature = "tui")]
+    #[test]
+    fn tui_conflicts_with_non_interactive_flags() {
+        assert!(parses("queries --last 1h --tui"));
+        for flag in [
+            "--cache c",
+            "--by-node",
+            "--cache c --use-cache",
+            "--iterations 3",
+        ] {
+            assert!(
+                !parses(&format!("queries --last 1h --tui {flag}")),
+                "{flag}"
+            );
+        }
+    }
 }
@@ -17,6 +17,7 @@
 //! ## Supported Operations
 //! - [`Client::stream_logs_by_fingerprint`] — Streams normalized query log summaries.
 //! - [`Client::stream_error_by_code`] — Streams frequent ClickHouse errors grouped by code.
+//! - [`Client::stream_settings`] — Streams `system.settings` of every node.
 //!
 //! ## Filtering
 //! Query filtering is handled internally and supports filtering query logs and system errors.
@@ -25,6 +26,20 @@
 //! If `danger_accept_invalid_certs` is true in [`Config`], the client will
 //! accept invalid or self-signed certificates (intended for dev/test environments).
 //!
+//! ## Settings and headers
+//! [`Config::settings`] are sent as ClickHouse query settings and [`Config::headers`]
+//! as raw HTTP headers on every request, e.g. to cap `max_threads` or pick a quota key.
+//!
+//! ## Read-only mode
+//! If [`Config::readonly`] is set, every query runs with the `readonly` setting, so
+//! clickcheck can't modify data even with write-capable credentials. This affects only
+//! clickcheck's own session, never the server configuration.
+//!
+//! ## Protocol
+//! Only the HTTP(S) interface is supported, as the [`clickhouse`] crate has no native
+//! TCP transport. `tcp://`, `clickhouse://` and `native://` URLs are rejected with
+//! [`ClientError::InvalidUrl`]. Servers from [`MIN_SERVER_VERSION`] on are supported.
+//!
 //! ## Notes
 //! - Uses custom impact score formulas (I/O, CPU, memory, time) to rank query logs.
 //! - Designed for async environments using [`tokio`] and channels.
@@ -33,29 +48,78 @@
 //! This module forms the core data access layer for ClickHouse-backed analytics.
 
 mod filter;
+mod impact;
+mod tls;
 
-use crate::model::{Error, QueryLog, QueryLogExtended, QueryLogTotal};
+use crate::diagnostics;
+use crate::model::{
+    redact_url, Backpressure, ColumnCount, Error, Merge, Mutation, NodePing, Part, QueryDuration,
+    QueryLog, QueryLogExtended, QueryLogRetention, QueryLogTotal, QueryLogTotalBucket, Replica,
+    ServerVersion, Setting, TimeBucket, Timing, TlsVersion,
+};
 use clickhouse::{error::Error as ChError, query::Query as ChQuery, Client as ChClient, Row};
-use filter::{ErrorFilter, QueryLogFilter};
-use futures::future::try_join_all;
-use hyper_tls::native_tls;
+use filter::{ErrorFilter, MutationFilter, PartFilter, QueryLogFilter, QueryParam};
+use futures::future::{join_all, try_join_all};
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client as HyperClient;
 use hyper_util::rt::TokioExecutor;
+pub use impact::ImpactExpr;
 use secrecy::ExposeSecret;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::sync::mpsc::{error::SendError, Sender};
+use tokio::sync::mpsc::{
+    self,
+    error::{SendError, TrySendError},
+    Sender,
+};
+use tokio::sync::OnceCell;
 
 // Константы для конфигурации HTTP клиента
 const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
 // ClickHouse uses 3s by default.
 // See https://github.com/ClickHouse/ClickHouse/blob/368cb74b4d222dc5472a7f2177f6bb154ebae07a/programs/server/config.xml#L201
 const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Oldest ClickHouse release known to provide every column and `ProfileEvents` key clickcheck reads.
+pub const MIN_SERVER_VERSION: (u32, u32) = (21, 8);
+/// Features that may be missing or misreported on servers older than [`MIN_SERVER_VERSION`].
+pub const MIN_SERVER_VERSION_FEATURES: &str =
+    "`ProfileEvents` map (CPU and network impact), `system.errors` last error time";
+
+// Read-only mode that still permits changing settings, see
+// https://clickhouse.com/docs/operations/settings/permissions-for-queries#readonly
+const READONLY_SESSION: &str = "2";
+// Пауза между проверками query_log, пока сервер не сбросил в него запросы
+const QUERY_LOG_POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Завершённые запросы, помеченные log_comment прогона `--iterations`
+const TAGGED_QUERIES_WHERE: &str =
+    "type = 'QueryFinish' AND event_date >= yesterday() AND log_comment = ?";
 
 pub struct Client {
-    nodes: Vec<ChClient>,
+    nodes: Vec<Node>,
+    /// Streaming duration and backpressure of every finished per-node query, reported by `--timings`.
+    node_timings: Mutex<Vec<Timing>>,
+    /// Server versions, queried at most once per client.
+    server_versions: OnceCell<Vec<ServerVersion>>,
+    /// See [`Config::retries`].
+    retries: u32,
+    /// See [`Config::retry_backoff`].
+    retry_backoff: Duration,
+    /// See [`Config::skip_unreachable`].
+    skip_unreachable: bool,
+    /// URLs of the nodes left out by `skip_unreachable` so far.
+    skipped_nodes: Mutex<Vec<String>>,
+    /// See [`Config::timeout`].
+    timeout: Option<Duration>,
+}
+
+/// A single ClickHouse node together with the URL it was built from.
+struct Node {
+    /// Node URL for display, with any embedded credentials redacted.
+    url: String,
+    client: ChClient,
 }
 
 pub struct Config<'a> {
@@ -63,6 +127,29 @@ pub struct Config<'a> {
     pub user: &'a str,
     pub password: &'a secrecy::SecretString,
     pub danger_accept_invalid_certs: bool,
+    /// Oldest TLS protocol version accepted from `https://` nodes.
+    pub tls_min_version: TlsVersion,
+    /// Name the certificate of `https://` nodes is verified against (and sent as SNI)
+    /// instead of the URL host, e.g. when connecting to a load balancer by IP.
+    pub tls_server_name: Option<&'a str>,
+    /// ClickHouse settings applied to every query (e.g. `max_threads`).
+    pub settings: &'a [(String, String)],
+    /// Extra HTTP headers sent with every request (e.g. `X-ClickHouse-Quota`).
+    pub headers: &'a [(String, secrecy::SecretString)],
+    /// Forbid data and DDL modifications for clickcheck's own session.
+    pub readonly: bool,
+    /// `max_memory_usage` in bytes for every query, overriding `settings`.
+    pub max_memory_usage: Option<u64>,
+    /// Retries of a node's streaming query on connection errors and timeouts.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further one.
+    pub retry_backoff: Duration,
+    /// Leave out nodes failing with connection errors or timeouts before streaming
+    /// any row, instead of failing the query.
+    pub skip_unreachable: bool,
+    /// Limit of every request to a node (streaming queries and probes), and of the
+    /// wait for `query_log`, also sent as `max_execution_time` unless `settings` has it.
+    pub timeout: Option<Duration>,
 }
 
 #[derive(Debug, Error)]
@@ -71,7 +158,7 @@ pub enum ClientError {
     Query(#[from] ChError),
 
     #[error("failed to send query log: {0}")]
-    SendQueryLog(#[from] SendError<QueryLog>),
+    SendQueryLog(#[from] Box<SendError<QueryLog>>),
 
     #[error("failed to send error: {0}")]
     SendError(#[from] SendError<Error>),
@@ -84,25 +171,160 @@ pub enum ClientError {
 
     #[error("failed to create native tls config: {0}")]
     InitializationError(#[from] native_tls::Error),
+
+    #[error("no ClickHouse URLs configured: supply `--url` or add `urls` to the profile")]
+    NoNodes,
+
+    #[error("invalid identifier {0:?}: expected [A-Za-z0-9_]+, optionally qualified as db.name")]
+    InvalidIdentifier(String),
+
+    #[error("query timed out after {}", humantime::format_duration(*.0))]
+    Timeout(Duration),
+
+    #[error("every ClickHouse node is unreachable")]
+    AllNodesUnreachable,
+
+    #[error("invalid ClickHouse URL {url}: {reason}")]
+    InvalidUrl { url: String, reason: String },
+
+    #[error("node {url}: {source}")]
+    Node {
+        url: String,
+        source: Box<ClientError>,
+    },
+}
+
+/// A `--url` split into the URL the client connects to and the per-node options
+/// encoded in it, e.g. `https://host:8443/logs?skip_verify=true`.
+struct NodeUrl {
+    /// Scheme and authority only, without path and query.
+    base: String,
+    /// Database holding `query_log`, from the URL path. `system` when unset.
+    database: Option<String>,
+    /// `skip_verify=true`: accept invalid certificates of this node only.
+    skip_verify: bool,
+}
+
+impl ClientError {
+    /// Whether the error is a connection failure or timeout worth retrying,
+    /// see [`Config::retries`]. Errors reported by ClickHouse itself are not.
+    fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ClientError::Query(ChError::Network(_) | ChError::TimedOut) | ClientError::Timeout(_)
+        )
+    }
 }
 
-fn from_insecure_hyper_client() -> Result<ChClient, ClientError> {
+impl NodeUrl {
+    /// Parses `[scheme://]host[:port][/database][?secure=..&skip_verify=..]`.
+    ///
+    /// Bare `host:port` URLs mean `http://`, and `secure=true` switches `http://`
+    /// to `https://`. Native TCP URLs (`clickhouse://`, `tcp://`) are not supported.
+    fn parse(url: &str) -> Result<Self, ClientError> {
+        let invalid = |reason: String| ClientError::InvalidUrl {
+            url: redact_url(url),
+            reason,
+        };
+        let (rest, query) = match url.split_once('?') {
+            Some((rest, query)) => (rest, query),
+            None => (url, ""),
+        };
+        let (scheme, after_scheme) = match rest.split_once("://") {
+            Some((scheme, after_scheme)) => (Some(scheme), after_scheme),
+            None => (None, rest),
+        };
+        let scheme = scheme.map(str::to_ascii_lowercase);
+        if let Some(scheme @ ("tcp" | "clickhouse" | "native")) = scheme.as_deref() {
+            return Err(invalid(format!(
+                "the native TCP protocol (`{scheme}://`) is not supported, clickcheck talks to \
+                 the HTTP interface: use port 8123, or 8443 with TLS"
+            )));
+        }
+        let (authority, path) = after_scheme.split_once('/').unwrap_or((after_scheme, ""));
+
+        let database = match path.trim_end_matches('/') {
+            "" => None,
+            database if database.contains(['/', '.']) => {
+                return Err(invalid(format!(
+                    "expected a single database in the path, got `{database}`"
+                )))
+            }
+            database => {
+                validate_identifier(database).map_err(|e| invalid(e.to_string()))?;
+                Some(database.to_string())
+            }
+        };
+
+        let mut secure = false;
+        let mut skip_verify = false;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, "true"));
+            let value = match value {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => return Err(invalid(format!("expected true or false for `{key}`"))),
+            };
+            match key {
+                "secure" => secure = value,
+                "skip_verify" => skip_verify = value,
+                _ => {
+                    return Err(invalid(format!(
+                        "unknown parameter `{key}`, expected secure or skip_verify"
+                    )))
+                }
+            }
+        }
+
+        // 9000/9440 — порты нативного протокола, по HTTP там не ответят
+        if authority.ends_with(":9000") || authority.ends_with(":9440") {
+            diagnostics::warn(format!(
+                "{} points at a native TCP port, clickcheck needs the HTTP interface (8123 or 8443)",
+                redact_url(url)
+            ));
+        }
+        let base = match (scheme.as_deref(), secure) {
+            (Some("http") | None, true) => format!("https://{authority}"),
+            (Some(scheme), _) => format!("{scheme}://{authority}"),
+            (None, false) => format!("http://{authority}"),
+        };
+        Ok(Self {
+            base,
+            database,
+            skip_verify,
+        })
+    }
+}
+
+fn from_hyper_client(cfg: &Config, node: &NodeUrl) -> Result<ChClient, ClientError> {
     let mut connector = HttpConnector::new(); // or HttpsConnectorBuilder
 
     connector.set_keepalive(Some(TCP_KEEPALIVE));
     connector.enforce_http(false);
 
+    let min_protocol_version = match cfg.tls_min_version {
+        TlsVersion::Tls12 => native_tls::Protocol::Tlsv12,
+        TlsVersion::Tls13 => native_tls::Protocol::Tlsv13,
+    };
     let tls = native_tls::TlsConnector::builder()
-        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_certs(cfg.danger_accept_invalid_certs || node.skip_verify)
+        .min_protocol_version(Some(min_protocol_version))
         .build()?;
 
-    let connector = hyper_tls::HttpsConnector::from((connector, tls.into()));
-
-    let hyper_client = HyperClient::builder(TokioExecutor::new())
-        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
-        .build(connector);
+    let mut builder = HyperClient::builder(TokioExecutor::new());
+    builder.pool_idle_timeout(POOL_IDLE_TIMEOUT);
+    let client = match cfg.tls_server_name {
+        Some(server_name) => {
+            let connector = tls::ServerNameConnector::new(connector, tls, server_name);
+            ChClient::with_http_client(builder.build(connector))
+        }
+        None => {
+            let connector = hyper_tls::HttpsConnector::from((connector, tls.into()));
+            ChClient::with_http_client(builder.build(connector))
+        }
+    };
 
-    Ok(ChClient::with_http_client(hyper_client))
+    Ok(client)
 }
 
 impl Client {
@@ -114,26 +336,390 @@ impl Client {
     ///
     /// # Errors
     ///
-    /// Returns `ClientError` if initialization fails, including problems with TLS or URL handling.
+    /// Returns `ClientError` if initialization fails, including problems with TLS or URL handling,
+    /// and `ClientError::NoNodes` if `cfg.urls` is empty.
     pub fn new(cfg: Config) -> Result<Self, ClientError> {
-        let nodes = cfg
-            .urls
+        if cfg.urls.is_empty() {
+            return Err(ClientError::NoNodes);
+        }
+
+        // The same node listed twice would have every row counted twice, also when
+        // written differently, e.g. `h:8123` and `http://h:8123/`.
+        let mut urls: Vec<&String> = Vec::with_capacity(cfg.urls.len());
+        let mut node_urls: Vec<NodeUrl> = Vec::with_capacity(cfg.urls.len());
+        for url in cfg.urls {
+            let node = NodeUrl::parse(url)?;
+            if node_urls
+                .iter()
+                .any(|seen| seen.base == node.base && seen.database == node.database)
+            {
+                diagnostics::warn(format!(
+                    "duplicate ClickHouse URL {} is ignored",
+                    redact_url(url)
+                ));
+            } else {
+                urls.push(url);
+                node_urls.push(node);
+            }
+        }
+
+        // Plain `http://` nodes have no certificate to skip.
+        let insecure: Vec<String> = urls
             .iter()
-            .map(|url| {
-                let node = if cfg.danger_accept_invalid_certs {
-                    from_insecure_hyper_client()?
+            .zip(&node_urls)
+            .filter(|(_, node)| {
+                (cfg.danger_accept_invalid_certs || node.skip_verify)
+                    && !node.base.starts_with("http://")
+            })
+            .map(|(url, _)| redact_url(url))
+            .collect();
+        if !insecure.is_empty() {
+            diagnostics::warn(format!(
+                "TLS certificate verification is disabled for {}",
+                insecure.join(", ")
+            ));
+        }
+
+        // Responses already come LZ4-compressed: the `lz4` default feature of the
+        // clickhouse crate adds `compress=1` to every query and decodes it, so HTTP
+        // compression (`enable_http_compression`, gzip) would gain nothing.
+        let nodes = urls
+            .into_iter()
+            .zip(node_urls)
+            .map(|(url, node)| {
+                let client = from_hyper_client(&cfg, &node)?
+                    .with_url(&node.base)
+                    .with_user(cfg.user)
+                    .with_password(cfg.password.expose_secret())
+                    .with_database(node.database.as_deref().unwrap_or("system"));
+                // Сервер тоже прекращает работу; явный `--setting` ниже имеет приоритет
+                let client = match cfg.timeout {
+                    Some(timeout) => client.with_option(
+                        "max_execution_time",
+                        timeout.as_secs_f64().ceil().to_string(),
+                    ),
+                    None => client,
+                };
+                let client = cfg.settings.iter().fold(client, |client, (key, value)| {
+                    client.with_option(key, value)
+                });
+                let client = cfg.headers.iter().fold(client, |client, (key, value)| {
+                    client.with_header(key, value.expose_secret())
+                });
+                let client = match cfg.max_memory_usage {
+                    Some(bytes) => client.with_option("max_memory_usage", bytes.to_string()),
+                    None => client,
+                };
+                // Applied after user settings so `--setting readonly=0` can't bypass it.
+                // `readonly=2` still allows the per-query settings above.
+                let client = if cfg.readonly {
+                    client.with_option("readonly", READONLY_SESSION)
                 } else {
-                    ChClient::default()
-                }
-                .with_url(url)
-                .with_user(cfg.user)
-                .with_password(cfg.password.expose_secret())
-                .with_database("system");
-                Ok::<ChClient, ClientError>(node)
+                    client
+                };
+                Ok::<Node, ClientError>(Node {
+                    url: redact_url(url),
+                    client,
+                })
             })
             .collect::<Result<Vec<_>, ClientError>>()?;
 
-        Ok(Self { nodes })
+        Ok(Self {
+            nodes,
+            node_timings: Mutex::new(Vec::new()),
+            server_versions: OnceCell::new(),
+            retries: cfg.retries,
+            retry_backoff: cfg.retry_backoff,
+            skip_unreachable: cfg.skip_unreachable,
+            skipped_nodes: Mutex::new(Vec::new()),
+            timeout: cfg.timeout,
+        })
+    }
+
+    /// Returns the ClickHouse server version of every node.
+    ///
+    /// The versions are queried lazily on the first call and cached afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError` if the version query fails on any node.
+    pub async fn server_versions(&self) -> Result<&[ServerVersion], ClientError> {
+        let versions = self
+            .server_versions
+            .get_or_try_init(|| async {
+                let (tx, mut rx) = mpsc::channel(16);
+                let stream_task = self.execute_on_all_nodes(tx, |node| {
+                    build_query_with_params(
+                        node,
+                        "SELECT hostName() AS host, version() AS version",
+                        &[],
+                    )
+                });
+                let collect_task = async {
+                    let mut versions = Vec::new();
+                    while let Some(version) = rx.recv().await {
+                        versions.push(version);
+                    }
+                    versions
+                };
+                let (stream_result, versions) = tokio::join!(stream_task, collect_task);
+                stream_result.map(|_| versions)
+            })
+            .await?;
+
+        Ok(versions)
+    }
+
+    /// Returns nodes running a ClickHouse release older than [`MIN_SERVER_VERSION`].
+    ///
+    /// Versions that can't be parsed are treated as supported.
+    pub async fn outdated_nodes(&self) -> Result<Vec<ServerVersion>, ClientError> {
+        let outdated = self
+            .server_versions()
+            .await?
+            .iter()
+            .filter(|v| v.major_minor().is_some_and(|mm| mm < MIN_SERVER_VERSION))
+            .cloned()
+            .collect();
+
+        Ok(outdated)
+    }
+
+    /// Returns the hosts whose `system.<table>` has no `column`.
+    ///
+    /// Used to fall back gracefully on stripped-down or older servers instead of
+    /// failing the whole query.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError` if `system.columns` can't be queried on any node.
+    pub async fn nodes_missing_system_column(
+        &self,
+        table: &str,
+        column: &str,
+    ) -> Result<Vec<String>, ClientError> {
+        let sql = r#"
+            SELECT
+                hostName() AS host,
+                countIf(name = ?) AS count
+            FROM system.columns
+            WHERE database = 'system' AND table = ?
+            "#;
+        let params = vec![
+            QueryParam::String(column.to_owned()),
+            QueryParam::String(table.to_owned()),
+        ];
+
+        let (tx, mut rx) = mpsc::channel::<ColumnCount>(16);
+        let stream_task =
+            self.execute_on_all_nodes(tx, move |node| build_query_with_params(node, sql, &params));
+        let collect_task = async {
+            let mut missing = Vec::new();
+            while let Some(column) = rx.recv().await {
+                if column.count == 0 {
+                    missing.push(column.host);
+                }
+            }
+            missing
+        };
+        let (stream_result, missing) = tokio::join!(stream_task, collect_task);
+
+        stream_result.map(|_| missing)
+    }
+
+    /// Probes every node and drops the unreachable ones, returning their errors.
+    ///
+    /// Used by `--allow-partial`: later queries only run on the remaining nodes, so
+    /// their results cover part of the cluster. A node failing after the probe
+    /// still fails the query.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first node when no node is reachable.
+    pub async fn retain_reachable_nodes(&mut self) -> Result<Vec<ClientError>, ClientError> {
+        let client = &*self;
+        let probes = client.nodes.iter().map(|node| async move {
+            client
+                .with_timeout(async { Ok(node.client.query("SELECT 1").execute().await?) })
+                .await
+                .map_err(|source| ClientError::Node {
+                    url: node.url.clone(),
+                    source: Box::new(source),
+                })
+        });
+        let results = join_all(probes).await;
+
+        let mut unreachable = Vec::new();
+        let nodes = std::mem::take(&mut self.nodes);
+        for (node, result) in nodes.into_iter().zip(results) {
+            match result {
+                Ok(()) => self.nodes.push(node),
+                Err(e) => unreachable.push(e),
+            }
+        }
+        if self.nodes.is_empty() {
+            return Err(unreachable.remove(0));
+        }
+
+        Ok(unreachable)
+    }
+
+    /// Sends `count` `SELECT 1` probes to every node, one after another on a node
+    /// and concurrently across nodes, timing each round trip.
+    ///
+    /// A node's first failed probe ends its probes and is reported in its [`NodePing`],
+    /// so this never fails as a whole.
+    pub async fn ping(&self, count: u32) -> Vec<NodePing> {
+        let probes = self.nodes.iter().map(|node| async move {
+            let mut latencies = Vec::with_capacity(count as usize);
+            let mut error = None;
+            for _ in 0..count {
+                let started = Instant::now();
+                let probe = async { Ok(node.client.query("SELECT 1").execute().await?) };
+                match self.with_timeout(probe).await {
+                    Ok(()) => latencies.push(started.elapsed()),
+                    Err(e) => {
+                        error = Some(e.to_string());
+                        break;
+                    }
+                }
+            }
+            let sent = latencies.len() as u32 + u32::from(error.is_some());
+            NodePing::new(node.url.clone(), sent, &latencies, error)
+        });
+        join_all(probes).await
+    }
+
+    /// Returns a client for the same nodes whose queries are tagged with the
+    /// `log_comment` setting, to find them in `query_log` afterwards (`--iterations`).
+    pub fn with_log_comment(&self, log_comment: &str) -> Client {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| Node {
+                url: node.url.clone(),
+                client: node.client.clone().with_option("log_comment", log_comment),
+            })
+            .collect();
+        Client {
+            nodes,
+            node_timings: Mutex::new(Vec::new()),
+            server_versions: OnceCell::new(),
+            retries: self.retries,
+            retry_backoff: self.retry_backoff,
+            skip_unreachable: self.skip_unreachable,
+            skipped_nodes: Mutex::new(Vec::new()),
+            timeout: self.timeout,
+        }
+    }
+
+    /// Polls `query_log` until every node not skipped by [`Config::skip_unreachable`]
+    /// has at least `rows` finished queries tagged with `log_comment`.
+    ///
+    /// Queries reach `query_log` on the server's periodic flush, 7.5s by default.
+    /// `SYSTEM FLUSH LOGS` would need a session that is not `--readonly`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(true)` - Every node logged enough queries.
+    /// * `Ok(false)` - Some node still lacks them after [`Config::timeout`].
+    /// * `Err(ClientError)` - On query failure of some node.
+    pub async fn wait_for_query_log(
+        &self,
+        log_comment: &str,
+        rows: u64,
+    ) -> Result<bool, ClientError> {
+        let sql = format!("SELECT count() FROM query_log WHERE {TAGGED_QUERIES_WHERE}");
+        let params = [QueryParam::String(log_comment.to_owned())];
+        let skipped = self.skipped_node_urls();
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            let counts = self
+                .nodes
+                .iter()
+                .filter(|node| !skipped.contains(&node.url))
+                .map(|node| async {
+                    self.with_timeout(async {
+                        let query = build_query_with_params(&node.client, &sql, &params)?;
+                        Ok(query.fetch_one::<u64>().await?)
+                    })
+                    .await
+                    .map_err(|source| ClientError::Node {
+                        url: node.url.clone(),
+                        source: Box::new(source),
+                    })
+                });
+            if try_join_all(counts)
+                .await?
+                .iter()
+                .all(|&count| count >= rows)
+            {
+                return Ok(true);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() + QUERY_LOG_POLL_INTERVAL > deadline)
+            {
+                return Ok(false);
+            }
+            tokio::time::sleep(QUERY_LOG_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Streams the server-side duration of every finished query tagged with
+    /// `log_comment` by [`Self::with_log_comment`] since yesterday, from every node.
+    pub async fn stream_query_durations(
+        &self,
+        log_comment: &str,
+        sender: Sender<QueryDuration>,
+    ) -> Result<(), ClientError> {
+        let sql = format!("SELECT query_duration_ms FROM query_log WHERE {TAGGED_QUERIES_WHERE}");
+        let params = vec![QueryParam::String(log_comment.to_owned())];
+
+        self.execute_on_all_nodes(sender, move |node| {
+            build_query_with_params(node, &sql, &params)
+        })
+        .await
+    }
+
+    /// Runs one request to a node within [`Config::timeout`], if any.
+    async fn with_timeout<T>(
+        &self,
+        request: impl std::future::Future<Output = Result<T, ClientError>>,
+    ) -> Result<T, ClientError> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, request)
+                .await
+                .unwrap_or(Err(ClientError::Timeout(timeout))),
+            None => request.await,
+        }
+    }
+
+    /// Returns the number of ClickHouse nodes every query is executed on.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns the URLs of the nodes, in the order used by the `senders` of the
+    /// `stream_*` methods, with any embedded credentials redacted.
+    pub fn node_urls(&self) -> Vec<String> {
+        self.nodes.iter().map(|node| node.url.clone()).collect()
+    }
+
+    /// Returns the URLs of the nodes left out by [`Config::skip_unreachable`] so far,
+    /// redacted like [`Self::node_urls`].
+    pub fn skipped_node_urls(&self) -> Vec<String> {
+        self.skipped_nodes
+            .lock()
+            .map(|skipped| skipped.clone())
+            .unwrap_or_default()
+    }
+
+    /// Drains how long streaming took on each node since the previous call, in completion order.
+    pub fn take_node_timings(&self) -> Vec<Timing> {
+        self.node_timings
+            .lock()
+            .map(|mut timings| std::mem::take(&mut *timings))
+            .unwrap_or_default()
     }
 
     async fn execute_on_all_nodes<R, B>(
@@ -145,17 +731,114 @@ impl Client {
         R: Serialize + Row + Send + Deserialize<'static> + 'static,
         B: Fn(&ChClient) -> Result<ChQuery, ClientError> + Send + Sync + 'static + Clone,
     {
-        let futures = self.nodes.iter().map(|node| {
+        self.execute_on_each_node(vec![sender], build_query).await
+    }
+
+    /// Like [`Self::execute_on_all_nodes`], but node `i` streams into `senders[i % senders.len()]`,
+    /// so consumers can aggregate each node's rows separately.
+    async fn execute_on_each_node<R, B>(
+        &self,
+        senders: Vec<Sender<R>>,
+        build_query: B,
+    ) -> Result<(), ClientError>
+    where
+        R: Serialize + Row + Send + Deserialize<'static> + 'static,
+        B: Fn(&ChClient) -> Result<ChQuery, ClientError> + Send + Sync + 'static + Clone,
+    {
+        if senders.is_empty() {
+            return Err(ClientError::Send);
+        }
+
+        let skipped = &AtomicUsize::new(0);
+        let futures = self.nodes.iter().enumerate().map(|(i, node)| {
             let build_query = build_query.clone();
-            let sender = sender.clone();
-            let node = node.clone();
+            let sender = senders[i % senders.len()].clone();
+            let url = node.url.clone();
+            let node = node.client.clone();
 
             async move {
-                let q = build_query(&node)?;
-                let mut cursor = q.fetch::<R>()?;
+                let started = Instant::now();
+                let mut backpressure = Backpressure::default();
+                let mut retries = 0;
+                let result = loop {
+                    let stream = async {
+                        let q = build_query(&node)?;
+                        let mut cursor = q.fetch::<R>()?;
 
-                while let Some(row) = cursor.next().await? {
-                    sender.send(row).await.map_err(|_| ClientError::Send)?;
+                        while let Some(row) = cursor.next().await? {
+                            backpressure.rows += 1;
+                            // Сначала без ожидания, чтобы посчитать, как часто канал заполнен
+                            match sender.try_send(row) {
+                                Ok(()) => {}
+                                Err(TrySendError::Full(row)) => {
+                                    backpressure.full += 1;
+                                    let blocked = Instant::now();
+                                    sender.send(row).await.map_err(|_| ClientError::Send)?;
+                                    backpressure.blocked += blocked.elapsed();
+                                }
+                                Err(TrySendError::Closed(_)) => return Err(ClientError::Send),
+                            }
+                        }
+                        Ok(())
+                    };
+                    let result = self.with_timeout(stream).await;
+                    match result {
+                        // Повтор только до первой строки, иначе строки узла посчитались бы дважды
+                        Err(err)
+                            if err.is_transient()
+                                && retries < self.retries
+                                && backpressure.rows == 0 =>
+                        {
+                            let delay = self.retry_backoff.saturating_mul(1 << retries.min(16));
+                            retries += 1;
+                            diagnostics::note(format!(
+                                "node {url}: {err}, retry {retries} of {} in {}",
+                                self.retries,
+                                humantime::format_duration(delay)
+                            ));
+                            tokio::time::sleep(delay).await;
+                        }
+                        result => break result,
+                    }
+                };
+                let result = match result {
+                    Err(err)
+                        if self.skip_unreachable
+                            && err.is_transient()
+                            && backpressure.rows == 0 =>
+                    {
+                        skipped.fetch_add(1, Ordering::Relaxed);
+                        // Узел предупреждается один раз, хотя команда может сделать несколько запросов
+                        if let Ok(mut skipped_nodes) = self.skipped_nodes.lock() {
+                            if !skipped_nodes.contains(&url) {
+                                diagnostics::warn(format!(
+                                    "skipping unreachable node {url}: {err}"
+                                ));
+                                skipped_nodes.push(url.clone());
+                            }
+                        }
+                        return Ok(());
+                    }
+                    result => result,
+                };
+                // Имя узла в ошибке, чтобы было понятно, какой из них недоступен
+                result.map_err(|source| ClientError::Node {
+                    url: url.clone(),
+                    source: Box::new(source),
+                })?;
+                if retries > 0 {
+                    diagnostics::note(format!(
+                        "node {url}: succeeded after {retries} retr{}",
+                        if retries == 1 { "y" } else { "ies" }
+                    ));
+                }
+
+                if let Ok(mut timings) = self.node_timings.lock() {
+                    timings.push(Timing {
+                        stage: format!("stream {url}"),
+                        elapsed: started.elapsed(),
+                        backpressure: Some(backpressure),
+                    });
                 }
 
                 Ok::<(), ClientError>(())
@@ -163,6 +846,9 @@ impl Client {
         });
 
         try_join_all(futures).await?;
+        if skipped.load(Ordering::Relaxed) == self.nodes.len() {
+            return Err(ClientError::AllNodesUnreachable);
+        }
         Ok(())
     }
 
@@ -170,10 +856,14 @@ impl Client {
     ///
     /// Useful for identifying query patterns and their cumulative impact across the system.
     ///
+    /// Rows carry only the summed metrics: the impacts of a fingerprint merged across
+    /// nodes are derived from them with [`QueryLog::compute_impacts`].
+    ///
     /// # Arguments
     ///
     /// * `filter` - Filter criteria (time range, user, etc.).
-    /// * `sender` - A `Sender<QueryLog>` to push results into a stream or channel.
+    /// * `senders` - `Sender<QueryLog>`s to push results into, node `i` uses
+    ///   `senders[i % senders.len()]`. Pass one sender per node to keep their streams apart.
     ///
     /// # Errors
     ///
@@ -185,37 +875,12 @@ impl Client {
     pub async fn stream_logs_by_fingerprint(
         &self,
         filter: QueryLogFilter,
-        sender: Sender<QueryLog>,
+        senders: Vec<Sender<QueryLog>>,
     ) -> Result<(), ClientError> {
-        let (where_clause, where_params) = filter.build_where();
-        let sql = format!(
-            r#"
-            WITH
-               sum(query_duration_ms) AS total_query_duration_ms,
-               sum(read_rows) AS total_read_rows,
-               sum(read_bytes) AS total_read_bytes,
-               sum(memory_usage) AS total_memory_usage,
-               sum(ProfileEvents['UserTimeMicroseconds']) AS total_user_time_us,
-               sum(ProfileEvents['SystemTimeMicroseconds']) AS total_system_time_us,
-               sum(ProfileEvents['NetworkReceiveBytes']) AS total_network_receive_bytes,
-               sum(ProfileEvents['NetworkSendBytes']) AS total_network_send_bytes
-            SELECT
-               normalized_query_hash,
-               any(query) AS query,
-               total_read_rows * 100 + total_read_bytes * 1 AS io_impact,
-               total_network_receive_bytes * 10 + total_network_send_bytes * 10 AS network_impact,
-               total_user_time_us * 10_000 + total_system_time_us * 10_000 AS cpu_impact,
-               total_memory_usage * 10 AS memory_impact,
-               total_query_duration_ms * 1_000_000 AS time_impact,
-               io_impact + network_impact + cpu_impact + memory_impact + time_impact AS total_impact
-            FROM query_log
-            WHERE type != 'QueryStart' AND query_kind = 'Select' {where_clause}
-            GROUP BY normalized_query_hash
-            "#,
-        );
+        let (sql, params) = logs_by_fingerprint_sql(&filter);
 
-        self.execute_on_all_nodes(sender, move |node| {
-            build_query_with_params(node, &sql, &where_params)
+        self.execute_on_each_node(senders, move |node| {
+            build_query_with_params(node, &sql, &params)
         })
         .await
     }
@@ -230,7 +895,9 @@ impl Client {
     /// # Arguments
     ///
     /// * `fingerprint` — The `normalized_query_hash` of the query group to inspect.
-    /// * `filter` — Optional additional filtering (e.g., time range, user).
+    /// * `filter` — Optional additional filtering (e.g., time range, user). Its
+    ///   `truncate_query_bytes` bounds the query text selected, so a huge query is
+    ///   never transferred in full.
     /// * `sender` — A `Sender<QueryLogExtended>` to stream the result.
     ///
     /// # Returns
@@ -247,35 +914,10 @@ impl Client {
         filter: QueryLogFilter,
         sender: Sender<QueryLogExtended>,
     ) -> Result<(), ClientError> {
-        let (where_clause, where_params) = filter.build_where();
-
-        let sql = format!(
-            r#"
-            SELECT
-               normalized_query_hash,
-               any(query) AS query,
-               max(event_time) AS max_event_time,
-               min(event_time) AS min_event_time,
-               sum(query_duration_ms) AS total_query_duration_ms,
-               sum(read_rows) AS total_read_rows,
-               sum(read_bytes) AS total_read_bytes,
-               sum(memory_usage) AS total_memory_usage,
-               sum(ProfileEvents['UserTimeMicroseconds']) AS total_user_time_us,
-               sum(ProfileEvents['SystemTimeMicroseconds']) AS total_system_time_us,
-               sum(ProfileEvents['NetworkReceiveBytes']) AS total_network_receive_bytes,
-               sum(ProfileEvents['NetworkSendBytes']) AS total_network_send_bytes,
-               groupUniqArray(user) AS users,
-               arrayDistinct(arrayFlatten(groupArray(databases))) AS databases,
-               arrayDistinct(arrayFlatten(groupArray(tables))) AS tables
-            FROM query_log
-            WHERE type != 'QueryStart' AND query_kind = 'Select'
-              AND normalized_query_hash = {fingerprint} {where_clause}
-            GROUP BY normalized_query_hash
-            "#,
-        );
+        let (sql, params) = log_by_fingerprint_sql(fingerprint, &filter);
 
         self.execute_on_all_nodes(sender, move |node| {
-            build_query_with_params(node, &sql, &where_params)
+            build_query_with_params(node, &sql, &params)
         })
         .await
     }
@@ -291,7 +933,8 @@ impl Client {
     /// # Arguments
     ///
     /// * `filter` - Filter criteria to restrict the aggregation scope (e.g., time range, user).
-    /// * `sender` - A `Sender<QueryLogTotal>` to deliver the aggregated result to consumers.
+    /// * `senders` - `Sender<QueryLogTotal>`s to deliver each node's total to, node `i` uses
+    ///   `senders[i % senders.len()]`. Pass one sender per node to compare them.
     ///
     /// # Returns
     ///
@@ -304,31 +947,34 @@ impl Client {
     pub async fn stream_logs_total(
         &self,
         filter: QueryLogFilter,
-        sender: Sender<QueryLogTotal>,
+        senders: Vec<Sender<QueryLogTotal>>,
     ) -> Result<(), ClientError> {
         let (where_clause, where_params) = filter.build_where();
-        let sql = format!(
-            r#"
-            WITH
-               sum(query_duration_ms) AS total_query_duration_ms,
-               sum(read_rows) AS total_read_rows,
-               sum(read_bytes) AS total_read_bytes,
-               sum(memory_usage) AS total_memory_usage,
-               sum(ProfileEvents['UserTimeMicroseconds']) AS total_user_time_us,
-               sum(ProfileEvents['SystemTimeMicroseconds']) AS total_system_time_us,
-               sum(ProfileEvents['NetworkReceiveBytes']) AS total_network_receive_bytes,
-               sum(ProfileEvents['NetworkSendBytes']) AS total_network_send_bytes
-            SELECT
-               count() AS queries_count,
-               total_read_rows * 100 + total_read_bytes * 1 AS io_impact,
-               total_network_receive_bytes * 10 + total_network_send_bytes * 10 AS network_impact,
-               total_user_time_us * 10_000 + total_system_time_us * 10_000 AS cpu_impact,
-               total_memory_usage * 10 AS memory_impact,
-               total_query_duration_ms * 1_000_000 AS time_impact,
-               io_impact + network_impact + cpu_impact + memory_impact + time_impact AS total_impact
-            FROM query_log
-            WHERE type != 'QueryStart' AND query_kind = 'Select' {where_clause}
-            "#,
+        let sql = total_sql("", &filter.impact_columns(), &where_clause, "");
+
+        self.execute_on_each_node(senders, move |node| {
+            build_query_with_params(node, &sql, &where_params)
+        })
+        .await
+    }
+
+    /// Like [`Self::stream_logs_total`] with a single sender, but streams one total per
+    /// `bucket` of `event_time` and node, ordered by bucket.
+    ///
+    /// Buckets without matching queries are not returned. Buckets start in the server
+    /// timezone of each node.
+    pub async fn stream_logs_total_by(
+        &self,
+        filter: QueryLogFilter,
+        bucket: TimeBucket,
+        sender: Sender<QueryLogTotalBucket>,
+    ) -> Result<(), ClientError> {
+        let (where_clause, where_params) = filter.build_where();
+        let sql = total_sql(
+            &format!("{}(event_time) AS bucket,", bucket.sql_function()),
+            &filter.impact_columns(),
+            &where_clause,
+            "GROUP BY bucket ORDER BY bucket",
         );
 
         self.execute_on_all_nodes(sender, move |node| {
@@ -343,9 +989,16 @@ impl Client {
     ///
     /// # Arguments
     ///
-    /// * `filter` - Filter criteria (e.g. time range, minimum count).
+    /// * `filter` - Filter criteria (e.g. time range, error codes).
+    /// * `limit` - Maximum number of codes, by count, each node returns, or `None` for all.
+    /// * `with_message` - Whether to select `last_error_message`. Without it,
+    ///   [`Error::error_message`] is empty, for servers that don't expose the column.
     /// * `sender` - A `Sender<Error>` to stream the results.
     ///
+    /// Each node only returns its own top `limit` codes, so the ranking merged across
+    /// nodes is approximate: a code just below the limit on every node is missed, and
+    /// merged counts only include the nodes where the code made it into the top.
+    ///
     /// # Errors
     ///
     /// Returns `ClientError` for query or channel failures.
@@ -356,33 +1009,233 @@ impl Client {
     pub async fn stream_error_by_code(
         &self,
         filter: ErrorFilter,
+        limit: Option<usize>,
+        with_message: bool,
+        with_first_seen: bool,
         sender: Sender<Error>,
     ) -> Result<(), ClientError> {
-        let (where_clause, where_params) = filter.build_where();
-        let (having_clause, having_params) = filter.build_having();
+        let (sql, params) = error_by_code_sql(&filter, limit, with_message, with_first_seen);
+
+        self.execute_on_all_nodes(sender, move |node| {
+            build_query_with_params(node, &sql, &params)
+        })
+        .await
+    }
+
+    /// Streams the oldest retained `event_time` of `system.query_log` on each node.
+    ///
+    /// Used as a preflight check to detect when the requested time window predates
+    /// the data kept by the `query_log` TTL. Nodes with an empty `query_log` send nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - A `Sender<QueryLogRetention>` to stream the results.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError` for query or channel failures.
+    pub async fn stream_query_log_retention(
+        &self,
+        sender: Sender<QueryLogRetention>,
+    ) -> Result<(), ClientError> {
+        let sql = r#"
+            SELECT
+                hostName() AS host,
+                min(event_time) AS min_event_time
+            FROM query_log
+            HAVING count() > 0
+            "#;
+
+        self.execute_on_all_nodes(sender, move |node| build_query_with_params(node, sql, &[]))
+            .await
+    }
+
+    /// Streams the merges and mutations in progress from `system.merges` of each node,
+    /// tagged with the node host name.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - A `Sender<Merge>` to stream the results.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError` for query or channel failures.
+    pub async fn stream_merges(&self, sender: Sender<Merge>) -> Result<(), ClientError> {
+        // Типы колонок менялись между версиями, приводим явно
+        let sql = r#"
+            SELECT
+                hostName() AS host,
+                database,
+                table,
+                elapsed,
+                progress,
+                toUInt64(num_parts) AS num_parts,
+                toUInt64(total_size_bytes_compressed) AS total_size_bytes_compressed,
+                toUInt64(memory_usage) AS memory_usage,
+                is_mutation
+            FROM system.merges
+            "#;
+
+        self.execute_on_all_nodes(sender, move |node| build_query_with_params(node, sql, &[]))
+            .await
+    }
+
+    /// Streams the unfinished mutations from `system.mutations` of each node,
+    /// tagged with the node host name.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - Conditions on the failure and age of the mutations.
+    /// * `sender` - A `Sender<Mutation>` to stream the results.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError` for query or channel failures.
+    pub async fn stream_mutations(
+        &self,
+        filter: MutationFilter,
+        sender: Sender<Mutation>,
+    ) -> Result<(), ClientError> {
+        let (where_clause, params) = filter.build_where();
         let sql = format!(
             r#"
             SELECT
-                code,
-                any(name)        AS name,
-                sum(value)       AS count,
-                max(last_error_time)    AS last_error_time,
-                any(last_error_message) AS error_message
-            FROM system.errors
-            WHERE 1 = 1
+                hostName() AS host,
+                database,
+                table,
+                mutation_id,
+                create_time,
+                toUInt64(greatest(parts_to_do, 0)) AS parts_to_do,
+                latest_fail_reason
+            FROM system.mutations
+            WHERE is_done = 0
               {where_clause}
-            GROUP BY code
-            HAVING 1 = 1
-              {having_clause}
-            "#,
+            "#
         );
-        let params = [where_params, having_params].concat();
 
         self.execute_on_all_nodes(sender, move |node| {
             build_query_with_params(node, &sql, &params)
         })
         .await
     }
+
+    /// Streams the state of every replicated table from `system.replicas` of each node,
+    /// tagged with the node host name.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - A `Sender<Replica>` to stream the results.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError` for query or channel failures.
+    pub async fn stream_replicas(&self, sender: Sender<Replica>) -> Result<(), ClientError> {
+        let sql = r#"
+            SELECT
+                hostName() AS host,
+                database,
+                table,
+                is_leader,
+                is_readonly,
+                toUInt64(absolute_delay) AS absolute_delay,
+                toUInt64(queue_size) AS queue_size,
+                toUInt64(inserts_in_queue) AS inserts_in_queue
+            FROM system.replicas
+            "#;
+
+        self.execute_on_all_nodes(sender, move |node| build_query_with_params(node, sql, &[]))
+            .await
+    }
+
+    /// Streams the storage of every table from the active parts in `system.parts`
+    /// of each node, one row per table and node.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - Databases and tables to include.
+    /// * `sender` - A `Sender<Part>` to stream the results.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError` for query or channel failures.
+    pub async fn stream_parts(
+        &self,
+        filter: PartFilter,
+        sender: Sender<Part>,
+    ) -> Result<(), ClientError> {
+        let (where_clause, params) = filter.build_where();
+        let sql = format!(
+            r#"
+            SELECT
+                database,
+                table,
+                count() AS parts,
+                sum(rows) AS rows,
+                sum(data_compressed_bytes) AS bytes_compressed,
+                sum(data_uncompressed_bytes) AS bytes_uncompressed,
+                if(bytes_compressed = 0, 0, bytes_uncompressed / bytes_compressed) AS compression_ratio
+            FROM system.parts
+            WHERE active
+              {where_clause}
+            GROUP BY database, table
+            "#
+        );
+
+        self.execute_on_all_nodes(sender, move |node| {
+            build_query_with_params(node, &sql, &params)
+        })
+        .await
+    }
+
+    /// Streams every setting from `system.settings` of each node, tagged with the node host name.
+    ///
+    /// Used to detect configuration drift between cluster nodes.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - A `Sender<Setting>` to stream the results.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError` for query or channel failures.
+    pub async fn stream_settings(&self, sender: Sender<Setting>) -> Result<(), ClientError> {
+        let sql = r#"
+            SELECT
+                hostName() AS host,
+                name,
+                value
+            FROM system.settings
+            "#;
+
+        self.execute_on_all_nodes(sender, move |node| build_query_with_params(node, sql, &[]))
+            .await
+    }
+}
+
+/// Checks that `name` is safe to interpolate into SQL as an identifier.
+///
+/// Values should be bound as query parameters whenever possible. This is only
+/// for identifiers (table, database, cluster names) that ClickHouse cannot take
+/// as a parameter. Accepts `[A-Za-z0-9_]+`, optionally qualified with a single dot
+/// as `database.table`.
+///
+/// # Errors
+///
+/// Returns `ClientError::InvalidIdentifier` for anything else, before any SQL is built.
+pub fn validate_identifier(name: &str) -> Result<(), ClientError> {
+    let is_valid_part = |part: &str| {
+        !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+    };
+
+    let valid = match name.split_once('.') {
+        Some((database, table)) => is_valid_part(database) && is_valid_part(table),
+        None => is_valid_part(name),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(ClientError::InvalidIdentifier(name.to_owned()))
+    }
 }
 
 fn build_query_with_params(
@@ -392,7 +1245,499 @@ fn build_query_with_params(
 ) -> Result<ChQuery, ClientError> {
     let mut query = node.query(sql);
     for param in params {
-        query = query.bind(param.to_sql_string()?);
+        query = param.bind(query)?;
     }
     Ok(query)
 }
+
+// SQL метрик по отпечаткам: параметры WHERE, затем HAVING
+fn logs_by_fingerprint_sql(filter: &QueryLogFilter) -> (String, Vec<QueryParam>) {
+    let (where_clause, where_params) = filter.build_where();
+    let (having_clause, having_params) = filter.build_having();
+    let normalized_query = filter.normalized_query_expr();
+    let sql = format!(
+        r#"
+        WITH
+           sum(query_duration_ms) AS total_query_duration_ms,
+           sum(read_rows) AS total_read_rows,
+           sum(read_bytes) AS total_read_bytes,
+           sum(memory_usage) AS total_memory_usage,
+           sum(ProfileEvents['UserTimeMicroseconds']) AS total_user_time_us,
+           sum(ProfileEvents['SystemTimeMicroseconds']) AS total_system_time_us,
+           sum(ProfileEvents['NetworkReceiveBytes']) AS total_network_receive_bytes,
+           sum(ProfileEvents['NetworkSendBytes']) AS total_network_send_bytes
+        SELECT
+           normalized_query_hash,
+           any(query) AS query,
+           {normalized_query} AS normalized_query,
+           sum(result_rows) AS total_result_rows,
+           sum(result_bytes) AS total_result_bytes,
+           groupUniqArray(user) AS users,
+           arrayDistinct(arrayFlatten(groupArray(databases))) AS databases,
+           arrayDistinct(arrayFlatten(groupArray(tables))) AS tables,
+           min(event_time) AS min_event_time,
+           max(event_time) AS max_event_time,
+           count() AS queries_count,
+           total_query_duration_ms,
+           total_read_rows,
+           total_read_bytes,
+           total_memory_usage,
+           total_user_time_us,
+           total_system_time_us,
+           total_network_receive_bytes,
+           total_network_send_bytes,
+           anyIf(exception_code, exception_code != 0) AS exception_code
+        FROM query_log
+        WHERE type != 'QueryStart' AND query_kind = 'Select' {where_clause}
+        GROUP BY normalized_query_hash
+        HAVING 1 = 1 {having_clause}
+        "#,
+    );
+    let params = [where_params, having_params].concat();
+    (sql, params)
+}
+
+// SQL ошибок по кодам: параметры WHERE, HAVING и LIMIT
+fn error_by_code_sql(
+    filter: &ErrorFilter,
+    limit: Option<usize>,
+    with_message: bool,
+    with_first_seen: bool,
+) -> (String, Vec<QueryParam>) {
+    let (where_clause, where_params) = filter.build_where();
+    let (having_clause, having_params) = filter.build_having();
+    let error_message = if with_message {
+        "any(last_error_message)"
+    } else {
+        "''"
+    };
+    let first_error_time = if with_first_seen {
+        "toNullable(min(first_error_time))"
+    } else {
+        "CAST(NULL AS Nullable(DateTime))"
+    };
+    let sql = format!(
+        r#"
+        SELECT
+            code,
+            any(name)        AS name,
+            sum(value)       AS count,
+            {first_error_time} AS first_error_time,
+            max(last_error_time)    AS last_error_time,
+            {error_message} AS error_message
+        FROM system.errors
+        WHERE 1 = 1
+          {where_clause}
+        GROUP BY code
+        HAVING 1 = 1
+          {having_clause}
+        ORDER BY count DESC, code
+        {limit_clause}
+        "#,
+        limit_clause = if limit.is_some() { "LIMIT ?" } else { "" },
+    );
+    // Placeholders of WHERE, HAVING and LIMIT in the order of the SQL text.
+    let limit_params: Vec<_> = limit
+        .map(|l| QueryParam::UInt64(l as u64))
+        .into_iter()
+        .collect();
+    let params = [where_params, having_params, limit_params].concat();
+    (sql, params)
+}
+
+// SQL детализации одного отпечатка; сам отпечаток передаётся параметром
+fn log_by_fingerprint_sql(fingerprint: u64, filter: &QueryLogFilter) -> (String, Vec<QueryParam>) {
+    let (where_clause, where_params) = filter.build_where();
+    let normalized_query = match filter.normalize_query {
+        true => "normalizeQuery(sample_query)",
+        false => "''",
+    };
+    let (query, normalized_query, query_truncated, truncate_params) =
+        match filter.truncate_query_bytes {
+            Some(limit) => (
+                "toValidUTF8(substring(sample_query, 1, ?))".to_string(),
+                format!("toValidUTF8(substring({normalized_query}, 1, ?))"),
+                "query_bytes > ?",
+                vec![QueryParam::UInt64(limit); 3],
+            ),
+            None => (
+                "sample_query".to_string(),
+                normalized_query.to_string(),
+                "false",
+                Vec::new(),
+            ),
+        };
+
+    // The output alias `query` shadows the column, so `sample_query` qualifies it
+    let sql = format!(
+        r#"
+        WITH any(query_log.query) AS sample_query
+        SELECT
+           normalized_query_hash,
+           {query} AS query,
+           {normalized_query} AS normalized_query,
+           length(sample_query) AS query_bytes,
+           {query_truncated} AS query_truncated,
+           max(event_time) AS max_event_time,
+           min(event_time) AS min_event_time,
+           sum(query_duration_ms) AS total_query_duration_ms,
+           sum(read_rows) AS total_read_rows,
+           sum(read_bytes) AS total_read_bytes,
+           sum(result_rows) AS total_result_rows,
+           sum(result_bytes) AS total_result_bytes,
+           sum(memory_usage) AS total_memory_usage,
+           sum(ProfileEvents['UserTimeMicroseconds']) AS total_user_time_us,
+           sum(ProfileEvents['SystemTimeMicroseconds']) AS total_system_time_us,
+           sum(ProfileEvents['NetworkReceiveBytes']) AS total_network_receive_bytes,
+           sum(ProfileEvents['NetworkSendBytes']) AS total_network_send_bytes,
+           groupUniqArray(user) AS users,
+           arrayDistinct(arrayFlatten(groupArray(databases))) AS databases,
+           arrayDistinct(arrayFlatten(groupArray(tables))) AS tables,
+           argMax(query_id, query_duration_ms) AS sample_query_id,
+           max(query_duration_ms) AS sample_query_duration_ms,
+           anyIf(exception_code, exception_code != 0) AS exception_code
+        FROM query_log
+        WHERE type != 'QueryStart' AND query_kind = 'Select'
+          AND normalized_query_hash = ? {where_clause}
+        GROUP BY normalized_query_hash
+        "#,
+    );
+    // The truncation placeholders come first in the SQL text, then the fingerprint
+    // and the filter ones.
+    let params = [
+        truncate_params,
+        vec![QueryParam::UInt64(fingerprint)],
+        where_params,
+    ]
+    .concat();
+    (sql, params)
+}
+
+// SQL итогов по query_log, `bucket_column` и `group_by` добавляют разбивку по времени
+fn total_sql(
+    bucket_column: &str,
+    impact_columns: &str,
+    where_clause: &str,
+    group_by: &str,
+) -> String {
+    format!(
+        r#"
+        WITH
+           sum(query_duration_ms) AS total_query_duration_ms,
+           sum(read_rows) AS total_read_rows,
+           sum(read_bytes) AS total_read_bytes,
+           sum(memory_usage) AS total_memory_usage,
+           sum(ProfileEvents['UserTimeMicroseconds']) AS total_user_time_us,
+           sum(ProfileEvents['SystemTimeMicroseconds']) AS total_system_time_us,
+           sum(ProfileEvents['NetworkReceiveBytes']) AS total_network_receive_bytes,
+           sum(ProfileEvents['NetworkSendBytes']) AS total_network_send_bytes
+        SELECT
+           {bucket_column}
+           count() AS queries_count,
+           {impact_columns},
+           io_impact + network_impact + cpu_impact + memory_impact + time_impact AS total_impact,
+           total_user_time_us + total_system_time_us AS total_cpu_time_us,
+           total_network_receive_bytes + total_network_send_bytes AS total_network_bytes
+        FROM query_log
+        WHERE type != 'QueryStart' AND query_kind = 'Select' {where_clause}
+        {group_by}
+        "#,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filter::tests::query_log_filter;
+    use std::sync::Arc;
+
+    fn new_client(urls: &[&str]) -> Result<Client, ClientError> {
+        let urls: Vec<String> = urls.iter().map(|url| url.to_string()).collect();
+        Client::new(Config {
+            urls: &urls,
+            user: "default",
+            password: &secrecy::SecretString::from("secret"),
+            danger_accept_invalid_certs: false,
+            tls_min_version: TlsVersion::default(),
+            tls_server_name: None,
+            settings: &[],
+            headers: &[],
+            readonly: false,
+            max_memory_usage: None,
+            retries: 0,
+            retry_backoff: Duration::ZERO,
+            skip_unreachable: false,
+            timeout: None,
+        })
+    }
+
+    fn parse(url: &str) -> (String, Option<String>, bool) {
+        let node = NodeUrl::parse(url).unwrap();
+        (node.base, node.database, node.skip_verify)
+    }
+
+    #[test]
+    fn node_url_shapes() {
+        let http = |base: &str| (base.to_string(), None, false);
+        assert_eq!(parse("h:8123"), http("http://h:8123"));
+        assert_eq!(parse("http://h:8123"), http("http://h:8123"));
+        assert_eq!(parse("http://h:8123/"), http("http://h:8123"));
+        assert_eq!(parse("HTTP://h:8123"), http("http://h:8123"));
+        assert_eq!(parse("https://h:8443"), http("https://h:8443"));
+        assert_eq!(parse("h:8443?secure=true"), http("https://h:8443"));
+        assert_eq!(parse("http://h:8443?secure=1"), http("https://h:8443"));
+        assert_eq!(
+            parse("h:8123/logs"),
+            ("http://h:8123".to_string(), Some("logs".to_string()), false)
+        );
+        assert_eq!(
+            parse("https://h:8443/mydb?secure=true&skip_verify=true"),
+            ("https://h:8443".to_string(), Some("mydb".to_string()), true)
+        );
+        assert_eq!(
+            parse("https://user:s3cr3t@h:8443"),
+            http("https://user:s3cr3t@h:8443")
+        );
+    }
+
+    #[test]
+    fn node_url_rejects() {
+        for url in [
+            "clickhouse://h:9000",
+            "tcp://h:9000",
+            "native://h:9000",
+            "TCP://h:9000",
+            "http://h:8123/db/table",
+            "http://h:8123/db.table",
+            "http://h:8123/my-db",
+            "http://h:8123?compress=true",
+            "http://h:8123?secure=yes",
+        ] {
+            assert!(
+                matches!(NodeUrl::parse(url), Err(ClientError::InvalidUrl { .. })),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn identifiers_are_plain_or_qualified_once() {
+        for name in [
+            "system",
+            "query_log",
+            "_tmp1",
+            "DB_2",
+            "system.query_log",
+            "a.b",
+        ] {
+            assert!(validate_identifier(name).is_ok(), "{name}");
+        }
+        for name in [
+            "",
+            ".",
+            "a.",
+            ".b",
+            "a.b.c",
+            "my-db",
+            "db table",
+            "db;DROP",
+            "`db`",
+            "\"db\"",
+            "таблица",
+        ] {
+            assert!(
+                matches!(validate_identifier(name), Err(ClientError::InvalidIdentifier(n)) if n == name),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn equivalent_urls_are_one_node() {
+        let client = new_client(&["h:8123", "http://h:8123", "http://h:8123/"]).unwrap();
+        assert_eq!(client.node_count(), 1);
+
+        // Другая база на том же сервере — отдельный узел
+        let client = new_client(&["http://h:8123", "http://h:8123/logs"]).unwrap();
+        assert_eq!(client.node_count(), 2);
+    }
+
+    #[test]
+    fn url_credentials_are_masked_in_nodes_and_errors() {
+        let client = new_client(&["https://user:s3cr3t@h:8443"]).unwrap();
+        assert_eq!(client.node_urls(), ["https://[REDACTED]@h:8443"]);
+
+        let Err(invalid) = new_client(&["https://user:s3cr3t@h:8443/a/b"]) else {
+            panic!("a multi-segment path is rejected");
+        };
+        let node = ClientError::Node {
+            url: client.node_urls().remove(0),
+            source: Box::new(ClientError::Timeout(Duration::from_secs(1))),
+        };
+        for error in [invalid.to_string(), node.to_string()] {
+            assert!(error.contains("[REDACTED]@h:8443"), "{error}");
+            assert!(!error.contains("s3cr3t"), "{error}");
+        }
+    }
+
+    // HTTP-сервер без строк в ответах, считает полученные запросы
+    async fn counting_server() -> (u16, Arc<AtomicUsize>) {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let counter = counter.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    loop {
+                        let mut content_length = 0;
+                        let mut line = String::new();
+                        loop {
+                            line.clear();
+                            if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            if line == "\r\n" {
+                                break;
+                            }
+                            if let Some((name, value)) = line.split_once(':') {
+                                if name.eq_ignore_ascii_case("content-length") {
+                                    content_length = value.trim().parse().unwrap();
+                                }
+                            }
+                        }
+                        let mut body = vec![0; content_length];
+                        stream.read_exact(&mut body).await.unwrap();
+                        counter.fetch_add(1, Ordering::Relaxed);
+                        let response = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+                        stream
+                            .get_mut()
+                            .write_all(response.as_bytes())
+                            .await
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        (port, requests)
+    }
+
+    #[tokio::test]
+    async fn identical_urls_query_the_node_once() {
+        let (port, requests) = counting_server().await;
+        let url = format!("http://127.0.0.1:{port}");
+        let client = new_client(&[&url, &url]).unwrap();
+        assert_eq!(client.node_count(), 1);
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        client.stream_query_durations("test", tx).await.unwrap();
+        assert!(rx.recv().await.is_none());
+        // Каждый запрос вернул бы все строки узла: второй удвоил бы счётчики
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+
+        // Другая база на том же сервере — отдельный узел со своим запросом
+        let client = new_client(&[&url, &format!("{url}/logs")]).unwrap();
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        client.stream_query_durations("test", tx).await.unwrap();
+        assert_eq!(requests.load(Ordering::Relaxed), 3);
+    }
+
+    // Число плейсхолдеров должно совпадать с числом параметров
+    fn placeholders(sql: &str) -> usize {
+        sql.matches('?').count()
+    }
+
+    #[test]
+    fn inspect_binds_the_fingerprint() {
+        let fingerprint = 12_345_678_901_234_567_890;
+        let mut filter = query_log_filter();
+
+        let (sql, params) = log_by_fingerprint_sql(fingerprint, &filter);
+        assert!(sql.contains("normalized_query_hash = ?"));
+        assert!(!sql.contains(&fingerprint.to_string()));
+        assert_eq!(params, [QueryParam::UInt64(fingerprint)]);
+        assert_eq!(placeholders(&sql), params.len());
+
+        // Усечение текста идёт в SQL раньше отпечатка, фильтры — после
+        filter.truncate_query_bytes = Some(100);
+        filter.users = vec!["alice".to_string()];
+        let (sql, params) = log_by_fingerprint_sql(fingerprint, &filter);
+        assert!(!sql.contains(&fingerprint.to_string()));
+        assert_eq!(
+            params,
+            [
+                QueryParam::UInt64(100),
+                QueryParam::UInt64(100),
+                QueryParam::UInt64(100),
+                QueryParam::UInt64(fingerprint),
+                QueryParam::StringArray(vec!["alice".to_string()]),
+            ]
+        );
+        assert_eq!(placeholders(&sql), params.len());
+    }
+
+    #[test]
+    fn where_having_and_limit_params_keep_sql_order() {
+        let filter = ErrorFilter {
+            last: Some(Duration::from_secs(3600)),
+            code: vec![1, 2],
+            exclude_code: vec![210],
+        };
+        let (sql, params) = error_by_code_sql(&filter, Some(5), false, true);
+        assert_eq!(placeholders(&sql), params.len());
+        let positions = [
+            "has(?, code)",
+            "NOT has(?, code)",
+            "last_error_time >=",
+            "LIMIT ?",
+        ]
+        .map(|fragment| sql.find(fragment).unwrap());
+        assert!(positions.is_sorted(), "{sql}");
+        assert!(matches!(
+            params.as_slice(),
+            [
+                QueryParam::Int32Array(code),
+                QueryParam::Int32Array(exclude_code),
+                QueryParam::DateTime(_),
+                QueryParam::UInt64(5),
+            ] if code == &[1, 2] && exclude_code == &[210]
+        ));
+
+        let (sql, params) = error_by_code_sql(
+            &ErrorFilter {
+                last: None,
+                ..filter
+            },
+            None,
+            true,
+            false,
+        );
+        assert!(!sql.contains("LIMIT"));
+        assert_eq!(placeholders(&sql), params.len());
+
+        let filter = QueryLogFilter {
+            users: vec!["alice".to_string()],
+            min_distinct_users: Some(2),
+            ..query_log_filter()
+        };
+        let (sql, params) = logs_by_fingerprint_sql(&filter);
+        assert_eq!(placeholders(&sql), params.len());
+        assert!(sql.find("has(?, user)") < sql.find("length(groupUniqArray(user)) >= ?"));
+        assert_eq!(
+            params,
+            [
+                QueryParam::StringArray(vec!["alice".to_string()]),
+                QueryParam::UInt64(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_urls_is_an_error() {
+        assert!(matches!(new_client(&[]), Err(ClientError::NoNodes)));
+    }
+}
@@ -1,7 +1,8 @@
 use crate::model;
+use clickhouse::query::Query as ChQuery;
 use std::time::Duration;
-use time::OffsetDateTime;
 use time::macros::format_description;
+use time::OffsetDateTime;
 
 #[derive(Debug, Clone)]
 pub struct QueryLogFilter {
@@ -10,102 +11,199 @@ pub struct QueryLogFilter {
     pub last: Option<Duration>,
 
     pub users: Vec<String>,
+    pub exclude_users: Vec<String>,
+    pub status: model::QueryStatus,
+    pub exception_codes: Vec<i32>,
     pub databases: Vec<String>,
     pub tables: Vec<String>,
 
     pub min_query_duration: Option<std::time::Duration>,
     pub min_read_rows: Option<u64>,
     pub min_read_data: Option<bytesize::ByteSize>,
+    /// Keep fingerprints run by at least this many distinct users, see [`Self::build_having`].
+    pub min_distinct_users: Option<usize>,
+
+    /// Also select `normalizeQuery(any(query)) AS normalized_query`, see [`Self::normalized_query_expr`].
+    pub normalize_query: bool,
+    /// Multipliers of the impact columns, see [`Self::impact_columns`].
+    pub impact_weights: model::ImpactWeights,
+    /// Select at most this many bytes of query text, only used by `inspect`.
+    pub truncate_query_bytes: Option<u64>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum QueryParam {
     DateTime(OffsetDateTime),
     UInt64(u64),
     Int32(i32),
     String(String),
+    /// Bound as a single ClickHouse array literal, e.g. `['a', 'b']`.
+    StringArray(Vec<String>),
+    /// Bound as a single ClickHouse array literal, e.g. `[1, 2]`.
+    Int32Array(Vec<i32>),
 }
 
 impl QueryParam {
-    pub fn to_sql_string(&self) -> Result<String, time::error::Format> {
-        match self {
+    /// Binds the parameter to the next `?` placeholder of `query`.
+    pub fn bind(&self, query: ChQuery) -> Result<ChQuery, time::error::Format> {
+        let query = match self {
             QueryParam::DateTime(t) => {
                 let format = format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
 
-                Ok(t.format(&format)?)
+                query.bind(t.format(&format)?)
             }
-            QueryParam::UInt64(v) => Ok(format!("{}", v)),
-            QueryParam::Int32(v) => Ok(format!("{}", v)),
-            QueryParam::String(v) => Ok(v.clone()),
-        }
+            QueryParam::UInt64(v) => query.bind(v),
+            QueryParam::Int32(v) => query.bind(v),
+            QueryParam::String(v) => query.bind(v),
+            QueryParam::StringArray(v) => query.bind(v),
+            QueryParam::Int32Array(v) => query.bind(v),
+        };
+        Ok(query)
+    }
+}
+
+/// Collects SQL conditions together with their bound parameters.
+///
+/// Each condition is pushed with exactly the parameters of its own placeholders,
+/// so the parameter order always follows the condition order.
+#[derive(Debug, Default)]
+pub struct Conditions {
+    clauses: Vec<String>,
+    params: Vec<QueryParam>,
+}
+
+impl Conditions {
+    /// Adds a condition with a single `?` placeholder.
+    pub fn push(&mut self, clause: impl Into<String>, param: QueryParam) {
+        self.clauses.push(clause.into());
+        self.params.push(param);
+    }
+
+    /// Adds a fixed condition without placeholders.
+    pub fn push_static(&mut self, clause: &'static str) {
+        self.clauses.push(clause.to_string());
+    }
+
+    /// Returns the conditions as an `AND ...` fragment and its parameters.
+    pub fn build(self) -> (String, Vec<QueryParam>) {
+        let clause = if self.clauses.is_empty() {
+            String::new()
+        } else {
+            format!("AND {}", self.clauses.join(" AND "))
+        };
+
+        (clause, self.params)
     }
 }
 
 impl QueryLogFilter {
+    /// Returns the SQL expression selected as `normalized_query`: the normalized
+    /// query text when requested, an empty string otherwise.
+    pub fn normalized_query_expr(&self) -> &'static str {
+        if self.normalize_query {
+            "normalizeQuery(any(query))"
+        } else {
+            "''"
+        }
+    }
+
+    /// Returns the `*_impact` columns of the SELECT list, weighted by `impact_weights`.
+    ///
+    /// Expects the `total_*` aliases of the query_log sums in scope.
+    pub fn impact_columns(&self) -> String {
+        self.impact_weights.sql_columns()
+    }
+
     /// Собирает SQL-фрагменты WHERE и возвращает (условие, параметры)
     pub fn build_where(&self) -> (String, Vec<QueryParam>) {
-        let mut clauses: Vec<String> = Vec::new();
-        let mut params = Vec::new();
+        let mut conditions = Conditions::default();
 
         if let Some(from) = self.from {
-            clauses.push("event_time >= toDateTime(?, 'UTC')".to_owned());
-            params.push(QueryParam::DateTime(from));
+            conditions.push(
+                "event_time >= toDateTime(?, 'UTC')",
+                QueryParam::DateTime(from),
+            );
         }
         if let Some(last) = self.last {
             let now = OffsetDateTime::now_utc();
             let threshold = now - last;
-            clauses.push("event_time >= toDateTime(?, 'UTC')".to_owned());
-            params.push(QueryParam::DateTime(threshold));
+            conditions.push(
+                "event_time >= toDateTime(?, 'UTC')",
+                QueryParam::DateTime(threshold),
+            );
         }
         if let Some(to) = self.to {
-            clauses.push("event_time < toDateTime(?, 'UTC')".to_owned());
-            params.push(QueryParam::DateTime(to));
+            conditions.push(
+                "event_time < toDateTime(?, 'UTC')",
+                QueryParam::DateTime(to),
+            );
         }
 
         if !self.users.is_empty() {
-            let placeholders = vec!["?"; self.users.len()].join(", ");
-            clauses.push(format!("user IN ({placeholders})"));
-            self.users.iter().for_each(|user| {
-                params.push(QueryParam::String(user.clone()));
-            });
+            conditions.push("has(?, user)", QueryParam::StringArray(self.users.clone()));
+        }
+        if let Some(status) = self.status.sql_condition() {
+            conditions.push_static(status);
+        }
+        if !self.exception_codes.is_empty() {
+            conditions.push(
+                "has(?, exception_code)",
+                QueryParam::Int32Array(self.exception_codes.clone()),
+            );
+        }
+        // Условия объединяются через AND, так что исключение побеждает включение
+        if !self.exclude_users.is_empty() {
+            conditions.push(
+                "NOT has(?, user)",
+                QueryParam::StringArray(self.exclude_users.clone()),
+            );
         }
         if let Some(min_read_rows) = self.min_read_rows {
-            clauses.push("read_rows >= ?".to_owned());
-            params.push(QueryParam::UInt64(min_read_rows));
+            conditions.push("read_rows >= ?", QueryParam::UInt64(min_read_rows));
         }
         if let Some(min_read_data) = self.min_read_data {
             let min_read_bytes = min_read_data.as_u64();
-            clauses.push("read_bytes >= ?".to_owned());
-            params.push(QueryParam::UInt64(min_read_bytes));
+            conditions.push("read_bytes >= ?", QueryParam::UInt64(min_read_bytes));
         }
         if let Some(min_query_duration) = self.min_query_duration {
             let min_query_duration = min_query_duration.as_millis() as u64;
-            clauses.push("query_duration_ms >= ?".to_owned());
-            params.push(QueryParam::UInt64(min_query_duration));
+            conditions.push(
+                "query_duration_ms >= ?",
+                QueryParam::UInt64(min_query_duration),
+            );
         }
 
         if !self.tables.is_empty() {
-            let placeholders = vec!["?"; self.tables.len()].join(", ");
-            clauses.push(format!("hasAny(query_log.tables, [{placeholders}])"));
-            self.tables.iter().for_each(|table| {
-                params.push(QueryParam::String(table.clone()));
-            });
+            conditions.push(
+                "hasAny(query_log.tables, ?)",
+                QueryParam::StringArray(self.tables.clone()),
+            );
         }
         if !self.databases.is_empty() {
-            let placeholders = vec!["?"; self.databases.len()].join(", ");
-            clauses.push(format!("hasAny(query_log.databases, [{placeholders}])"));
-            self.databases.iter().for_each(|database| {
-                params.push(QueryParam::String(database.clone()));
-            });
+            conditions.push(
+                "hasAny(query_log.databases, ?)",
+                QueryParam::StringArray(self.databases.clone()),
+            );
         }
 
-        let where_clause = if clauses.is_empty() {
-            String::new()
-        } else {
-            format!("AND {}", clauses.join(" AND "))
-        };
+        conditions.build()
+    }
 
-        (where_clause, params)
+    /// Собирает SQL-фрагменты HAVING и возвращает (условие, параметры)
+    ///
+    /// Distinct users are counted per node, so on several nodes this must only be
+    /// applied after merging, see [`crate::command::top_queries`].
+    pub fn build_having(&self) -> (String, Vec<QueryParam>) {
+        let mut conditions = Conditions::default();
+
+        if let Some(min_distinct_users) = self.min_distinct_users {
+            conditions.push(
+                "length(groupUniqArray(user)) >= ?",
+                QueryParam::UInt64(min_distinct_users as u64),
+            );
+        }
+
+        conditions.build()
     }
 }
 
@@ -116,11 +214,18 @@ impl From<model::QueriesFilter> for QueryLogFilter {
             to: filter.to,
             last: filter.last,
             users: filter.users,
+            exclude_users: filter.exclude_users,
+            status: filter.status,
+            exception_codes: filter.exception_codes,
             tables: filter.tables,
             databases: filter.databases,
             min_query_duration: filter.min_query_duration,
             min_read_rows: filter.min_read_rows,
             min_read_data: filter.min_read_data,
+            min_distinct_users: filter.min_distinct_users,
+            normalize_query: filter.normalize_query,
+            impact_weights: filter.impact_weights,
+            truncate_query_bytes: filter.truncate_query_bytes,
         }
     }
 }
@@ -128,16 +233,16 @@ impl From<model::QueriesFilter> for QueryLogFilter {
 #[derive(Debug, Clone)]
 pub struct ErrorFilter {
     pub last: Option<Duration>,
-    pub min_count: Option<usize>,
     pub code: Vec<i32>,
+    pub exclude_code: Vec<i32>,
 }
 
 impl From<model::ErrorsFilter> for ErrorFilter {
     fn from(args: model::ErrorsFilter) -> Self {
         Self {
             last: args.last,
-            min_count: args.min_count,
             code: args.code,
+            exclude_code: args.exclude_code,
         }
     }
 }
@@ -145,49 +250,239 @@ impl From<model::ErrorsFilter> for ErrorFilter {
 impl ErrorFilter {
     /// Собирает SQL-фрагменты WHERE и возвращает (условие, параметры)
     pub fn build_where(&self) -> (String, Vec<QueryParam>) {
-        let mut clauses: Vec<String> = Vec::new();
-        let mut params = Vec::new();
+        let mut conditions = Conditions::default();
 
         if !self.code.is_empty() {
-            let placeholders = vec!["?"; self.code.len()].join(", ");
-            clauses.push(format!("code IN ({})", placeholders));
-            self.code.iter().for_each(|code| {
-                params.push(QueryParam::Int32(*code));
-            });
+            conditions.push("has(?, code)", QueryParam::Int32Array(self.code.clone()));
+        }
+        if !self.exclude_code.is_empty() {
+            conditions.push(
+                "NOT has(?, code)",
+                QueryParam::Int32Array(self.exclude_code.clone()),
+            );
         }
 
-        let where_clause = if clauses.is_empty() {
-            String::new()
-        } else {
-            format!("AND {}", clauses.join(" AND "))
-        };
-
-        (where_clause, params)
+        conditions.build()
     }
 
     /// Собирает SQL-фрагменты HAVING и возвращает (условие, параметры)
+    ///
+    /// `last` keeps codes whose latest occurrence on the node is recent ("last seen
+    /// recently"), even if they were first seen long ago.
+    ///
+    /// The minimal count and the first-seen time (`--new-since`, from
+    /// `system.errors.first_error_time`) are not applied here: both need the totals
+    /// merged across nodes, see [`crate::analyzer::top_errors`].
     pub fn build_having(&self) -> (String, Vec<QueryParam>) {
-        let mut clauses = Vec::new();
-        let mut params = Vec::new();
-
-        if let Some(min_count) = self.min_count {
-            clauses.push("count >= ?");
-            params.push(QueryParam::UInt64(min_count as u64));
-        }
+        let mut conditions = Conditions::default();
 
         if let Some(last) = self.last {
             let now = OffsetDateTime::now_utc();
             let threshold = now - last;
-            clauses.push("last_error_time >= toDateTime(?, 'UTC')");
-            params.push(QueryParam::DateTime(threshold));
+            conditions.push(
+                "last_error_time >= toDateTime(?, 'UTC')",
+                QueryParam::DateTime(threshold),
+            );
         }
 
-        let having_clause = if clauses.is_empty() {
-            String::new()
-        } else {
-            format!("AND {}", clauses.join(" AND "))
+        conditions.build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MutationFilter {
+    pub only_stuck: bool,
+    pub min_age: Option<Duration>,
+}
+
+impl From<model::MutationsFilter> for MutationFilter {
+    fn from(args: model::MutationsFilter) -> Self {
+        Self {
+            only_stuck: args.only_stuck,
+            min_age: args.min_age,
+        }
+    }
+}
+
+impl MutationFilter {
+    /// Собирает SQL-фрагменты WHERE и возвращает (условие, параметры)
+    pub fn build_where(&self) -> (String, Vec<QueryParam>) {
+        let mut conditions = Conditions::default();
+
+        if self.only_stuck {
+            conditions.push_static("latest_fail_reason != ''");
+        }
+        if let Some(min_age) = self.min_age {
+            let threshold = OffsetDateTime::now_utc() - min_age;
+            conditions.push(
+                "create_time <= toDateTime(?, 'UTC')",
+                QueryParam::DateTime(threshold),
+            );
+        }
+
+        conditions.build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PartFilter {
+    pub databases: Vec<String>,
+    pub tables: Vec<String>,
+}
+
+impl From<model::PartsFilter> for PartFilter {
+    fn from(args: model::PartsFilter) -> Self {
+        Self {
+            databases: args.databases,
+            tables: args.tables,
+        }
+    }
+}
+
+impl PartFilter {
+    /// Собирает SQL-фрагменты WHERE и возвращает (условие, параметры)
+    pub fn build_where(&self) -> (String, Vec<QueryParam>) {
+        let mut conditions = Conditions::default();
+
+        if !self.databases.is_empty() {
+            conditions.push(
+                "has(?, database)",
+                QueryParam::StringArray(self.databases.clone()),
+            );
+        }
+        if !self.tables.is_empty() {
+            conditions.push(
+                "has(?, table)",
+                QueryParam::StringArray(self.tables.clone()),
+            );
+        }
+
+        conditions.build()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A filter without any condition.
+    pub(crate) fn query_log_filter() -> QueryLogFilter {
+        QueryLogFilter {
+            from: None,
+            to: None,
+            last: None,
+            users: Vec::new(),
+            exclude_users: Vec::new(),
+            status: model::QueryStatus::All,
+            exception_codes: Vec::new(),
+            databases: Vec::new(),
+            tables: Vec::new(),
+            min_query_duration: None,
+            min_read_rows: None,
+            min_read_data: None,
+            min_distinct_users: None,
+            normalize_query: false,
+            impact_weights: model::ImpactWeights::default(),
+            truncate_query_bytes: None,
+        }
+    }
+
+    #[test]
+    fn error_filter_includes_then_excludes_codes() {
+        let filter = ErrorFilter {
+            last: None,
+            code: vec![1, 2],
+            exclude_code: vec![210],
+        };
+        assert_eq!(
+            filter.build_where(),
+            (
+                "AND has(?, code) AND NOT has(?, code)".to_string(),
+                vec![
+                    QueryParam::Int32Array(vec![1, 2]),
+                    QueryParam::Int32Array(vec![210]),
+                ]
+            )
+        );
+
+        let filter = ErrorFilter {
+            code: Vec::new(),
+            ..filter
+        };
+        assert_eq!(
+            filter.build_where(),
+            (
+                "AND NOT has(?, code)".to_string(),
+                vec![QueryParam::Int32Array(vec![210])]
+            )
+        );
+    }
+
+    #[test]
+    fn error_filter_having_is_only_last_seen() {
+        let filter = ErrorFilter {
+            last: None,
+            code: vec![1],
+            exclude_code: Vec::new(),
+        };
+        assert_eq!(filter.build_having(), (String::new(), Vec::new()));
+
+        let before = OffsetDateTime::now_utc() - Duration::from_secs(3600);
+        let filter = ErrorFilter {
+            last: Some(Duration::from_secs(3600)),
+            ..filter
+        };
+        let (having, params) = filter.build_having();
+        assert_eq!(having, "AND last_error_time >= toDateTime(?, 'UTC')");
+        let [QueryParam::DateTime(threshold)] = params.as_slice() else {
+            panic!("unexpected params: {params:?}");
+        };
+        assert!(*threshold >= before);
+        assert!(*threshold <= OffsetDateTime::now_utc() - Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn query_log_filter_params_follow_their_placeholders() {
+        let from = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let to = from + Duration::from_secs(3600);
+        let filter = QueryLogFilter {
+            from: Some(from),
+            to: Some(to),
+            users: vec!["alice".to_string()],
+            status: model::QueryStatus::Failed,
+            min_read_rows: Some(1000),
+            min_distinct_users: Some(2),
+            ..query_log_filter()
         };
 
-        (having_clause, params)
+        // Статус не занимает плейсхолдер и не должен сдвигать параметры
+        assert_eq!(
+            filter.build_where(),
+            (
+                "AND event_time >= toDateTime(?, 'UTC') \
+                 AND event_time < toDateTime(?, 'UTC') \
+                 AND has(?, user) \
+                 AND type IN ('ExceptionBeforeStart', 'ExceptionWhileProcessing') \
+                 AND read_rows >= ?"
+                    .to_string(),
+                vec![
+                    QueryParam::DateTime(from),
+                    QueryParam::DateTime(to),
+                    QueryParam::StringArray(vec!["alice".to_string()]),
+                    QueryParam::UInt64(1000),
+                ]
+            )
+        );
+        assert_eq!(
+            filter.build_having(),
+            (
+                "AND length(groupUniqArray(user)) >= ?".to_string(),
+                vec![QueryParam::UInt64(2)]
+            )
+        );
+        assert_eq!(
+            query_log_filter().build_having(),
+            (String::new(), Vec::new())
+        );
     }
 }
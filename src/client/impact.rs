@@ -0,0 +1,334 @@
+//! Custom impact formulas (`queries --impact-expr`).
+//!
+//! An expression is a small arithmetic formula over the raw metrics of a query
+//! group, e.g. `read_bytes / query_duration_ms`. Only known identifiers, numbers,
+//! `+ - * /` and parentheses are accepted. The formula never reaches the query
+//! text: it is evaluated on the merged sums of a [`QueryLog`], see [`ImpactExpr::eval`].
+use crate::model::QueryLog;
+use std::fmt;
+use std::str::FromStr;
+
+/// A metric usable in an impact expression.
+#[derive(Debug)]
+struct Metric {
+    name: &'static str,
+    /// The sum it stands for in a [`QueryLog`].
+    value: fn(&QueryLog) -> u64,
+}
+
+// Имена метрик уникальны, указатели на функции сравнивать нельзя
+impl PartialEq for Metric {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "queries",
+        value: |log| log.queries_count,
+    },
+    Metric {
+        name: "query_duration_ms",
+        value: |log| log.total_query_duration_ms,
+    },
+    Metric {
+        name: "read_rows",
+        value: |log| log.total_read_rows,
+    },
+    Metric {
+        name: "read_bytes",
+        value: |log| log.total_read_bytes,
+    },
+    Metric {
+        name: "result_rows",
+        value: |log| log.total_result_rows,
+    },
+    Metric {
+        name: "result_bytes",
+        value: |log| log.total_result_bytes,
+    },
+    Metric {
+        name: "memory_usage",
+        value: |log| log.total_memory_usage,
+    },
+    Metric {
+        name: "user_time_us",
+        value: |log| log.total_user_time_us,
+    },
+    Metric {
+        name: "system_time_us",
+        value: |log| log.total_system_time_us,
+    },
+    Metric {
+        name: "network_receive_bytes",
+        value: |log| log.total_network_receive_bytes,
+    },
+    Metric {
+        name: "network_send_bytes",
+        value: |log| log.total_network_send_bytes,
+    },
+];
+
+/// A validated impact expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImpactExpr {
+    source: String,
+    expr: Expr,
+}
+
+impl ImpactExpr {
+    /// The expression as given by the user.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Computes the expression on the sums of `log` in floating point, as a
+    /// non-negative integer.
+    ///
+    /// Division by zero and other non-finite results count as `0`.
+    pub fn eval(&self, log: &QueryLog) -> u64 {
+        let value = self.expr.eval(log);
+        if value.is_finite() {
+            value.max(0.0) as u64
+        } else {
+            0
+        }
+    }
+}
+
+impl fmt::Display for ImpactExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for ImpactExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+        };
+        let expr = parser.expr()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected `{token}` in impact expression"));
+        }
+        Ok(Self {
+            source: s.trim().to_string(),
+            expr,
+        })
+    }
+}
+
+/// Parsed form of an impact expression, evaluated by [`ImpactExpr::eval`].
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Metric(&'static Metric),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, log: &QueryLog) -> f64 {
+        match self {
+            Expr::Number(number) => *number,
+            Expr::Metric(metric) => (metric.value)(log) as f64,
+            Expr::Neg(expr) => -expr.eval(log),
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(log), rhs.eval(log));
+                match op {
+                    '+' => lhs + rhs,
+                    '-' => lhs - rhs,
+                    '*' => lhs * rhs,
+                    _ => lhs / rhs,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(String),
+    Metric(&'static Metric),
+    Op(char),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(number) => f.write_str(number),
+            Token::Metric(metric) => f.write_str(metric.name),
+            Token::Op(op) => write!(f, "{op}"),
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '+' | '-' | '*' | '/' => {
+                tokens.push(Token::Op(c));
+                chars.next();
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                chars.next();
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                chars.next();
+            }
+            c if c.is_ascii_digit() => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_ascii_digit() || c == '.') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let number = &s[start..end];
+                number
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid number `{number}` in impact expression"))?;
+                tokens.push(Token::Number(number.to_string()));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let name = &s[start..end];
+                let metric = METRICS.iter().find(|m| m.name == name).ok_or_else(|| {
+                    let known: Vec<&str> = METRICS.iter().map(|m| m.name).collect();
+                    format!(
+                        "unknown metric `{name}` in impact expression, expected one of: {}",
+                        known.join(", ")
+                    )
+                })?;
+                tokens.push(Token::Metric(metric));
+            }
+            c => return Err(format!("unexpected character `{c}` in impact expression")),
+        }
+    }
+    Ok(tokens)
+}
+
+// Рекурсивный спуск по грамматике:
+// expr = term (('+' | '-') term)*, term = factor (('*' | '/') factor)*,
+// factor = number | metric | '(' expr ')' | '-' factor
+// Строит дерево для вычисления на стороне клиента
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.term()?;
+        while let Some(&Token::Op(op @ ('+' | '-'))) = self.peek() {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.factor()?;
+        while let Some(&Token::Op(op @ ('*' | '/'))) = self.peek() {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.factor()?));
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        let expr = match self.next().cloned() {
+            // Число уже проверено в tokenize
+            Some(Token::Number(number)) => Expr::Number(number.parse().unwrap_or_default()),
+            Some(Token::Metric(metric)) => Expr::Metric(metric),
+            Some(Token::Open) => {
+                let expr = self.expr()?;
+                if self.next() != Some(&Token::Close) {
+                    return Err("missing `)` in impact expression".to_string());
+                }
+                expr
+            }
+            Some(Token::Op('-')) => Expr::Neg(Box::new(self.factor()?)),
+            Some(token) => return Err(format!("unexpected `{token}` in impact expression")),
+            None => return Err("impact expression ends unexpectedly".to_string()),
+        };
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::tests::query_log;
+
+    fn eval(expr: &str, log: &QueryLog) -> u64 {
+        expr.parse::<ImpactExpr>().unwrap().eval(log)
+    }
+
+    #[test]
+    fn eval_follows_precedence() {
+        let mut log = query_log(1);
+        log.total_read_bytes = 10;
+        log.total_read_rows = 4;
+        log.queries_count = 2;
+        assert_eq!(eval("read_bytes + read_rows * queries", &log), 18);
+        assert_eq!(eval("(read_bytes + read_rows) * queries", &log), 28);
+        assert_eq!(eval("read_bytes / queries - -1", &log), 6);
+    }
+
+    #[test]
+    fn eval_clamps_non_finite_and_negative_to_zero() {
+        let mut log = query_log(1);
+        log.total_read_bytes = 10;
+        assert_eq!(eval("read_bytes / query_duration_ms", &log), 0);
+        assert_eq!(eval("query_duration_ms / query_duration_ms", &log), 0);
+        assert_eq!(eval("0 - read_bytes", &log), 0);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_input() {
+        for expr in [
+            "read_bytes; DROP TABLE t",
+            "unknown_metric",
+            "(read_bytes",
+            "read_bytes +",
+            "",
+        ] {
+            assert!(expr.parse::<ImpactExpr>().is_err(), "{expr}");
+        }
+    }
+}
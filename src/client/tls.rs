@@ -0,0 +1,65 @@
+//! HTTPS connector with a fixed TLS server name (`--tls-server-name`).
+//!
+//! [`hyper_tls::HttpsConnector`] always verifies the certificate against the host
+//! of the URL. Behind a load balancer reached by IP that host never matches the
+//! certificate, so this connector sends and verifies another name instead, while
+//! still connecting to the address of the URL.
+use hyper::Uri;
+use hyper_tls::MaybeHttpsStream;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioIo;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Clone)]
+pub(super) struct ServerNameConnector {
+    http: HttpConnector,
+    tls: tokio_native_tls::TlsConnector,
+    /// Name used for SNI and certificate verification of every `https://` connection.
+    server_name: String,
+}
+
+impl ServerNameConnector {
+    pub(super) fn new(
+        http: HttpConnector,
+        tls: native_tls::TlsConnector,
+        server_name: impl Into<String>,
+    ) -> Self {
+        Self {
+            http,
+            tls: tls.into(),
+            server_name: server_name.into(),
+        }
+    }
+}
+
+impl Service<Uri> for ServerNameConnector {
+    type Response = MaybeHttpsStream<TokioIo<TcpStream>>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let is_https = dst.scheme_str() == Some("https");
+        let connecting = self.http.call(dst);
+        let tls = self.tls.clone();
+        let server_name = self.server_name.clone();
+
+        Box::pin(async move {
+            let tcp = connecting.await?;
+            if !is_https {
+                return Ok(MaybeHttpsStream::Http(tcp));
+            }
+            let stream = tls.connect(&server_name, TokioIo::new(tcp)).await?;
+            Ok(MaybeHttpsStream::Https(TokioIo::new(stream)))
+        })
+    }
+}
@@ -4,6 +4,9 @@
 //!
 //! - `queries`: Analyzes heavy queries grouped by `normalized_query_hash`
 //! - `errors`: Displays top system errors grouped by error code
+//! - `health`: Combines top queries, top errors and totals into one overview
+//! - `settings-diff`: Shows settings that differ between cluster nodes
+//! - `ping`: Checks that every node answers, with its round-trip latency
 //! - `context`: Manages ClickHouse connection profiles
 //!
 //! These functions coordinate between [`cli`] (input CLI arguments), internal logic, and [`output`] (printing).
@@ -17,32 +20,179 @@
 //! 4. Renders the result via the [`output`] module
 
 use crate::analyzer;
+use crate::cache;
 use crate::cli;
 use crate::client;
 use crate::context;
+use crate::diagnostics;
+use crate::history;
 use crate::model;
 use crate::output;
+use futures::future::{join_all, try_join_all};
+use std::future::Future;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 use tokio::sync::mpsc;
 
 /// Executes the `queries` command by analyzing heavy queries in `system.query_log`.
 ///
 /// Streams log entries grouped by `normalized_query_hash` and prints top queries
-/// sorted by the selected impact metric.
+/// sorted by the selected impact metric. With `top_users`, `group_by_table` and
+/// `group_by_database` the same stream also ranks users, tables and databases
+/// by the impact of their queries.
 pub async fn top_queries(
     client: client::Client,
     req: model::TopQueriesRequest,
-) -> Result<(), String> {
-    let (tx, rx) = mpsc::channel(128);
-    let analyzer_task = analyzer::top_queries(rx, req.limit, req.sort_by);
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    let now = time::OffsetDateTime::now_utc();
+    let filter = req.filter.clone().resolved_at(now);
+    warn_on_truncated_query_log(&client, filter.start_time()).await;
+
+    // Rollups rank over every query group, not only the top `limit` ones,
+    // and the cache keeps all of them so it can be ranked again in any order.
+    let rollups = req.top_users || req.group_by_table || req.group_by_database;
+    let limit = if req.cache.is_some() || rollups {
+        usize::MAX
+    } else {
+        req.limit
+    };
+    if let Some(iterations) = req.iterations {
+        return benchmark(&client, iterations, &req.out, async |client| {
+            let mut timings = model::Timings::default();
+            fetch_top_queries(
+                client,
+                filter.clone(),
+                limit,
+                req.sort_by.clone(),
+                req.out.fingerprint_format,
+                &mut timings,
+            )
+            .await
+            .map(drop)
+        })
+        .await;
+    }
+    if req.by_node {
+        let nodes = fetch_top_queries_by_node(
+            &client,
+            filter.clone(),
+            req.limit,
+            req.sort_by.clone(),
+            timings,
+        )
+        .await?;
+        let queries: Vec<_> = nodes.iter().flat_map(|node| node.queries.clone()).collect();
+        warn_on_unavailable_profile_events(&queries);
+        note_extrapolated(&filter);
+        if req.node_column {
+            // Топ каждого узла уже содержит его лучшие `limit` пар, общий топ — среди них
+            let mut rows: Vec<_> = nodes
+                .into_iter()
+                .flat_map(|node| {
+                    node.queries.into_iter().map(move |query| model::NodeQuery {
+                        node: node.node.clone(),
+                        query,
+                    })
+                })
+                .collect();
+            rows.sort_by_key(|row| {
+                std::cmp::Reverse(analyzer::sort_value(&row.query, &req.sort_by))
+            });
+            rows.truncate(req.limit);
+            output::print_node_queries(&rows, &req.columns, req.rate, &filter, &req.out);
+            return Ok(());
+        }
+        output::print_queries_by_node(&nodes, &req.columns, req.rate, &filter, &req.out);
+        return Ok(());
+    }
+
+    let logs = fetch_top_queries(
+        &client,
+        filter.clone(),
+        limit,
+        req.sort_by.clone(),
+        req.out.fingerprint_format,
+        timings,
+    )
+    .await?;
+    if let Some(path) = &req.cache {
+        cache::write(path, &req.filter, now, &logs)?;
+    }
+    print_ranked_queries(&logs, &filter, &req);
+
+    Ok(())
+}
+
+/// Executes the `queries --use-cache` command, ranking the query groups saved
+/// by an earlier `queries --cache` run without connecting to ClickHouse.
+pub fn top_queries_cached(req: model::TopQueriesRequest) -> Result<(), crate::Error> {
+    let path = req
+        .cache
+        .as_ref()
+        .ok_or("`--use-cache` requires `--cache <path>`")?;
+    let (logs, filter) = cache::read(path, &req.filter)?;
+    print_ranked_queries(&logs, &filter, &req);
+    Ok(())
+}
 
-    let stream_task = client.stream_logs_by_fingerprint(req.filter.into(), tx);
+// Ранжирует все собранные группы запросов локально и печатает топ
+fn print_ranked_queries(
+    logs: &[model::QueryLog],
+    filter: &model::QueriesFilter,
+    req: &model::TopQueriesRequest,
+) {
+    let top_queries = analyzer::rank_queries(logs, req.limit, req.sort_by.clone());
+    let rollup_limit = req.rollup_limit.unwrap_or(req.limit);
+    let rollups = model::Rollups {
+        users: req
+            .top_users
+            .then(|| analyzer::rank_users(logs, rollup_limit, req.sort_by.clone())),
+        tables: req
+            .group_by_table
+            .then(|| analyzer::rank_tables(logs, rollup_limit, req.sort_by.clone())),
+        databases: req
+            .group_by_database
+            .then(|| analyzer::rank_databases(logs, rollup_limit, req.sort_by.clone())),
+    };
 
-    let (stream_result, top_queries) = tokio::join!(stream_task, analyzer_task);
+    warn_on_unavailable_profile_events(&top_queries);
+    note_extrapolated(filter);
+    output::print_top_queries(
+        &top_queries,
+        &rollups,
+        &req.columns,
+        req.rate,
+        filter,
+        &req.out,
+    );
+}
 
-    stream_result.map_err(|e| format!("Stream error: {e}"))?;
+/// Executes the `queries --tui` command, an interactive view of heavy queries.
+///
+/// Collects every query group once, then hands them to [`crate::tui`] which ranks
+/// them locally and drills down into a fingerprint on demand.
+#[cfg(feature = "tui")]
+pub async fn top_queries_tui(
+    client: client::Client,
+    req: model::TopQueriesRequest,
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    warn_on_truncated_query_log(&client, req.filter.start_time()).await;
 
-    output::print_top_queries(&top_queries, req.out);
+    let queries = fetch_top_queries(
+        &client,
+        req.filter.clone(),
+        usize::MAX,
+        req.sort_by.clone(),
+        req.out.fingerprint_format,
+        timings,
+    )
+    .await?;
 
+    crate::tui::run(&client, queries, req, timings).await?;
     Ok(())
 }
 
@@ -64,21 +214,90 @@ pub async fn top_queries(
 ///
 /// # Returns
 ///
-/// `Result<(), String>` indicating success or a streaming error.
+/// `Result<(), crate::Error>` indicating success or a streaming error.
 pub async fn total_queries(
     client: client::Client,
     req: model::TotalQueriesRequest,
-) -> Result<(), String> {
-    let (tx, rx) = mpsc::channel(128);
-    let analyzer_task = analyzer::total_queries(rx);
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    let filter = req.filter.resolved();
+    warn_on_truncated_query_log(&client, filter.start_time()).await;
+
+    if let Some(iterations) = req.iterations {
+        return benchmark(&client, iterations, &req.out, async |client| {
+            let mut timings = model::Timings::default();
+            fetch_total_queries(client, filter.clone(), &mut timings)
+                .await
+                .map(drop)
+        })
+        .await;
+    }
 
-    let stream_task = client.stream_logs_total(req.filter.into(), tx);
+    if let Some(factor) = req.compare_nodes {
+        if client.node_count() < 2 {
+            diagnostics::note("--compare-nodes needs several nodes, only one is configured");
+        }
+        let totals = fetch_node_totals(&client, filter.clone(), timings).await?;
+        let queried: Vec<_> = totals
+            .iter()
+            .filter(|total| total.queries_count > 0)
+            .cloned()
+            .collect();
+        warn_on_unavailable_profile_events(&queried);
+        note_extrapolated(&filter);
+        // Пропущенные узлы не отвечали, их нули не должны выглядеть выбросом
+        let skipped = client.skipped_node_urls();
+        let (urls, totals): (Vec<_>, Vec<_>) = client
+            .node_urls()
+            .into_iter()
+            .zip(totals)
+            .filter(|(url, _)| !skipped.contains(url))
+            .unzip();
+        let nodes = analyzer::compare_nodes(urls, totals, factor);
+        output::print_node_totals(&nodes, factor, &filter, &req.out);
+        return Ok(());
+    }
 
-    let (stream_result, total_queries) = tokio::join!(stream_task, analyzer_task);
+    if let Some(bucket) = req.breakdown_by {
+        let buckets = fetch_total_buckets(&client, filter.clone(), bucket, timings).await?;
+        let totals: Vec<_> = buckets.iter().map(|b| b.total.clone()).collect();
+        warn_on_unavailable_profile_events(&totals);
+        note_extrapolated(&filter);
+        output::print_total_buckets(&buckets, &filter, &req.out);
+        return Ok(());
+    }
 
-    stream_result.map_err(|e| format!("Stream error: {e}"))?;
+    let total_queries = fetch_total_queries(&client, filter.clone(), timings).await?;
 
-    output::print_total_queries(&total_queries, req.out);
+    if total_queries.queries_count > 0 {
+        warn_on_unavailable_profile_events(std::slice::from_ref(&total_queries));
+    }
+    note_extrapolated(&filter);
+    let nodes_queried = client.node_count() - client.skipped_node_urls().len();
+    output::print_total_queries(&total_queries, nodes_queried, &filter, &req.out);
+
+    if let Some(path) = &req.record {
+        let record = model::HistoryRecord {
+            recorded_at: OffsetDateTime::now_utc(),
+            total: total_queries,
+        };
+        history::append(path, &record)?;
+    }
+
+    Ok(())
+}
+
+/// Executes `total --history`: prints the latest `last` records of the history at
+/// `path` without querying ClickHouse.
+pub fn total_history(path: &Path, last: usize, out: &model::Output) -> Result<(), crate::Error> {
+    let records = history::read(path, last)?;
+    if records.is_empty() {
+        diagnostics::note(format!(
+            "history {} is empty, record runs with `total --record`",
+            path.display()
+        ));
+    }
+    output::print_history(&records, out);
 
     Ok(())
 }
@@ -102,50 +321,309 @@ pub async fn total_queries(
 ///
 /// # Returns
 ///
-/// A `Result<(), String>` indicating success or a streaming/processing error.
+/// A `Result<(), crate::Error>` indicating success or a streaming/processing error.
 pub async fn inspect_fingerprint(
     client: client::Client,
     req: model::InspectFingerprintRequest,
-) -> Result<(), String> {
-    let (tx, rx) = mpsc::channel(128);
-    let analyzer_task = analyzer::extended_query(rx);
-
-    let stream_task = client.stream_log_by_fingerprint(req.fingerprint, req.filter.into(), tx);
-
-    let (stream_result, query_extended) = tokio::join!(stream_task, analyzer_task);
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    if let Some(iterations) = req.iterations {
+        return benchmark(&client, iterations, &req.out, async |client| {
+            let mut timings = model::Timings::default();
+            fetch_query_extended(client, req.fingerprint, req.filter.clone(), &mut timings)
+                .await
+                .map(drop)
+        })
+        .await;
+    }
 
-    stream_result.map_err(|e| format!("Stream error: {e}"))?;
+    note_extrapolated(&req.filter);
+    let query_extended =
+        fetch_query_extended(&client, req.fingerprint, req.filter, timings).await?;
 
     if let Some(query_extended) = query_extended {
-        output::print_query_extended(&query_extended, req.out);
+        warn_on_unavailable_profile_events(std::slice::from_ref(&query_extended));
+        output::print_query_extended(&query_extended, req.explain_impact, &req.out);
         Ok(())
     } else {
-        let fingerprint = format!("{:#x}", req.fingerprint);
-        Err(format!("Fingerprint {fingerprint} not found"))
+        let fingerprint = req.out.fingerprint_format.format(req.fingerprint);
+        Err(format!("Fingerprint {fingerprint} not found").into())
     }
 }
 
 /// Executes the `errors` command by analyzing top errors in `system.errors`.
 ///
 /// Streams error entries grouped by error code and prints top recurring errors.
+/// With `watch`, keeps polling until interrupted, see [`watch_errors`].
 pub async fn top_errors(
     client: client::Client,
     req: model::TopErrorsRequest,
-) -> Result<(), String> {
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    if let Some(interval) = req.watch {
+        return tokio::select! {
+            result = watch_errors(&client, &req, interval) => result,
+            _ = tokio::signal::ctrl_c() => Ok(()),
+        };
+    }
+
+    let (top_errors, below_min_count) = fetch_top_errors(
+        &client,
+        req.filter.clone(),
+        req.limit,
+        req.sort_by,
+        !req.no_error_message,
+        timings,
+    )
+    .await?;
+
+    output::print_top_errors(&top_errors, below_min_count.as_ref(), &req.filter, &req.out);
+
+    Ok(())
+}
+
+/// Prints `errors` every `interval`, or with `diff` the occurrences since the
+/// previous poll, as `system.errors` counts are cumulative. Runs until an error.
+async fn watch_errors(
+    client: &client::Client,
+    req: &model::TopErrorsRequest,
+    interval: Duration,
+) -> Result<(), crate::Error> {
+    let mut watch = analyzer::ErrorWatch::default();
+    loop {
+        // Тайминги опросов не копим, цикл бесконечный
+        let mut timings = model::Timings::default();
+        let with_message = !req.no_error_message;
+        if req.diff {
+            // Все коды: иначе код, выпавший из топа, вернулся бы как новый
+            let (errors, _) = fetch_top_errors(
+                client,
+                req.filter.clone(),
+                usize::MAX,
+                req.sort_by,
+                with_message,
+                &mut timings,
+            )
+            .await?;
+            match watch.diff(errors, req.limit) {
+                Some(deltas) => output::print_error_deltas(&deltas, &req.filter, &req.out),
+                None => diagnostics::note(format!(
+                    "baseline recorded, showing new occurrences every {}",
+                    humantime::format_duration(interval)
+                )),
+            }
+        } else {
+            let (errors, below_min_count) = fetch_top_errors(
+                client,
+                req.filter.clone(),
+                req.limit,
+                req.sort_by,
+                with_message,
+                &mut timings,
+            )
+            .await?;
+            output::print_top_errors(&errors, below_min_count.as_ref(), &req.filter, &req.out);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Executes the `health` command, a one-shot overview of the cluster.
+///
+/// Runs abbreviated `queries` and `errors` analyses together with the `total` summary
+/// over a shared client, and prints them as stacked sections.
+pub async fn health(
+    client: client::Client,
+    req: model::HealthRequest,
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    let errors_filter = model::ErrorsFilter {
+        last: req.filter.last,
+        min_count: None,
+        code: Vec::new(),
+        exclude_code: Vec::new(),
+        new_since: None,
+    };
+    let filter = req.filter.resolved();
+    warn_on_truncated_query_log(&client, filter.start_time()).await;
+
+    let queries = fetch_top_queries(
+        &client,
+        filter.clone(),
+        req.limit,
+        model::QueriesSortBy::TotalImpact,
+        req.out.fingerprint_format,
+        timings,
+    )
+    .await?;
+    let (errors, _) = fetch_top_errors(
+        &client,
+        errors_filter,
+        req.limit,
+        model::ErrorsSortBy::Count,
+        true,
+        timings,
+    )
+    .await?;
+    let total = fetch_total_queries(&client, filter.clone(), timings).await?;
+
+    warn_on_unavailable_profile_events(&queries);
+    note_extrapolated(&filter);
+    output::print_health(
+        &model::Health {
+            queries,
+            errors,
+            total,
+        },
+        &filter,
+        &req.out,
+    );
+
+    Ok(())
+}
+
+/// Executes the `settings-diff` command by comparing `system.settings` across nodes.
+///
+/// Streams every setting of each node and prints the ones whose values drift between nodes.
+pub async fn settings_diff(
+    client: client::Client,
+    req: model::SettingsDiffRequest,
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    let (tx, rx) = mpsc::channel(128);
+    let analyzer_task = analyzer::settings_diff(rx, req.changed_only);
+
+    let stream_task = client.stream_settings(tx);
+
+    let (stream_result, (settings_diff, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
+
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+
+    output::print_settings_diff(&settings_diff, &req.out);
+
+    Ok(())
+}
+
+/// Executes the `merges` command, listing the merges in progress on every node.
+pub async fn merges(
+    client: client::Client,
+    req: model::MergesRequest,
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    let (tx, rx) = mpsc::channel(128);
+    let analyzer_task = analyzer::top_merges(rx, req.limit, req.sort_by);
+
+    let stream_task = client.stream_merges(tx);
+
+    let (stream_result, (merges, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
+
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+
+    output::print_merges(&merges, &req.out);
+
+    Ok(())
+}
+
+/// Executes the `mutations` command, listing the unfinished mutations on every node.
+pub async fn mutations(
+    client: client::Client,
+    req: model::MutationsRequest,
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
     let (tx, rx) = mpsc::channel(128);
-    let analyzer_task = analyzer::top_errors(rx, req.limit);
+    let analyzer_task = analyzer::top_mutations(rx, req.limit, req.sort_by);
 
-    let stream_task = client.stream_error_by_code(req.filter.into(), tx);
+    let stream_task = client.stream_mutations(req.filter.into(), tx);
 
-    let (stream_result, top_errors) = tokio::join!(stream_task, analyzer_task);
+    let (stream_result, (mutations, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
 
-    stream_result.map_err(|e| format!("Stream error: {e}"))?;
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
 
-    output::print_top_errors(&top_errors, req.out);
+    output::print_mutations(&mutations, &req.out);
 
     Ok(())
 }
 
+/// Executes the `replication` command, showing the lag of replicated tables.
+pub async fn replication(
+    client: client::Client,
+    req: model::ReplicationRequest,
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    let (tx, rx) = mpsc::channel(128);
+    let analyzer_task = analyzer::replica_lags(rx, req.max_lag);
+
+    let stream_task = client.stream_replicas(tx);
+
+    let (stream_result, (replicas, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
+
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+
+    if req.by_node {
+        output::print_replica_lags(&replicas, req.max_lag, &req.out);
+    } else {
+        let tables = analyzer::replicated_tables(&replicas);
+        output::print_replicated_tables(&tables, req.max_lag, &req.out);
+    }
+
+    Ok(())
+}
+
+/// Executes the `parts` command, showing the largest tables by storage.
+pub async fn parts(
+    client: client::Client,
+    req: model::PartsRequest,
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    let (tx, rx) = mpsc::channel(128);
+    let analyzer_task = analyzer::top_parts(rx, req.limit);
+
+    let stream_task = client.stream_parts(req.filter.into(), tx);
+
+    let (stream_result, (parts, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
+
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+
+    output::print_parts(&parts, &req.out);
+
+    Ok(())
+}
+
+/// Executes the `ping` command, probing every node with `SELECT 1`.
+///
+/// Prints the latency of every node, then fails if any of them didn't answer.
+pub async fn ping(
+    client: client::Client,
+    req: model::PingRequest,
+    timings: &mut model::Timings,
+) -> Result<(), crate::Error> {
+    let (pings, elapsed) = timed(client.ping(req.count)).await;
+    timings.record("ping", elapsed);
+
+    output::print_ping(&pings, &req.out);
+
+    let failed = pings.iter().filter(|p| p.error.is_some()).count();
+    if failed > 0 {
+        return Err(format!("{failed} of {} nodes did not answer", pings.len()).into());
+    }
+    Ok(())
+}
+
 /// Handles the `context` CLI command.
 ///
 /// This command is a wrapper around the [`mod@context`] module, providing access to
@@ -156,19 +634,20 @@ pub async fn top_errors(
 pub async fn context(
     ctx: &mut context::Context,
     command: &cli::ContextCommand,
-    out: model::OutputFormat,
-) -> Result<(), String> {
+    dry_run: bool,
+    out: &model::Output,
+) -> Result<(), crate::Error> {
     match command {
         cli::ContextCommand::ConfigPath => {
             let path = ctx.get_config_path();
-            output::print_context_config_path(path, out);
+            output::print_context_config_path(path, ctx.local_config_path(), out);
         }
-        cli::ContextCommand::List => {
-            let names = ctx.list();
+        cli::ContextCommand::List { labels } => {
+            let names = ctx.list_by_labels(labels);
             output::print_context_list(&names, out);
         }
 
-        cli::ContextCommand::Current => {
+        cli::ContextCommand::Current { .. } => {
             let active = ctx.active_profile_name();
             output::print_context_current(active, out);
         }
@@ -182,38 +661,562 @@ pub async fn context(
             output::print_context_profile(&printable, out);
         }
 
+        cli::ContextCommand::Delete { name } if dry_run => {
+            let changes = ctx
+                .plan_delete_profile(name)
+                .map_err(|e| format!("delete profile error: {e}"))?;
+            output::print_context_dry_run(&changes, out);
+        }
         cli::ContextCommand::Delete { name } => ctx
             .delete_profile(name)
             .map_err(|e| format!("delete profile error: {e}"))?,
 
         cli::ContextCommand::Set { command } => match command {
-            cli::ContextSetCommand::Current { name } => {
+            cli::ContextSetCommand::Current { name: None, .. } if dry_run => {
+                output::print_context_dry_run(&ctx.plan_unset_default(), out);
+            }
+            cli::ContextSetCommand::Current { name: None, .. } => {
+                ctx.unset_default()
+                    .map_err(|e| format!("unset current error: {}", e))?;
+            }
+            cli::ContextSetCommand::Current {
+                name: Some(name), ..
+            } if dry_run => {
+                let changes = ctx
+                    .plan_set_default(name)
+                    .map_err(|e| format!("set current error: {}", e))?;
+                output::print_context_dry_run(&changes, out);
+            }
+            cli::ContextSetCommand::Current {
+                name: Some(name), ..
+            } => {
                 ctx.set_default(name)
                     .map_err(|e| format!("set current error: {}", e))?;
             }
             cli::ContextSetCommand::Profile(args) => {
                 let user = args.user.clone();
-                let password = if args.interactive_password {
-                    let password =
-                        rpassword::prompt_password(format!("ClickHouse {user} password: "))
-                            .map_err(|e| format!("read password from prompt: {e}"))?;
-                    secrecy::SecretString::new(password.into())
+                // Нет смысла спрашивать пароль, если он не будет сохранён
+                let password = if dry_run {
+                    secrecy::SecretString::default()
+                } else if args.interactive_password {
+                    cli::prompt_password(&user)?
                 } else {
                     args.password.clone().unwrap()
                 };
-                ctx.set_profile(
-                    model::ContextProfile {
-                        user,
-                        password,
-                        urls: args.urls.clone(),
-                        accept_invalid_certificate: args.accept_invalid_certificate.clone(),
-                    },
-                    &args.name,
-                )
-                .map_err(|e| format!("set profile error: {}", e))?;
+                let profile = model::ContextProfile {
+                    user,
+                    password,
+                    urls: args.urls.clone(),
+                    accept_invalid_certificate: args.accept_invalid_certificate,
+                    tls_server_name: args.tls_server_name.clone(),
+                    labels: args.labels.iter().cloned().collect(),
+                };
+                if dry_run {
+                    let changes = ctx.plan_set_profile(&profile, &args.name);
+                    output::print_context_dry_run(&changes, out);
+                } else {
+                    ctx.set_profile(profile, &args.name)
+                        .map_err(|e| format!("set profile error: {}", e))?;
+                }
             }
         },
     }
 
     Ok(())
 }
+
+/// Runs `analyze` `iterations` times with its queries tagged by a `log_comment`,
+/// then prints the wall-clock times and the server times of the tagged queries
+/// from `query_log` instead of the results, see `--iterations`.
+async fn benchmark<F>(
+    client: &client::Client,
+    iterations: u32,
+    out: &model::Output,
+    mut analyze: F,
+) -> Result<(), crate::Error>
+where
+    F: AsyncFnMut(&client::Client) -> Result<(), crate::Error>,
+{
+    let log_comment = format!(
+        "clickcheck-iterations-{}",
+        OffsetDateTime::now_utc().unix_timestamp_nanos()
+    );
+    let tagged = client.with_log_comment(&log_comment);
+    let mut elapsed = Vec::with_capacity(iterations as usize);
+    for _ in 0..iterations {
+        let started = Instant::now();
+        analyze(&tagged).await?;
+        elapsed.push(started.elapsed());
+    }
+
+    let logged = client
+        .wait_for_query_log(&log_comment, iterations.into())
+        .await
+        .map_err(|e| crate::Error::client("Query log error", e))?;
+    let server = if logged {
+        let (tx, mut rx) = mpsc::channel::<model::QueryDuration>(128);
+        let collect_task = async {
+            let mut durations = Vec::new();
+            while let Some(row) = rx.recv().await {
+                durations.push(Duration::from_millis(row.query_duration_ms));
+            }
+            durations
+        };
+        let (stream_result, server) = tokio::join!(
+            client.stream_query_durations(&log_comment, tx),
+            collect_task
+        );
+        stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+        model::DurationStats::new(&server)
+    } else {
+        diagnostics::note(format!(
+            "server times are missing, query_log did not log {iterations} runs \
+             on every node within --timeout"
+        ));
+        None
+    };
+
+    output::print_benchmark(
+        &model::Benchmark {
+            iterations,
+            client: model::DurationStats::new(&elapsed),
+            server,
+        },
+        out,
+    );
+    Ok(())
+}
+
+/// Streams query logs grouped by fingerprint and returns the top `limit` of them.
+///
+/// `min_distinct_users` is applied in SQL on a single node and unsplit time range
+/// only: a fingerprint's users may be spread over several nodes or parts, so there
+/// it is applied after merging.
+///
+/// With `check_query_texts`, notes the returned fingerprints whose texts differ
+/// across nodes, shown in `fingerprint_format`.
+async fn fetch_top_queries(
+    client: &client::Client,
+    mut filter: model::QueriesFilter,
+    limit: usize,
+    sort_by: model::QueriesSortBy,
+    fingerprint_format: model::FingerprintFormat,
+    timings: &mut model::Timings,
+) -> Result<Vec<model::QueryLog>, crate::Error> {
+    let factor = filter.log_sampling_factor;
+    let min_distinct_users = match client.node_count() > 1 || filter.concurrency_per_node > 1 {
+        true => filter.min_distinct_users.take(),
+        false => None,
+    };
+    let analyzer_limit = match min_distinct_users {
+        Some(_) => usize::MAX,
+        None => limit,
+    };
+    let check_query_texts = filter.check_query_texts;
+    let weights = filter.impact_weights;
+    let impact_expr = filter.impact_expr.clone();
+    let (mut queries, divergences) = fetch_query_logs(client, filter, timings, |receivers| {
+        analyzer::top_queries(
+            receivers,
+            analyzer_limit,
+            sort_by,
+            check_query_texts,
+            weights,
+            impact_expr.clone(),
+        )
+    })
+    .await?;
+    if let Some(min_distinct_users) = min_distinct_users {
+        queries.retain(|query| query.users.len() >= min_distinct_users);
+        queries.truncate(limit);
+    }
+    for divergence in divergences {
+        let hash = divergence.normalized_query_hash;
+        if queries
+            .iter()
+            .any(|query| query.normalized_query_hash == hash)
+        {
+            diagnostics::note(format!(
+                "fingerprint {} has {} distinct query texts across nodes, \
+                 its normalization may collide",
+                fingerprint_format.format(hash),
+                divergence.texts
+            ));
+        }
+    }
+    if let Some(factor) = factor {
+        for query in &mut queries {
+            query.extrapolate(factor);
+            query.compute_impacts(&weights, impact_expr.as_ref());
+        }
+    }
+    Ok(queries)
+}
+
+/// Streams query logs grouped by fingerprint and returns the top `limit` of every
+/// node on its own, in node order, see `queries --by-node`.
+///
+/// `min_distinct_users` stays in SQL unless the time range is split, as each node
+/// only counts its own users here.
+async fn fetch_top_queries_by_node(
+    client: &client::Client,
+    mut filter: model::QueriesFilter,
+    limit: usize,
+    sort_by: model::QueriesSortBy,
+    timings: &mut model::Timings,
+) -> Result<Vec<model::NodeQueries>, crate::Error> {
+    let factor = filter.log_sampling_factor;
+    let min_distinct_users = match filter.concurrency_per_node > 1 {
+        true => filter.min_distinct_users.take(),
+        false => None,
+    };
+    let analyzer_limit = match min_distinct_users {
+        Some(_) => usize::MAX,
+        None => limit,
+    };
+    let weights = filter.impact_weights;
+    let impact_expr = filter.impact_expr.clone();
+    let per_node = fetch_query_logs(client, filter, timings, |receivers| {
+        join_all(receivers.into_iter().map(|receiver| {
+            analyzer::top_queries(
+                vec![receiver],
+                analyzer_limit,
+                sort_by.clone(),
+                false,
+                weights,
+                impact_expr.clone(),
+            )
+        }))
+    })
+    .await?;
+
+    let skipped = client.skipped_node_urls();
+    Ok(client
+        .node_urls()
+        .into_iter()
+        .zip(per_node)
+        .filter(|(node, _)| !skipped.contains(node))
+        .map(|(node, (mut queries, _))| {
+            if let Some(min_distinct_users) = min_distinct_users {
+                queries.retain(|query| query.users.len() >= min_distinct_users);
+                queries.truncate(limit);
+            }
+            if let Some(factor) = factor {
+                for query in &mut queries {
+                    query.extrapolate(factor);
+                    query.compute_impacts(&weights, impact_expr.as_ref());
+                }
+            }
+            model::NodeQueries { node, queries }
+        })
+        .collect())
+}
+
+/// Runs `stream` once for every part of `--concurrency-per-node`, concurrently.
+///
+/// Every part of a node streams into the same senders, so analyzers merge the parts
+/// like the rows of a single scan.
+async fn stream_split<S, F>(
+    filter: model::QueriesFilter,
+    stream: S,
+) -> Result<(), client::ClientError>
+where
+    S: Fn(model::QueriesFilter) -> F,
+    F: Future<Output = Result<(), client::ClientError>>,
+{
+    let parts = filter.split_time_range(OffsetDateTime::now_utc());
+    try_join_all(parts.into_iter().map(stream)).await?;
+    Ok(())
+}
+
+/// Streams query logs grouped by fingerprint, one channel per node, into `analyze`.
+async fn fetch_query_logs<F, A>(
+    client: &client::Client,
+    filter: model::QueriesFilter,
+    timings: &mut model::Timings,
+    analyze: A,
+) -> Result<F::Output, crate::Error>
+where
+    F: Future,
+    A: FnOnce(Vec<mpsc::Receiver<model::QueryLog>>) -> F,
+{
+    let (senders, receivers): (Vec<_>, _) = (0..client.node_count().max(1))
+        .map(|_| mpsc::channel(128))
+        .unzip();
+    let analyzer_task = analyze(receivers);
+
+    let stream_task = stream_split(filter, move |part| {
+        client.stream_logs_by_fingerprint(part.into(), senders.clone())
+    });
+
+    let (stream_result, (analyzed, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
+
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+
+    Ok(analyzed)
+}
+
+/// Streams detailed query logs of one fingerprint and merges them across nodes.
+pub(crate) async fn fetch_query_extended(
+    client: &client::Client,
+    fingerprint: u64,
+    filter: model::QueriesFilter,
+    timings: &mut model::Timings,
+) -> Result<Option<model::QueryLogExtended>, crate::Error> {
+    let factor = filter.log_sampling_factor;
+    let (tx, rx) = mpsc::channel(128);
+    let analyzer_task = analyzer::extended_query(rx);
+
+    let stream_task = stream_split(filter, move |part| {
+        client.stream_log_by_fingerprint(fingerprint, part.into(), tx.clone())
+    });
+
+    let (stream_result, (query_extended, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
+
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+
+    Ok(query_extended.map(|mut query| {
+        if let Some(factor) = factor {
+            query.extrapolate(factor);
+        }
+        query
+    }))
+}
+
+/// Streams query log totals and sums them across nodes.
+async fn fetch_total_queries(
+    client: &client::Client,
+    filter: model::QueriesFilter,
+    timings: &mut model::Timings,
+) -> Result<model::QueryLogTotal, crate::Error> {
+    let factor = filter.log_sampling_factor;
+    let (tx, rx) = mpsc::channel(128);
+    let analyzer_task = analyzer::total_queries(rx);
+
+    let stream_task = stream_split(filter, move |part| {
+        client.stream_logs_total(part.into(), vec![tx.clone()])
+    });
+
+    let (stream_result, (mut total_queries, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
+
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+
+    if let Some(factor) = factor {
+        total_queries.extrapolate(factor);
+    }
+    Ok(total_queries)
+}
+
+/// Streams query log totals per time bucket and merges them across nodes.
+async fn fetch_total_buckets(
+    client: &client::Client,
+    filter: model::QueriesFilter,
+    bucket: model::TimeBucket,
+    timings: &mut model::Timings,
+) -> Result<Vec<model::TotalBucket>, crate::Error> {
+    let factor = filter.log_sampling_factor;
+    let (tx, rx) = mpsc::channel(128);
+    let analyzer_task = analyzer::total_queries_by_bucket(rx);
+
+    let stream_task = stream_split(filter, move |part| {
+        client.stream_logs_total_by(part.into(), bucket, tx.clone())
+    });
+
+    let (stream_result, (mut buckets, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
+
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+
+    if let Some(factor) = factor {
+        buckets
+            .iter_mut()
+            .for_each(|bucket| bucket.total.extrapolate(factor));
+    }
+    Ok(buckets)
+}
+
+/// Streams query log totals, one channel per node, and returns them in node order.
+async fn fetch_node_totals(
+    client: &client::Client,
+    filter: model::QueriesFilter,
+    timings: &mut model::Timings,
+) -> Result<Vec<model::QueryLogTotal>, crate::Error> {
+    let factor = filter.log_sampling_factor;
+    let (senders, receivers): (Vec<_>, Vec<_>) =
+        (0..client.node_count()).map(|_| mpsc::channel(128)).unzip();
+    let analyzer_task = join_all(receivers.into_iter().map(analyzer::total_queries));
+
+    let stream_task = stream_split(filter, move |part| {
+        client.stream_logs_total(part.into(), senders.clone())
+    });
+
+    let (stream_result, (mut totals, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
+
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+
+    if let Some(factor) = factor {
+        totals
+            .iter_mut()
+            .for_each(|total| total.extrapolate(factor));
+    }
+    Ok(totals)
+}
+
+/// Streams errors grouped by code and returns the top `limit` of them, together
+/// with the totals dropped by `--min-count` when it is set.
+///
+/// With `with_message`, messages are still skipped (with a note) when some node
+/// has no `system.errors.last_error_message` column.
+async fn fetch_top_errors(
+    client: &client::Client,
+    filter: model::ErrorsFilter,
+    limit: usize,
+    sort_by: model::ErrorsSortBy,
+    with_message: bool,
+    timings: &mut model::Timings,
+) -> Result<(Vec<model::Error>, Option<model::BelowMinCount>), crate::Error> {
+    let with_message = with_message && error_message_available(client).await?;
+    let with_first_seen = first_error_time_available(client, filter.new_since.is_some()).await?;
+    // Excluded totals and first-seen times are only exact when every node sends all of its codes.
+    // Nodes rank their codes by count, so other orders need all of them too.
+    let node_limit = (filter.min_count.is_none()
+        && filter.new_since.is_none()
+        && sort_by == model::ErrorsSortBy::Count)
+        .then_some(limit);
+
+    let (tx, rx) = mpsc::channel(128);
+    let analyzer_task =
+        analyzer::top_errors(rx, limit, sort_by, filter.min_count, filter.new_since);
+
+    let stream_task =
+        client.stream_error_by_code(filter.into(), node_limit, with_message, with_first_seen, tx);
+
+    let (stream_result, (top_errors, analyzer_elapsed)) =
+        tokio::join!(stream_task, timed(analyzer_task));
+    timings.stages.extend(client.take_node_timings());
+    timings.record("analyzer", analyzer_elapsed);
+
+    stream_result.map_err(|e| crate::Error::client("Stream error", e))?;
+
+    Ok(top_errors)
+}
+
+/// Checks that every node has `system.errors.last_error_message`, noting the ones that don't.
+async fn error_message_available(client: &client::Client) -> Result<bool, crate::Error> {
+    let missing = client
+        .nodes_missing_system_column("errors", "last_error_message")
+        .await
+        .map_err(|e| crate::Error::client("Check system.errors columns error", e))?;
+    if !missing.is_empty() {
+        diagnostics::note(format!(
+            "system.errors.last_error_message is unavailable on {}, messages are left empty",
+            missing.join(", ")
+        ));
+    }
+
+    Ok(missing.is_empty())
+}
+
+/// Checks that every node has `system.errors.first_error_time`.
+///
+/// Older servers don't have it, then first-seen times are left empty, unless
+/// `required` by `--new-since`.
+async fn first_error_time_available(
+    client: &client::Client,
+    required: bool,
+) -> Result<bool, crate::Error> {
+    let missing = client
+        .nodes_missing_system_column("errors", "first_error_time")
+        .await
+        .map_err(|e| crate::Error::client("Check system.errors columns error", e))?;
+    if required && !missing.is_empty() {
+        return Err(format!(
+            "--new-since needs system.errors.first_error_time, unavailable on {}",
+            missing.join(", ")
+        )
+        .into());
+    }
+
+    Ok(missing.is_empty())
+}
+
+/// Warns to stderr when the requested time window starts before the oldest row
+/// retained in `query_log` on some node, since results would be silently partial.
+async fn warn_on_truncated_query_log(client: &client::Client, start: Option<OffsetDateTime>) {
+    let Some(start) = start else {
+        return;
+    };
+
+    let (tx, mut rx) = mpsc::channel(16);
+    let stream_task = client.stream_query_log_retention(tx);
+    let collect_task = async {
+        let mut retentions = Vec::new();
+        while let Some(retention) = rx.recv().await {
+            retentions.push(retention);
+        }
+        retentions
+    };
+
+    let (stream_result, retentions) = tokio::join!(stream_task, collect_task);
+    if let Err(e) = stream_result {
+        diagnostics::warn(format!("failed to check query_log retention: {e}"));
+        return;
+    }
+
+    for retention in retentions
+        .iter()
+        .filter(|retention| retention.min_event_time > start)
+    {
+        let earliest = retention
+            .min_event_time
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| "-".into());
+        diagnostics::warn(format!(
+            "query_log on {} only retains events since {earliest}, \
+             the requested time window is truncated",
+            retention.host
+        ));
+    }
+}
+
+/// Notes to stderr that metrics are extrapolated with `--log-sampling-factor`,
+/// so structured output isn't mistaken for exact counts either.
+fn note_extrapolated(filter: &model::QueriesFilter) {
+    if let Some(factor) = filter.log_sampling_factor {
+        diagnostics::note(format!(
+            "metrics are extrapolated from a sampled query_log, multiplied by {factor}"
+        ));
+    }
+}
+
+/// Notes to stderr which `ProfileEvents` metric categories look unavailable on the server,
+/// so their zero impact isn't mistaken for a real measurement.
+fn warn_on_unavailable_profile_events<T: model::ProfileEventsMetrics>(entries: &[T]) {
+    for category in analyzer::unavailable_profile_events(entries) {
+        diagnostics::note(format!(
+            "{category} metrics are zero for every query, \
+             the corresponding ProfileEvents may be unavailable on this server"
+        ));
+    }
+}
+
+/// Awaits `future` and returns its output together with the elapsed wall-clock time.
+async fn timed<F: Future>(future: F) -> (F::Output, Duration) {
+    let started = Instant::now();
+    let output = future.await;
+    (output, started.elapsed())
+}
@@ -16,14 +16,49 @@
 //!
 //! Profiles can be created, modified, and selected as the default. Credentials
 //! are stored securely using the [`keyring`] crate.
-use crate::model::{ContextConfig, ContextProfile};
+//!
+//! # Project-local config
+//!
+//! Without an explicit `--config`, clickcheck also looks for `.clickcheck/config.toml`
+//! in the current directory and its parents, stopping at the first directory with a
+//! `.git` entry (the repository root). When found, its profiles are added to the
+//! global ones, and its `current`, when set, wins over the global one.
+//!
+//! A checkout may be untrusted, so the local config can't reach the credentials of
+//! global profiles. It is refused when it:
+//!
+//! - defines a profile with the name of a global one, as that profile's keyring
+//!   password would be sent to the local `urls`;
+//! - sets `current` to a profile it doesn't define itself;
+//! - sets `keyring_service`.
+//!
+//! The local file is read-only for clickcheck: `context set`/`delete` only change the
+//! global config, and profiles defined locally can't be deleted.
+//!
+//! # Keyring service
+//!
+//! Passwords are stored under the `clickcheck` keyring service by default. It can be
+//! overridden with `--keyring-service` or the top-level `keyring_service` config key,
+//! e.g. to keep test and production configs (or CI runs) from sharing credentials.
+//!
+//! # Config from stdin
+//!
+//! `--config -` reads the config from stdin instead of a file, e.g. when it is generated
+//! by a secret manager in an ephemeral container. Such a config is read-only: `context
+//! set`/`delete` are refused, and no project-local config is merged over it. The
+//! `total --record` history stays next to the default config file.
+use crate::model::{redact_url, ContextConfig, ContextProfile};
 use secrecy::ExposeSecret;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use thiserror::Error;
 
 const SERVICE_NAME: &str = "clickcheck";
+const LOCAL_CONFIG_PATH: &str = ".clickcheck/config.toml";
+/// `--config` value reading the config from stdin.
+const STDIN_CONFIG_PATH: &str = "-";
 
 #[derive(Debug, Error)]
 pub enum ContextError {
@@ -42,8 +77,14 @@ pub enum ContextError {
     PersistTempFile(#[from] tempfile::PersistError),
     #[error("context profile '{0}' not found")]
     ProfileNotFound(String),
+    #[error("context profile '{name}' is defined in the local config {}, edit it there", path.display())]
+    LocalProfile { name: String, path: PathBuf },
+    #[error("local config {} may not {reason}", path.display())]
+    UntrustedLocalConfig { path: PathBuf, reason: String },
     #[error("keyring error: {0}")]
     KeyringError(#[from] keyring::Error),
+    #[error("config is read-only (stdin)")]
+    ReadOnly,
 }
 
 #[derive(Debug)]
@@ -53,47 +94,61 @@ pub enum ContextError {
 /// in the system keyring.
 pub struct Context {
     path: PathBuf,
+    /// Global config merged with the project-local one, used for reading
     config: ContextConfig,
+    /// Content of the file at `path`, the only one written back
+    stored: ContextConfig,
+    /// Project-local config file, if one was discovered
+    local_path: Option<PathBuf>,
     /// If the user passed `--context foo` on the CLI, store it here
     override_name: Option<String>,
+    /// Keyring service name the passwords are stored under
+    keyring_service: String,
+    /// Config was read from stdin (`--config -`) and can't be written back
+    read_only: bool,
 }
 
 impl Context {
     /// Constructs a new `Context` by reading a config file or creating a default config.
     ///
-    /// - `config_path`: Optional path to a custom config file.
+    /// - `config_path`: Optional path to a custom config file, `-` for stdin. Without it,
+    ///   a project-local config is merged over the global one (see the module docs).
     /// - `override_name`: Optional profile name to use instead of the default.
+    /// - `keyring_service`: Optional keyring service name, takes precedence over the
+    ///   `keyring_service` config key and the default `clickcheck`.
     ///
     /// Returns an error if the path is invalid or the profile does not exist.
     pub fn new(
         config_path: Option<&PathBuf>,
         override_name: Option<&str>,
+        keyring_service: Option<&str>,
     ) -> Result<Self, ContextError> {
-        let path = config_path.map_or_else(
-            || -> Result<PathBuf, ContextError> {
-                let path = dirs_next::config_dir()
-                    .ok_or(ContextError::InvalidPath)?
-                    .join(SERVICE_NAME)
-                    .join("config.toml");
-                Ok(path)
-            },
-            |p| Ok(p.clone()),
-        )?;
+        let read_only = config_path.is_some_and(|p| p.as_os_str() == STDIN_CONFIG_PATH);
+        let path = config_path.map_or_else(default_config_path, |p| Ok(p.clone()))?;
         // Создаём директорию при необходимости
-        if let Some(parent) = path.parent() {
+        if let Some(parent) = path.parent().filter(|_| !read_only) {
             std::fs::create_dir_all(parent)?;
         }
 
-        let config = if path.exists() {
-            let content = fs::read_to_string(&path)?;
-            toml::from_str(&content).map_err(|e| ContextError::ParseToml {
-                path: path.clone(),
-                source: e,
-            })?
+        let stored = if read_only {
+            read_stdin_config()?
+        } else if path.exists() {
+            read_config(&path)?
         } else {
             ContextConfig::default()
         };
 
+        let local_path = match config_path {
+            Some(_) => None,
+            None => find_local_config(&std::env::current_dir()?),
+        };
+        let config = match local_path.as_deref() {
+            Some(local_path) => {
+                merge_configs(stored.clone(), read_config(local_path)?, local_path)?
+            }
+            None => stored.clone(),
+        };
+
         let override_name = override_name.map(|n| n.to_string());
         if let Some(name) = override_name.as_deref() {
             if !config.profiles.contains_key(name) {
@@ -101,10 +156,19 @@ impl Context {
             }
         }
 
+        let keyring_service = keyring_service
+            .or(config.keyring_service.as_deref())
+            .unwrap_or(SERVICE_NAME)
+            .to_string();
+
         Ok(Self {
             config,
+            stored,
+            local_path,
             path,
             override_name,
+            keyring_service,
+            read_only,
         })
     }
     /// Returns a list of all available profile names.
@@ -112,6 +176,16 @@ impl Context {
         self.config.profiles.keys().cloned().collect()
     }
 
+    /// Returns the names of profiles that have every one of the `key=value` `labels`.
+    pub fn list_by_labels(&self, labels: &[(String, String)]) -> Vec<String> {
+        self.config
+            .profiles
+            .iter()
+            .filter(|(_, profile)| profile.has_labels(labels))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
     /// Returns the name of the currently active profile, either the overridden (see [`Context::new`]) one,
     /// or the default profile from the config.
     pub fn active_profile_name(&self) -> Option<&str> {
@@ -127,28 +201,148 @@ impl Context {
             .transpose()
     }
 
+    /// Like [`Self::profile`], but leaves the password empty without reading the
+    /// keyring, for callers that already have one. Returns the profile name too,
+    /// for a later [`Self::profile_password`].
+    pub fn profile_without_password(&self) -> Result<Option<(&str, ContextProfile)>, ContextError> {
+        self.active_profile_name()
+            .map(|name| Ok((name, self.get_profile_config(name)?)))
+            .transpose()
+    }
+
+    /// Reads the password of profile `name` from the system keyring.
+    pub fn profile_password(&self, name: &str) -> Result<secrecy::SecretString, ContextError> {
+        self.get_password(name)
+    }
+
     /// Adds or updates a profile with the given name, storing the password securely.
     ///
     /// Writes the config to disk after setting.
     pub fn set_profile(&mut self, profile: ContextProfile, name: &str) -> Result<(), ContextError> {
+        self.check_writable()?;
         self.store_password(name, &profile.password)?;
 
+        self.stored
+            .profiles
+            .insert(name.to_string(), profile.clone());
         self.config.profiles.insert(name.to_string(), profile);
         self.write_to_file()?;
 
         Ok(())
     }
 
-    /// Delete a profile with the given name.
+    /// Describes what [`Self::set_profile`] would change, without changing anything.
+    pub fn plan_set_profile(&self, profile: &ContextProfile, name: &str) -> Vec<String> {
+        let Some(existing) = self.stored.profiles.get(name) else {
+            return vec![
+                format!("add profile '{name}'"),
+                format!("store password of '{name}' in keyring"),
+            ];
+        };
+
+        let mut changes = Vec::new();
+        if existing.user != profile.user {
+            changes.push(format!(
+                "change '{name}' user: {} -> {}",
+                existing.user, profile.user
+            ));
+        }
+        if existing.urls != profile.urls {
+            changes.push(format!(
+                "change '{name}' urls: {} -> {}",
+                redact_urls(&existing.urls),
+                redact_urls(&profile.urls)
+            ));
+        }
+        if existing.accept_invalid_certificate != profile.accept_invalid_certificate {
+            changes.push(format!(
+                "change '{name}' accept_invalid_certificate: {} -> {}",
+                existing.accept_invalid_certificate, profile.accept_invalid_certificate
+            ));
+        }
+        if existing.tls_server_name != profile.tls_server_name {
+            let server_name = |p: &ContextProfile| {
+                p.tls_server_name
+                    .clone()
+                    .unwrap_or_else(|| "(URL host)".to_string())
+            };
+            changes.push(format!(
+                "change '{name}' tls_server_name: {} -> {}",
+                server_name(existing),
+                server_name(profile)
+            ));
+        }
+        if existing.labels != profile.labels {
+            let labels = |p: &ContextProfile| {
+                p.labels
+                    .iter()
+                    .map(|(key, value)| format!("{key}={value}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            changes.push(format!(
+                "change '{name}' labels: {} -> {}",
+                labels(existing),
+                labels(profile)
+            ));
+        }
+        changes.push(format!("overwrite password of '{name}' in keyring"));
+        changes
+    }
+
+    /// Describes what [`Self::delete_profile`] would change, without changing anything.
     ///
-    /// Writes the config to disk after setting.
-    pub fn delete_profile(&mut self, name: &str) -> Result<(), ContextError> {
+    /// Fails like [`Self::delete_profile`] would.
+    pub fn plan_delete_profile(&self, name: &str) -> Result<Vec<String>, ContextError> {
+        self.check_deletable(name)?;
+
+        let mut changes = vec![
+            format!("remove profile '{name}'"),
+            format!("delete password of '{name}' from keyring"),
+        ];
+        if self.stored.current.as_deref() == Some(name) {
+            changes.push(format!(
+                "'{name}' stays set as current but no longer exists"
+            ));
+        }
+        Ok(changes)
+    }
+
+    /// Describes what [`Self::set_default`] would change, without changing anything.
+    ///
+    /// Fails like [`Self::set_default`] would.
+    pub fn plan_set_default(&self, name: &str) -> Result<Vec<String>, ContextError> {
         if !self.config.profiles.contains_key(name) {
             return Err(ContextError::ProfileNotFound(name.to_string()));
         }
 
+        let change = match self.stored.current.as_deref() {
+            Some(current) if current == name => format!("'{name}' is already current"),
+            Some(current) => format!("change current: {current} -> {name}"),
+            None => format!("set current: {name}"),
+        };
+        Ok(vec![change])
+    }
+
+    /// Describes what [`Self::unset_default`] would change, without changing anything.
+    pub fn plan_unset_default(&self) -> Vec<String> {
+        let change = match self.stored.current.as_deref() {
+            Some(current) => format!("unset current: {current}"),
+            None => "no current is set".to_string(),
+        };
+        vec![change]
+    }
+
+    /// Delete a profile with the given name.
+    ///
+    /// Writes the config to disk after setting.
+    pub fn delete_profile(&mut self, name: &str) -> Result<(), ContextError> {
+        self.check_writable()?;
+        self.check_deletable(name)?;
+
         self.delete_password(name)?;
 
+        self.stored.profiles.remove(name);
         self.config.profiles.remove(name);
         self.write_to_file()?;
 
@@ -159,38 +353,92 @@ impl Context {
     ///
     /// Returns an error if the profile does not exist.
     pub fn set_default(&mut self, name: &str) -> Result<(), ContextError> {
+        self.check_writable()?;
         if !self.config.profiles.contains_key(name) {
             return Err(ContextError::ProfileNotFound(name.to_string()));
         }
 
+        self.stored.current = Some(name.to_string());
         self.config.current = Some(name.to_string());
         self.write_to_file()?;
 
         Ok(())
     }
 
+    /// Clears the default profile, so commands fall back to CLI flags or `--context`.
+    ///
+    /// Profiles are kept. Writes the config to disk after clearing.
+    pub fn unset_default(&mut self) -> Result<(), ContextError> {
+        self.check_writable()?;
+        self.stored.current = None;
+        self.config.current = None;
+        self.write_to_file()?;
+
+        Ok(())
+    }
+
     /// Loads a profile by name and fills in its password from the system keyring.
     pub fn get_profile(&self, name: &str) -> Result<ContextProfile, ContextError> {
-        let mut profile = self
-            .config
-            .profiles
-            .get(name)
-            .ok_or_else(|| ContextError::ProfileNotFound(name.to_string()))?
-            .clone();
-
+        let mut profile = self.get_profile_config(name)?;
         profile.password = self.get_password(name)?;
         Ok(profile)
     }
 
+    // Профиль из конфига, без пароля из keyring
+    fn get_profile_config(&self, name: &str) -> Result<ContextProfile, ContextError> {
+        self.config
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ContextError::ProfileNotFound(name.to_string()))
+    }
+
     /// Returns the resolved path to the config file used by this context.
     pub fn get_config_path(&self) -> &PathBuf {
         &self.path
     }
 
+    /// Returns the path of the `total --record` history, next to the config file
+    /// (the default one for a config read from stdin).
+    pub fn history_path(&self) -> Result<PathBuf, ContextError> {
+        let config_path = match self.read_only {
+            true => default_config_path()?,
+            false => self.path.clone(),
+        };
+        Ok(config_path.with_file_name("history.jsonl"))
+    }
+
+    /// Returns the project-local config merged over the global one, if any.
+    pub fn local_config_path(&self) -> Option<&Path> {
+        self.local_path.as_deref()
+    }
+
     // --- Приватные вспомогательные методы ---
 
+    fn check_writable(&self) -> Result<(), ContextError> {
+        match self.read_only {
+            true => Err(ContextError::ReadOnly),
+            false => Ok(()),
+        }
+    }
+
+    fn check_deletable(&self, name: &str) -> Result<(), ContextError> {
+        if self.stored.profiles.contains_key(name) {
+            return Ok(());
+        }
+        match &self.local_path {
+            Some(path) if self.config.profiles.contains_key(name) => {
+                Err(ContextError::LocalProfile {
+                    name: name.to_string(),
+                    path: path.clone(),
+                })
+            }
+            _ => Err(ContextError::ProfileNotFound(name.to_string())),
+        }
+    }
+
     fn write_to_file(&self) -> Result<(), ContextError> {
-        let toml = toml::to_string_pretty(&self.config)
+        let toml = toml::to_string_pretty(&self.stored)
             .map_err(|e| ContextError::SerializeToml(e.to_string()))?;
 
         let dir = self
@@ -212,20 +460,244 @@ impl Context {
         profile_name: &str,
         password: &secrecy::SecretString,
     ) -> Result<(), ContextError> {
-        let entry = keyring::Entry::new(SERVICE_NAME, profile_name)?;
+        let entry = keyring::Entry::new(&self.keyring_service, profile_name)?;
         entry.set_password(password.expose_secret())?;
         Ok(())
     }
 
     fn delete_password(&self, profile_name: &str) -> Result<(), ContextError> {
-        let entry = keyring::Entry::new(SERVICE_NAME, profile_name)?;
+        let entry = keyring::Entry::new(&self.keyring_service, profile_name)?;
         entry.delete_credential()?;
         Ok(())
     }
 
     fn get_password(&self, profile_name: &str) -> Result<secrecy::SecretString, ContextError> {
-        let entry = keyring::Entry::new(SERVICE_NAME, profile_name)?;
+        let entry = keyring::Entry::new(&self.keyring_service, profile_name)?;
         let password = entry.get_password()?;
         Ok(secrecy::SecretString::new(password.into()))
     }
 }
+
+// Список URL для вывода, без учётных данных
+fn redact_urls(urls: &[String]) -> String {
+    urls.iter()
+        .map(|url| redact_url(url))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn default_config_path() -> Result<PathBuf, ContextError> {
+    let path = dirs_next::config_dir()
+        .ok_or(ContextError::InvalidPath)?
+        .join(SERVICE_NAME)
+        .join("config.toml");
+    Ok(path)
+}
+
+// stdin читается один раз: контекст может создаваться несколько раз за запуск
+fn read_stdin_config() -> Result<ContextConfig, ContextError> {
+    static CONTENT: OnceLock<String> = OnceLock::new();
+    let content = match CONTENT.get() {
+        Some(content) => content,
+        None => {
+            let mut content = String::new();
+            std::io::stdin().read_to_string(&mut content)?;
+            CONTENT.get_or_init(|| content)
+        }
+    };
+    toml::from_str(content).map_err(|e| ContextError::ParseToml {
+        path: PathBuf::from("<stdin>"),
+        source: e,
+    })
+}
+
+fn read_config(path: &Path) -> Result<ContextConfig, ContextError> {
+    let content = fs::read_to_string(path)?;
+    toml::from_str(&content).map_err(|e| ContextError::ParseToml {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+/// Looks for [`LOCAL_CONFIG_PATH`] in `start` and its parents, up to the first
+/// directory containing `.git`.
+fn find_local_config(start: &Path) -> Option<PathBuf> {
+    for dir in start.ancestors() {
+        let candidate = dir.join(LOCAL_CONFIG_PATH);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// Merges the project-local config at `path` over the global one, refusing a local
+/// config that could reach the credentials of global profiles (see the module docs).
+fn merge_configs(
+    mut global: ContextConfig,
+    local: ContextConfig,
+    path: &Path,
+) -> Result<ContextConfig, ContextError> {
+    let refuse = |reason: String| ContextError::UntrustedLocalConfig {
+        path: path.to_path_buf(),
+        reason,
+    };
+    if let Some(name) = local
+        .profiles
+        .keys()
+        .find(|name| global.profiles.contains_key(*name))
+    {
+        return Err(refuse(format!("redefine the global profile '{name}'")));
+    }
+    if let Some(current) = &local.current {
+        if !local.profiles.contains_key(current) {
+            return Err(refuse(format!(
+                "select the profile '{current}' it doesn't define as `current`"
+            )));
+        }
+    }
+    if local.keyring_service.is_some() {
+        return Err(refuse("set `keyring_service`".to_string()));
+    }
+
+    global.profiles.extend(local.profiles);
+    global.current = local.current.or(global.current);
+    Ok(global)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [profiles.prod]
+        user = "analyst"
+        urls = ["http://prod:8123"]
+        accept_invalid_certificate = false
+
+        [profiles.staging]
+        user = "analyst"
+        urls = ["http://staging:8123"]
+        accept_invalid_certificate = false
+    "#;
+
+    #[test]
+    fn set_then_unset_current_keeps_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, CONFIG).unwrap();
+
+        let mut ctx = Context::new(Some(&path), None, None).unwrap();
+        ctx.set_default("prod").unwrap();
+        assert_eq!(
+            Context::new(Some(&path), None, None)
+                .unwrap()
+                .active_profile_name(),
+            Some("prod")
+        );
+
+        ctx.unset_default().unwrap();
+        let ctx = Context::new(Some(&path), None, None).unwrap();
+        assert_eq!(ctx.active_profile_name(), None);
+        let mut profiles = ctx.list();
+        profiles.sort();
+        assert_eq!(profiles, ["prod", "staging"]);
+        assert_eq!(
+            ctx.get_profile_config("prod").unwrap().urls,
+            ["http://prod:8123"]
+        );
+    }
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn local_config_is_found_in_parents_up_to_repo_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        let nested = repo.join("a/b");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_local_config(&nested), None);
+
+        // Конфиг выше корня репозитория не читается
+        write(&dir.path().join(LOCAL_CONFIG_PATH), CONFIG);
+        assert_eq!(find_local_config(&nested), None);
+
+        write(&repo.join(LOCAL_CONFIG_PATH), CONFIG);
+        assert_eq!(
+            find_local_config(&nested),
+            Some(repo.join(LOCAL_CONFIG_PATH))
+        );
+        assert_eq!(find_local_config(&repo), Some(repo.join(LOCAL_CONFIG_PATH)));
+
+        // Ближайший к текущей директории выигрывает
+        write(&repo.join("a").join(LOCAL_CONFIG_PATH), CONFIG);
+        assert_eq!(
+            find_local_config(&nested),
+            Some(repo.join("a").join(LOCAL_CONFIG_PATH))
+        );
+    }
+
+    fn merge(local: &str) -> Result<ContextConfig, ContextError> {
+        let mut global: ContextConfig = toml::from_str(CONFIG).unwrap();
+        global.current = Some("prod".to_string());
+        merge_configs(
+            global,
+            toml::from_str(local).unwrap(),
+            Path::new(LOCAL_CONFIG_PATH),
+        )
+    }
+
+    #[test]
+    fn local_config_adds_profiles() {
+        let config = merge(
+            r#"
+            current = "dev"
+
+            [profiles.dev]
+            user = "dev"
+            urls = ["http://localhost:8123"]
+            accept_invalid_certificate = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.current.as_deref(), Some("dev"));
+        assert_eq!(config.profiles.len(), 3);
+        assert_eq!(config.profiles["prod"].urls, ["http://prod:8123"]);
+
+        let config = merge("[profiles]").unwrap();
+        assert_eq!(config.current.as_deref(), Some("prod"));
+    }
+
+    #[test]
+    fn local_config_cant_reach_global_credentials() {
+        for local in [
+            // Чужие URL для пароля профиля prod
+            r#"
+            [profiles.prod]
+            user = "analyst"
+            urls = ["http://attacker:8123"]
+            accept_invalid_certificate = false
+            "#,
+            r#"
+            current = "staging"
+            [profiles]
+            "#,
+            r#"
+            keyring_service = "other"
+            [profiles]
+            "#,
+        ] {
+            assert!(
+                matches!(merge(local), Err(ContextError::UntrustedLocalConfig { .. })),
+                "{local}"
+            );
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! Non-fatal diagnostics printed to stderr.
+//!
+//! Warnings and notes (outdated servers, truncated `query_log`, missing metrics, ...)
+//! go through [`warn`] and [`note`], so a single `--quiet` flag can silence all of them
+//! while results on stdout and real errors stay intact.
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppresses (or re-enables) every subsequent warning and note.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Returns `true` if diagnostics are suppressed by `--quiet`.
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints a warning about a condition that may make results wrong or incomplete.
+pub fn warn(message: impl Display) {
+    if !is_quiet() {
+        eprintln!("Warning: {message}");
+    }
+}
+
+/// Prints an informational note that helps to interpret the results.
+pub fn note(message: impl Display) {
+    if !is_quiet() {
+        eprintln!("Note: {message}");
+    }
+}
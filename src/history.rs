@@ -0,0 +1,68 @@
+//! History of the totals recorded by `total --record`.
+//!
+//! Every recorded run appends one [`HistoryRecord`] as a JSON line to `history.jsonl`
+//! next to the config file, `total --history` prints the latest ones. Only the
+//! latest [`MAX_RECORDS`] are kept, older ones are dropped on the next append.
+use crate::model::HistoryRecord;
+use std::io::Write;
+use std::path::Path;
+
+/// Number of records kept in the history file.
+pub const MAX_RECORDS: usize = 1000;
+
+/// Appends `record` to the history at `path`, dropping the oldest records beyond
+/// [`MAX_RECORDS`].
+///
+/// The file is replaced atomically, so a failed write never loses the history.
+pub fn append(path: &Path, record: &HistoryRecord) -> Result<(), String> {
+    let mut records = read(path, MAX_RECORDS - 1)?;
+    records.push(record.clone());
+
+    let mut content = Vec::new();
+    for record in &records {
+        serde_json::to_writer(&mut content, record)
+            .map_err(|e| format!("serialize history record error: {e}"))?;
+        content.push(b'\n');
+    }
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut tmp_file = tempfile::NamedTempFile::new_in(dir)
+        .map_err(|e| format!("create history {} error: {e}", path.display()))?;
+    tmp_file
+        .write_all(&content)
+        .map_err(|e| format!("write history {} error: {e}", path.display()))?;
+    tmp_file
+        .persist(path)
+        .map_err(|e| format!("write history {} error: {e}", path.display()))?;
+    Ok(())
+}
+
+/// Reads the latest `last` records from the history at `path`, oldest first.
+///
+/// A missing file is an empty history.
+pub fn read(path: &Path, last: usize) -> Result<Vec<HistoryRecord>, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("read history {} error: {e}", path.display())),
+    };
+
+    let lines: Vec<&str> = content.lines().filter(|line| !line.is_empty()).collect();
+    lines
+        .iter()
+        .enumerate()
+        .skip(lines.len().saturating_sub(last))
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                format!(
+                    "parse history {} record {} error: {e}",
+                    path.display(),
+                    i + 1
+                )
+            })
+        })
+        .collect()
+}
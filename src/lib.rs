@@ -2,64 +2,216 @@
 //! See [`command`] for available CLI commands.
 
 pub mod analyzer;
+pub mod cache;
 pub mod cli;
 pub mod client;
 pub mod command;
 pub mod context;
+pub mod diagnostics;
+pub mod history;
 pub mod model;
 pub mod output;
+#[cfg(feature = "tui")]
+pub mod tui;
 
 use clap::Parser;
 use cli::{CliArgs, Command};
+use std::time::Instant;
 
-pub async fn run() -> Result<(), String> {
+/// Error of a failed [`run`], see [`Error::exit_code`].
+#[derive(Debug)]
+pub enum Error {
+    /// `--skip-unreachable` left out every node, see
+    /// [`client::ClientError::AllNodesUnreachable`].
+    AllNodesUnreachable(String),
+    Failed(String),
+}
+
+impl Error {
+    /// Wraps a failed query with `context`, e.g. `Stream error`, keeping
+    /// [`client::ClientError::AllNodesUnreachable`] apart for its exit code.
+    pub fn client(context: &str, e: client::ClientError) -> Self {
+        match e {
+            client::ClientError::AllNodesUnreachable => {
+                Error::AllNodesUnreachable(format!("{context}: {e}"))
+            }
+            e => Error::Failed(format!("{context}: {e}")),
+        }
+    }
+
+    /// Exit code of the process: 3 when `--skip-unreachable` left out every node, 1 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::AllNodesUnreachable(_) => 3,
+            Error::Failed(_) => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::AllNodesUnreachable(message) | Error::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Failed(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Failed(message.to_string())
+    }
+}
+
+pub async fn run() -> Result<(), Error> {
     let cli_args = CliArgs::parse();
+    diagnostics::set_quiet(cli_args.quiet);
+    let mut timings = model::Timings::default();
+    let (output_dir, overwrite) = match &cli_args.command {
+        Command::Health {
+            output_dir,
+            overwrite,
+            ..
+        } => (output_dir.clone(), *overwrite),
+        _ => (None, false),
+    };
+    let out = model::Output {
+        raw_impact: cli_args.raw_impact,
+        fingerprint_format: cli_args.fingerprint_format,
+        ..model::Output::new(
+            &cli_args.out,
+            cli_args.output_file.clone(),
+            output_dir,
+            !cli_args.no_pager,
+        )
+    };
+    let structured = out.formats.iter().any(|f| *f != model::OutputFormat::Text);
+    if out.file.is_some() && !structured {
+        return Err("`--output-file` requires a structured `--out` format, e.g. json".into());
+    }
+    if out.dir.is_some() {
+        // clap не видит конфликт, если глобальный `--output-file` стоит до подкоманды
+        if out.file.is_some() {
+            return Err("`--output-dir` cannot be used with `--output-file`".into());
+        }
+        if !structured {
+            return Err("`--output-dir` requires a structured `--out` format, e.g. json".into());
+        }
+        prepare_output_dir(&out, &model::Health::SECTIONS, overwrite)?;
+    }
+    if out.formats.contains(&model::OutputFormat::Prometheus)
+        && !matches!(
+            cli_args.command,
+            Command::Queries { .. } | Command::Total { .. } | Command::Errors { .. }
+        )
+    {
+        return Err(
+            "`--out prometheus` is only supported by `queries`, `total` and `errors`".into(),
+        );
+    }
 
     match &cli_args.command {
         Command::Queries {
             conn,
             sort_by,
             filter,
+            weights,
             limit,
+            top_users,
+            group_by_table,
+            group_by_database,
+            top,
+            columns,
+            show_normalized,
+            impact_expr,
+            rate,
+            min_distinct_users,
+            check_query_texts,
+            by_node,
+            node_column,
+            cache,
+            use_cache,
+            iterations,
+            #[cfg(feature = "tui")]
+            tui,
         } => {
-            let ctx = context::Context::new(cli_args.config.as_ref(), cli_args.context.as_deref())
-                .map_err(|e| format!("context error: {e}"))?;
-            let profile = resolve_profile(&conn, &ctx)?;
-            let client = client::Client::new(client::Config {
-                urls: &profile.urls,
-                user: &profile.user,
-                password: &profile.password,
-                danger_accept_invalid_certs: profile.accept_invalid_certificate,
-            })
-            .map_err(|e| format!("create clickhouse client error: {e}"))?;
-            command::top_queries(
-                client,
-                model::TopQueriesRequest {
-                    sort_by: sort_by.clone(),
-                    filter: filter.clone().into(),
-                    limit: limit.clone(),
-                    out: cli_args.out,
+            let req = model::TopQueriesRequest {
+                sort_by: sort_by.clone(),
+                filter: model::QueriesFilter {
+                    normalize_query: *show_normalized,
+                    impact_expr: impact_expr.clone(),
+                    min_distinct_users: *min_distinct_users,
+                    check_query_texts: *check_query_texts,
+                    impact_weights: weights.clone().into(),
+                    ..filter.clone().into()
                 },
-            )
-            .await?
+                limit: *limit,
+                iterations: *iterations,
+                by_node: *by_node,
+                node_column: *node_column,
+                top_users: *top_users,
+                group_by_table: *group_by_table,
+                group_by_database: *group_by_database,
+                rollup_limit: *top,
+                columns: if columns.is_empty() {
+                    model::QueryColumn::DEFAULT.to_vec()
+                } else {
+                    columns.clone()
+                },
+                rate: *rate,
+                cache: cache.clone(),
+                out: out.clone(),
+            };
+            if *use_cache {
+                command::top_queries_cached(req)?;
+                return Ok(());
+            }
+            let client = connect(conn, &cli_args, &mut timings).await?;
+            #[cfg(feature = "tui")]
+            if *tui {
+                command::top_queries_tui(client, req, &mut timings).await?;
+                return Ok(());
+            }
+            command::top_queries(client, req, &mut timings).await?
         }
-        Command::Total { conn, filter } => {
-            let ctx = context::Context::new(cli_args.config.as_ref(), cli_args.context.as_deref())
-                .map_err(|e| format!("context error: {e}"))?;
-            let profile = resolve_profile(&conn, &ctx)?;
-            let client = client::Client::new(client::Config {
-                urls: &profile.urls,
-                user: &profile.user,
-                password: &profile.password,
-                danger_accept_invalid_certs: profile.accept_invalid_certificate,
-            })
-            .map_err(|e| format!("create clickhouse client error: {e}"))?;
+        Command::Total {
+            conn,
+            filter,
+            weights,
+            compare_nodes,
+            outlier_factor,
+            record,
+            breakdown_by,
+            history,
+            iterations,
+        } => {
+            if let Some(last) = history {
+                return command::total_history(&history_path(&cli_args)?, *last, &out);
+            }
+            let record = match record {
+                true => Some(history_path(&cli_args)?),
+                false => None,
+            };
+            let client = connect(conn, &cli_args, &mut timings).await?;
             command::total_queries(
                 client,
                 model::TotalQueriesRequest {
-                    filter: filter.clone().into(),
-                    out: cli_args.out,
+                    filter: model::QueriesFilter {
+                        impact_weights: weights.clone().into(),
+                        ..filter.clone().into()
+                    },
+                    iterations: *iterations,
+                    compare_nodes: compare_nodes.then_some(*outlier_factor),
+                    record,
+                    breakdown_by: *breakdown_by,
+                    out: out.clone(),
                 },
+                &mut timings,
             )
             .await?
         }
@@ -67,24 +219,26 @@ pub async fn run() -> Result<(), String> {
             conn,
             fingerprint,
             filter,
+            show_normalized,
+            truncate_query_bytes,
+            explain_impact,
+            iterations,
         } => {
-            let ctx = context::Context::new(cli_args.config.as_ref(), cli_args.context.as_deref())
-                .map_err(|e| format!("context error: {e}"))?;
-            let profile = resolve_profile(&conn, &ctx)?;
-            let client = client::Client::new(client::Config {
-                urls: &profile.urls,
-                user: &profile.user,
-                password: &profile.password,
-                danger_accept_invalid_certs: profile.accept_invalid_certificate,
-            })
-            .map_err(|e| format!("create clickhouse client error: {e}"))?;
+            let client = connect(conn, &cli_args, &mut timings).await?;
             command::inspect_fingerprint(
                 client,
                 model::InspectFingerprintRequest {
-                    fingerprint: fingerprint.clone(),
-                    filter: filter.clone().into(),
-                    out: cli_args.out,
+                    fingerprint: *fingerprint,
+                    iterations: *iterations,
+                    filter: model::QueriesFilter {
+                        normalize_query: *show_normalized,
+                        truncate_query_bytes: Some(truncate_query_bytes.as_u64()),
+                        ..filter.clone().into()
+                    },
+                    explain_impact: *explain_impact,
+                    out: out.clone(),
                 },
+                &mut timings,
             )
             .await?
         }
@@ -92,38 +246,388 @@ pub async fn run() -> Result<(), String> {
             conn,
             filter,
             limit,
+            sort_by,
+            no_error_message,
+            watch,
+            diff,
         } => {
-            let ctx = context::Context::new(cli_args.config.as_ref(), cli_args.context.as_deref())
-                .map_err(|e| format!("context error: {e}"))?;
-            let profile = resolve_profile(&conn, &ctx)?;
-            let client = client::Client::new(client::Config {
-                urls: &profile.urls,
-                user: &profile.user,
-                password: &profile.password,
-                danger_accept_invalid_certs: profile.accept_invalid_certificate,
-            })
-            .map_err(|e| format!("create clickhouse client error: {e}"))?;
+            let client = connect(conn, &cli_args, &mut timings).await?;
             command::top_errors(
                 client,
                 model::TopErrorsRequest {
-                    limit: limit.clone(),
+                    limit: *limit,
+                    sort_by: *sort_by,
                     filter: filter.clone().into(),
-                    out: cli_args.out,
+                    no_error_message: *no_error_message,
+                    watch: *watch,
+                    diff: *diff,
+                    out: out.clone(),
                 },
+                &mut timings,
             )
             .await?
         }
-        Command::Context { command } => {
-            let mut ctx =
-                context::Context::new(cli_args.config.as_ref(), cli_args.context.as_deref())
-                    .map_err(|e| format!("context error: {e}"))?;
-            command::context(&mut ctx, command, cli_args.out).await?
+        Command::Health {
+            conn,
+            filter,
+            limit,
+            ..
+        } => {
+            let client = connect(conn, &cli_args, &mut timings).await?;
+            command::health(
+                client,
+                model::HealthRequest {
+                    limit: *limit,
+                    filter: filter.clone().into(),
+                    out: out.clone(),
+                },
+                &mut timings,
+            )
+            .await?
+        }
+        Command::Merges {
+            conn,
+            limit,
+            sort_by,
+        } => {
+            let client = connect(conn, &cli_args, &mut timings).await?;
+            command::merges(
+                client,
+                model::MergesRequest {
+                    limit: *limit,
+                    sort_by: *sort_by,
+                    out: out.clone(),
+                },
+                &mut timings,
+            )
+            .await?
+        }
+        Command::Mutations {
+            conn,
+            filter,
+            limit,
+            sort_by,
+        } => {
+            let client = connect(conn, &cli_args, &mut timings).await?;
+            command::mutations(
+                client,
+                model::MutationsRequest {
+                    limit: *limit,
+                    sort_by: *sort_by,
+                    filter: filter.clone().into(),
+                    out: out.clone(),
+                },
+                &mut timings,
+            )
+            .await?
+        }
+        Command::Replication {
+            conn,
+            max_lag,
+            by_node,
+        } => {
+            let client = connect(conn, &cli_args, &mut timings).await?;
+            command::replication(
+                client,
+                model::ReplicationRequest {
+                    max_lag: *max_lag,
+                    by_node: *by_node,
+                    out: out.clone(),
+                },
+                &mut timings,
+            )
+            .await?
+        }
+        Command::Parts {
+            conn,
+            filter,
+            limit,
+        } => {
+            let client = connect(conn, &cli_args, &mut timings).await?;
+            command::parts(
+                client,
+                model::PartsRequest {
+                    limit: *limit,
+                    filter: filter.clone().into(),
+                    out: out.clone(),
+                },
+                &mut timings,
+            )
+            .await?
+        }
+        Command::SettingsDiff { conn, changed_only } => {
+            let client = connect(conn, &cli_args, &mut timings).await?;
+            command::settings_diff(
+                client,
+                model::SettingsDiffRequest {
+                    changed_only: *changed_only,
+                    out: out.clone(),
+                },
+                &mut timings,
+            )
+            .await?
+        }
+        Command::Ping { conn, count } => {
+            let client = build_client(conn, &cli_args, &mut timings)?;
+            command::ping(
+                client,
+                model::PingRequest {
+                    count: *count,
+                    out: out.clone(),
+                },
+                &mut timings,
+            )
+            .await?
+        }
+        Command::Context { dry_run, command } => {
+            let mut ctx = context::Context::new(
+                cli_args.config.as_ref(),
+                cli_args.context.as_deref(),
+                cli_args.keyring_service.as_deref(),
+            )
+            .map_err(|e| format!("context error: {e}"))?;
+            if let cli::ContextCommand::Current {
+                resolve: true,
+                show_secrets,
+                conn,
+            } = command
+            {
+                let mut profile = resolve_profile(conn, &ctx)?;
+                profile.urls = select_nodes(&profile.urls, conn)?;
+                output::print_context_profile(&profile.to_printable(*show_secrets), &out);
+                return Ok(());
+            }
+            command::context(&mut ctx, command, *dry_run, &out).await?
+        }
+        #[cfg(feature = "schema")]
+        Command::Schema { kind } => output::print_schema(*kind, &out),
+        Command::Version { verbose } => {
+            output::print_version(&model::BuildInfo::current(), *verbose, &out)
         }
     }
 
+    if cli_args.timings {
+        output::print_timings(&timings);
+    }
+
+    Ok(())
+}
+
+/// Returns the path of the `total --record` history of the config in use.
+fn history_path(cli_args: &CliArgs) -> Result<std::path::PathBuf, String> {
+    let ctx = context::Context::new(
+        cli_args.config.as_ref(),
+        cli_args.context.as_deref(),
+        cli_args.keyring_service.as_deref(),
+    )
+    .map_err(|e| format!("context error: {e}"))?;
+    ctx.history_path()
+        .map_err(|e| format!("context error: {e}"))
+}
+
+/// Creates the `--output-dir` of `out` if missing and, unless `overwrite` is set,
+/// refuses to go on when any file of `sections` already exists there.
+fn prepare_output_dir(
+    out: &model::Output,
+    sections: &[&'static str],
+    overwrite: bool,
+) -> Result<(), String> {
+    let Some(dir) = &out.dir else {
+        return Ok(());
+    };
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("create output directory {} error: {e}", dir.display()))?;
+    if overwrite {
+        return Ok(());
+    }
+    let existing: Vec<_> = out
+        .section_files(sections)
+        .into_iter()
+        .filter(|path| path.exists())
+        .map(|path| path.display().to_string())
+        .collect();
+    if !existing.is_empty() {
+        return Err(format!(
+            "refusing to overwrite {}, pass `--overwrite` to replace them",
+            existing.join(", ")
+        ));
+    }
     Ok(())
 }
 
+/// Builds a ClickHouse client with [`build_client`].
+///
+/// Also checks the server versions, warning about (or with `--strict-version`
+/// rejecting) nodes older than [`client::MIN_SERVER_VERSION`].
+async fn connect(
+    conn: &cli::ConnectArgs,
+    cli_args: &CliArgs,
+    timings: &mut model::Timings,
+) -> Result<client::Client, Error> {
+    let mut client = build_client(conn, cli_args, timings)?;
+
+    if conn.allow_partial {
+        let unreachable = client
+            .retain_reachable_nodes()
+            .await
+            .map_err(|e| format!("no reachable ClickHouse node: {e}"))?;
+        for e in unreachable {
+            diagnostics::warn(format!("skipping unreachable {e}"));
+        }
+    }
+
+    let outdated = client
+        .outdated_nodes()
+        .await
+        .map_err(|e| Error::client("check clickhouse version error", e))?;
+    let (major, minor) = client::MIN_SERVER_VERSION;
+    for node in &outdated {
+        let message = format!(
+            "ClickHouse {} on {} is older than {major}.{minor}, \
+             these features may be unavailable: {}",
+            node.version,
+            node.host,
+            client::MIN_SERVER_VERSION_FEATURES
+        );
+        if conn.strict_version {
+            return Err(message.into());
+        }
+        diagnostics::warn(message);
+    }
+
+    Ok(client)
+}
+
+/// Builds a ClickHouse client for the resolved profile,
+/// recording the construction time into `timings`.
+///
+/// Unlike [`connect`], no node is contacted yet.
+fn build_client(
+    conn: &cli::ConnectArgs,
+    cli_args: &CliArgs,
+    timings: &mut model::Timings,
+) -> Result<client::Client, String> {
+    let ctx = context::Context::new(
+        cli_args.config.as_ref(),
+        cli_args.context.as_deref(),
+        cli_args.keyring_service.as_deref(),
+    )
+    .map_err(|e| format!("context error: {e}"))?;
+    let mut profile = resolve_profile(conn, &ctx)?;
+    profile.urls = select_nodes(&profile.urls, conn)?;
+    let settings = resolve_settings(conn)?;
+
+    let started = Instant::now();
+    let client = client::Client::new(client::Config {
+        urls: &profile.urls,
+        user: &profile.user,
+        password: &profile.password,
+        danger_accept_invalid_certs: profile.accept_invalid_certificate,
+        tls_min_version: conn.tls_min_version,
+        tls_server_name: profile.tls_server_name.as_deref(),
+        settings: &settings,
+        headers: &conn.headers,
+        readonly: conn.readonly,
+        max_memory_usage: conn.server_max_memory.map(|m| m.as_u64()),
+        retries: conn.retries,
+        retry_backoff: conn.retry_backoff,
+        skip_unreachable: conn.skip_unreachable,
+        timeout: (!conn.timeout.is_zero()).then_some(conn.timeout),
+    })
+    .map_err(|e| format!("create clickhouse client error: {e}"))?;
+    timings.record("client construction", started.elapsed());
+
+    Ok(client)
+}
+
+/// Returns the settings of `--settings-file` followed by the `--setting` ones,
+/// so that the latter win when the client applies them in order.
+fn resolve_settings(conn: &cli::ConnectArgs) -> Result<Vec<(String, String)>, String> {
+    let Some(path) = &conn.settings_file else {
+        return Ok(conn.settings.clone());
+    };
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("read settings file {} error: {e}", path.display()))?;
+    let mut settings = cli::parse_settings_file(&content)
+        .map_err(|e| format!("invalid settings file {}:\n{e}", path.display()))?;
+    settings.extend(conn.settings.iter().cloned());
+    Ok(settings)
+}
+
+/// Restricts `urls` to the nodes picked by `--node` or `--node-index`,
+/// keeping all of them if neither is given.
+fn select_nodes(urls: &[String], conn: &cli::ConnectArgs) -> Result<Vec<String>, String> {
+    if !conn.nodes.is_empty() {
+        if let Some(node) = conn.nodes.iter().find(|node| !urls.contains(node)) {
+            return Err(format!(
+                "node {} is not one of the configured URLs",
+                model::redact_url(node)
+            ));
+        }
+        return Ok(urls
+            .iter()
+            .filter(|url| conn.nodes.contains(url))
+            .cloned()
+            .collect());
+    }
+    if !conn.node_indexes.is_empty() {
+        if let Some(index) = conn.node_indexes.iter().find(|&&i| i >= urls.len()) {
+            return Err(format!(
+                "node index {index} is out of range: {} URLs are configured",
+                urls.len()
+            ));
+        }
+        return Ok(urls
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| conn.node_indexes.contains(i))
+            .map(|(_, url)| url.clone())
+            .collect());
+    }
+    Ok(urls.to_vec())
+}
+
+/// Applies the connection flags given on the command line over `profile`.
+///
+/// Returns a note naming the profile fields the flags changed, if any.
+fn override_profile(cli: &cli::ConnectArgs, profile: &mut model::ContextProfile) -> Option<String> {
+    let mut overridden = Vec::new();
+    if !cli.urls.is_empty() {
+        if profile.urls != cli.urls {
+            overridden.push("urls");
+        }
+        profile.urls = cli.urls.clone();
+    }
+    if let Some(user) = cli.user.as_deref() {
+        if profile.user != user {
+            overridden.push("user");
+        }
+        profile.user = user.to_string();
+    }
+    if cli.accept_invalid_certificate.is_some() {
+        if !profile.accept_invalid_certificate {
+            overridden.push("accept_invalid_certificate");
+        }
+        profile.accept_invalid_certificate = true
+    }
+    if let Some(server_name) = cli.tls_server_name.clone() {
+        if profile
+            .tls_server_name
+            .as_ref()
+            .is_some_and(|name| *name != server_name)
+        {
+            overridden.push("tls_server_name");
+        }
+        profile.tls_server_name = Some(server_name);
+    }
+    (!overridden.is_empty()).then(|| {
+        format!(
+            "command-line flags override the profile's {}",
+            overridden.join(", ")
+        )
+    })
+}
+
 /// Centralized profile resolution:
 /// 1. If `--context` or `current` is set, use that ContextProfile.
 /// 2. Otherwise fall back to CLI flags (and error if missing).
@@ -131,25 +635,27 @@ fn resolve_profile(
     cli: &cli::ConnectArgs,
     ctx: &context::Context,
 ) -> Result<model::ContextProfile, String> {
-    if let Some(profile) = ctx.profile().map_err(|e| format!("error: {e}"))? {
-        let mut profile = profile.clone();
-        if !cli.urls.is_empty() {
-            profile.urls = cli.urls.clone();
-        }
-        if let Some(user) = cli.user.as_deref() {
-            profile.user = user.to_string();
-        }
-        if let Some(password) = cli.password.clone() {
-            profile.password = password;
+    // Keyring читаем, только если пароль не передан иначе: он может спрашивать разрешение
+    let profile = ctx
+        .profile_without_password()
+        .map_err(|e| format!("error: {e}"))?;
+    if let Some((name, mut profile)) = profile {
+        if let Some(message) = override_profile(cli, &mut profile) {
+            diagnostics::note(message);
         }
         if cli.interactive_password {
-            let user = &profile.user;
-            let password = rpassword::prompt_password(format!("ClickHouse {user} password: "))
-                .map_err(|e| format!("read password from prompt: {e}"))?;
-            profile.password = secrecy::SecretString::new(password.into());
+            profile.password = cli::prompt_password(&profile.user)?;
+        } else if let Some(password) = cli.password.clone() {
+            profile.password = password;
+        } else {
+            profile.password = ctx
+                .profile_password(name)
+                .map_err(|e| format!("error: {e}"))?;
         }
-        if let Some(_) = cli.accept_invalid_certificate {
-            profile.accept_invalid_certificate = true
+        if profile.urls.is_empty() {
+            return Err(
+                "no ClickHouse URLs configured: the profile has no `urls`, supply `--url`".into(),
+            );
         }
         return Ok(profile);
     };
@@ -162,20 +668,92 @@ fn resolve_profile(
         .user
         .clone()
         .ok_or("missing `--user`: supply it or set a context")?;
-    let password = cli
-        .password
-        .clone()
-        .unwrap_or(secrecy::SecretString::new("".to_string().into()));
-    let accept_invalid_certificate = if let Some(_) = cli.accept_invalid_certificate {
-        true
-    } else {
-        false
+    let password = match cli.interactive_password {
+        true => cli::prompt_password(&user)?,
+        false => cli
+            .password
+            .clone()
+            .unwrap_or(secrecy::SecretString::new("".to_string().into())),
     };
+    let accept_invalid_certificate = cli.accept_invalid_certificate.is_some();
 
     Ok(model::ContextProfile {
         urls: cli.urls.clone(),
         user,
         password,
         accept_invalid_certificate,
+        tls_server_name: cli.tls_server_name.clone(),
+        labels: Default::default(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::ExposeSecret;
+
+    fn connect_args(args: &[&str]) -> cli::ConnectArgs {
+        let args = ["clickcheck", "errors"].iter().chain(args);
+        match CliArgs::try_parse_from(args).unwrap().command {
+            Command::Errors { conn, .. } => conn,
+            _ => unreachable!(),
+        }
+    }
+
+    // Конфиг с текущим профилем `prod`, пароль которого лежит в keyring
+    fn context_with_profile(dir: &tempfile::TempDir) -> context::Context {
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+            current = "prod"
+
+            [profiles.prod]
+            user = "analyst"
+            urls = ["http://prod:8123"]
+            accept_invalid_certificate = false
+            "#,
+        )
+        .unwrap();
+        context::Context::new(Some(&path), None, Some("clickcheck-test")).unwrap()
+    }
+
+    #[test]
+    fn env_password_skips_keyring() {
+        // В mock-хранилище нет ни одной записи: любое чтение keyring завершится ошибкой
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = context_with_profile(&dir);
+
+        assert!(resolve_profile(&connect_args(&[]), &ctx).is_err());
+
+        std::env::set_var("CLICKCHECK_PASSWORD", "from-env");
+        let conn = connect_args(&[]);
+        std::env::remove_var("CLICKCHECK_PASSWORD");
+        let profile = resolve_profile(&conn, &ctx).unwrap();
+        assert_eq!(profile.password.expose_secret(), "from-env");
+        assert_eq!(profile.user, "analyst");
+    }
+
+    #[test]
+    fn override_note_only_for_conflicting_flags() {
+        let profile = || model::ContextProfile {
+            user: "analyst".to_string(),
+            urls: vec!["http://prod:8123".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(override_profile(&connect_args(&[]), &mut profile()), None);
+        let same = connect_args(&["-U", "http://prod:8123", "--user", "analyst"]);
+        assert_eq!(override_profile(&same, &mut profile()), None);
+
+        let conflicting = connect_args(&["-U", "http://staging:8123", "--user", "admin"]);
+        let mut overridden = profile();
+        assert_eq!(
+            override_profile(&conflicting, &mut overridden).as_deref(),
+            Some("command-line flags override the profile's urls, user")
+        );
+        assert_eq!(overridden.urls, ["http://staging:8123"]);
+        assert_eq!(overridden.user, "admin");
+    }
+}
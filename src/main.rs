@@ -4,6 +4,6 @@
 async fn main() {
     if let Err(err) = clickcheck::run().await {
         eprintln!("Error: {err}");
-        std::process::exit(1);
+        std::process::exit(err.exit_code());
     }
 }
@@ -1,89 +1,793 @@
 //! Handles output formatting and printing for different data types in the CLI.
 //!
-//! Supports output formats: plain text (human-readable), JSON, and YAML.
+//! Supports output formats: plain text (human-readable), JSON, YAML, CSV, Prometheus
+//! metrics (`queries`, `total` and `errors` only) and, with the `xml` feature, XML.
+//!
+//! Several formats can be rendered in one run, see [`Output`] for where each of them lands.
+#[cfg(feature = "schema")]
+use crate::model::SchemaKind;
 use crate::model::{
-    Error, OutputFormat as Format, PrintableContextProfile, QueryLog, QueryLogExtended,
-    QueryLogTotal,
+    BelowMinCount, Benchmark, BuildInfo, Error, ErrorDelta, ErrorsFilter, FingerprintFormat,
+    Health, HistoryRecord, ImpactTerm, ImpactWeights, Merge, Mutation, NodePing, NodeQueries,
+    NodeQuery, NodeTotal, Output, OutputFormat as Format, Part, PrintableContextProfile,
+    QueriesFilter, QueryColumn, QueryLog, QueryLogExtended, QueryLogTotal, QueryRate, ReplicaLag,
+    ReplicatedTable, Rollups, SettingsDiff, Timings, TotalBucket,
 };
 use serde::Serialize;
+use std::time::Duration;
 
+mod csv;
+mod pager;
+mod prometheus;
 mod text;
 
+// Печатает текстовый вывод в stdout, через pager если он включён
+fn print_text(rendered: &str, out: &Output) {
+    pager::page(rendered, out.pager);
+}
+
 // Вспомогательная функция для сериализации и печати
-fn serialize_and_print<T: Serialize + ?Sized>(data: &T, format: Format, data_description: &str) {
-    match format {
-        Format::Json => match serde_json::to_string_pretty(data) {
-            Ok(json) => println!("{json}"),
-            Err(err) => eprintln!("Failed to serialize {data_description} to JSON: {err}"),
-        },
-        Format::Yaml => match serde_yaml::to_string(data) {
-            Ok(yaml) => println!("{yaml}"),
-            Err(err) => eprintln!("Failed to serialize {data_description} to YAML: {err}"),
-        },
+fn serialize_and_print<T: Serialize + ?Sized>(
+    data: &T,
+    format: Format,
+    out: &Output,
+    data_description: &str,
+) {
+    let serialized = match format {
+        Format::Json => serde_json::to_string_pretty(data)
+            .map_err(|err| format!("Failed to serialize {data_description} to JSON: {err}")),
+        Format::Yaml => serde_yaml::to_string(data)
+            .map_err(|err| format!("Failed to serialize {data_description} to YAML: {err}")),
+        Format::Csv => csv::to_csv(data)
+            .map_err(|err| format!("Failed to serialize {data_description} to CSV: {err}")),
+        #[cfg(feature = "xml")]
+        Format::Xml => to_xml(data, data_description)
+            .map_err(|err| format!("Failed to serialize {data_description} to XML: {err}")),
+        Format::Prometheus => Err(format!(
+            "Prometheus output is not supported for {data_description}, only for the plain `queries`, `total` and `errors` results"
+        )),
         Format::Text => {
             // Эта ветка не должна достигаться, если функция используется правильно,
             // так как Text формат обрабатывается отдельно.
-            eprintln!(
+            Err(format!(
                 "Error: serialize_and_print called with Text format for {data_description}. This should be handled separately."
-            );
+            ))
         }
+    };
+    match serialized {
+        Ok(serialized) => write_serialized(serialized, format, out, data_description),
+        Err(err) => eprintln!("{err}"),
     }
 }
 
-pub fn print_query_extended(query: &QueryLogExtended, format: Format) {
+// Пишет готовый структурированный вывод в файл формата или в stdout
+fn write_serialized(serialized: String, format: Format, out: &Output, data_description: &str) {
+    match out.file_for(format) {
+        Some(path) => {
+            let mut serialized = serialized;
+            if !serialized.ends_with('\n') {
+                serialized.push('\n');
+            }
+            if let Err(err) = std::fs::write(&path, serialized) {
+                eprintln!(
+                    "Failed to write {data_description} to {}: {err}",
+                    path.display()
+                );
+            }
+        }
+        // CSV и Prometheus уже заканчиваются переводом строки, лишняя пустая
+        // строка стала бы записью CSV
+        None if matches!(format, Format::Csv | Format::Prometheus) => print!("{serialized}"),
+        None => println!("{serialized}"),
+    }
+}
+
+/// Serializes `data` as an indented XML document.
+///
+/// The root element is `data_description` in snake case, e.g. `<top_queries>`.
+#[cfg(feature = "xml")]
+fn to_xml<T: Serialize + ?Sized>(
+    data: &T,
+    data_description: &str,
+) -> Result<String, quick_xml::SeError> {
+    let root = data_description.replace([' ', '-'], "_");
+    let mut xml = String::new();
+    let mut serializer = quick_xml::se::Serializer::with_root(&mut xml, Some(&root))?;
+    serializer.indent(' ', 2);
+    data.serialize(serializer)?;
+    Ok(xml)
+}
+
+// Сериализует список строк: XML не поддерживает последовательность на верхнем уровне,
+// поэтому там каждая строка оборачивается в элемент `<row>`
+fn serialize_rows_and_print<T: Serialize>(
+    rows: &[T],
+    format: Format,
+    out: &Output,
+    data_description: &str,
+) {
     match format {
-        Format::Text => text::print_query_extended(query),
-        Format::Json | Format::Yaml => serialize_and_print(query, format, "top queries"),
+        #[cfg(feature = "xml")]
+        Format::Xml => {
+            #[derive(Serialize)]
+            struct XmlRows<'a, T> {
+                row: &'a [T],
+            }
+            serialize_and_print(&XmlRows { row: rows }, format, out, data_description)
+        }
+        _ => serialize_and_print(rows, format, out, data_description),
+    }
+}
+
+/// Prints the details of one fingerprint of `inspect`.
+///
+/// With `explain_impact`, text output adds an "Impact breakdown" table and structured
+/// formats an `impact_breakdown` list, see [`QueryLogExtended::impact_breakdown`].
+pub fn print_query_extended(query: &QueryLogExtended, explain_impact: bool, out: &Output) {
+    let breakdown = explain_impact.then(|| query.impact_breakdown(&ImpactWeights::default()));
+    for &format in &out.formats {
+        match (format, &breakdown) {
+            (Format::Text, None) => print_text(
+                &text::render_query_extended(query, out.fingerprint_format),
+                out,
+            ),
+            (Format::Text, Some(breakdown)) => {
+                let rendered = format!(
+                    "{}\nImpact breakdown:\n{}",
+                    text::render_query_extended(query, out.fingerprint_format),
+                    text::render_impact_breakdown(breakdown, out.raw_impact),
+                );
+                print_text(&rendered, out)
+            }
+            (_, None) => serialize_and_print(query, format, out, "inspect"),
+            (_, Some(impact_breakdown)) => {
+                #[derive(Serialize)]
+                struct ExplainedQuery<'a> {
+                    #[serde(flatten)]
+                    query: &'a QueryLogExtended,
+                    impact_breakdown: &'a [ImpactTerm],
+                }
+                let wrapper = ExplainedQuery {
+                    query,
+                    impact_breakdown,
+                };
+                serialize_and_print(&wrapper, format, out, "inspect")
+            }
+        }
     }
 }
 
+/// Formats an impact like the text tables do, see `--raw-impact`.
+pub fn format_impact(impact: u64, raw: bool) -> String {
+    text::format_impact(impact, raw)
+}
+
+/// Renders [`QueryLogExtended`] as human readable text, without printing it.
+pub fn render_query_extended_text(
+    query: &QueryLogExtended,
+    fingerprint_format: FingerprintFormat,
+) -> String {
+    text::render_query_extended(query, fingerprint_format)
+}
+
 /// Prints the top heaviest queries in the selected output format.
 ///
+/// With any of the `rollups`, text output adds a separate "Top users", "Top tables"
+/// or "Top databases" section for each, and structured formats wrap the lists in
+/// an object with `queries`, `users`, `tables` and `databases` keys.
+///
 /// - `queries`: A slice of query logs, typically sorted by weight.
-/// - `format`: Output format (Text, JSON, or YAML).
-pub fn print_top_queries(queries: &[QueryLog], format: Format) {
-    match format {
-        Format::Text => text::print_weighted_queries_table(queries),
-        Format::Json | Format::Yaml => serialize_and_print(queries, format, "top queries"),
+/// - `rollups`: Users, tables and databases ranked by attributed impact, if requested.
+/// - `columns`: Columns of the text table, structured formats always have every field.
+/// - `rate`: Show impacts per hour, see [`QueryLog::rate`]. Structured formats add
+///   `*_per_hour` fields to every query.
+/// - `filter`: The effective filter, summarized in a text footer.
+/// - `out`: Output formats and destinations.
+pub fn print_top_queries(
+    queries: &[QueryLog],
+    rollups: &Rollups,
+    columns: &[QueryColumn],
+    rate: bool,
+    filter: &QueriesFilter,
+    out: &Output,
+) {
+    for &format in &out.formats {
+        match format {
+            Format::Text if rollups.is_empty() => {
+                let rendered = format!(
+                    "{}{}",
+                    text::render_weighted_queries_table(
+                        queries,
+                        columns,
+                        rate,
+                        out.raw_impact,
+                        out.fingerprint_format
+                    ),
+                    text::render_queries_filter(filter),
+                );
+                print_text(&rendered, out)
+            }
+            Format::Text => {
+                let mut rendered = format!(
+                    "Top queries:\n{}",
+                    text::render_weighted_queries_table(
+                        queries,
+                        columns,
+                        rate,
+                        out.raw_impact,
+                        out.fingerprint_format
+                    )
+                );
+                if let Some(users) = &rollups.users {
+                    rendered += &format!(
+                        "\nTop users:\n{}",
+                        text::render_user_impacts_table(users, out.raw_impact)
+                    );
+                }
+                if let Some(tables) = &rollups.tables {
+                    rendered += &format!(
+                        "\nTop tables:\n{}",
+                        text::render_table_impacts_table(tables, out.raw_impact)
+                    );
+                }
+                if let Some(databases) = &rollups.databases {
+                    rendered += &format!(
+                        "\nTop databases:\n{}",
+                        text::render_database_impacts_table(databases, out.raw_impact)
+                    );
+                }
+                rendered += &text::render_queries_filter(filter);
+                print_text(&rendered, out);
+            }
+            Format::Prometheus => write_serialized(
+                prometheus::render_queries(queries),
+                format,
+                out,
+                "top queries",
+            ),
+            _ if rate => {
+                let rated: Vec<_> = queries
+                    .iter()
+                    .map(|query| RatedQuery {
+                        query,
+                        rate: query.rate(),
+                    })
+                    .collect();
+                serialize_queries(&rated, rollups, filter, format, out)
+            }
+            _ => serialize_queries(queries, rollups, filter, format, out),
+        }
+    }
+}
+
+/// Prints the top queries of every node of `queries --by-node`.
+///
+/// Text output has a table per node, structured formats an object keyed by node
+/// URL, in node order. XML has no such keys, so it prints `node` and `queries` rows.
+///
+/// - `nodes`: Top queries of every node, with redacted URLs.
+/// - `columns`, `rate`: As in [`print_top_queries`].
+/// - `filter`: The effective filter, summarized in a text footer.
+/// - `out`: Output formats and destinations.
+pub fn print_queries_by_node(
+    nodes: &[NodeQueries],
+    columns: &[QueryColumn],
+    rate: bool,
+    filter: &QueriesFilter,
+    out: &Output,
+) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => {
+                let mut rendered = String::new();
+                for (i, node) in nodes.iter().enumerate() {
+                    if i > 0 {
+                        rendered.push('\n');
+                    }
+                    rendered += &format!(
+                        "Node {}:\n{}",
+                        node.node,
+                        text::render_weighted_queries_table(
+                            &node.queries,
+                            columns,
+                            rate,
+                            out.raw_impact,
+                            out.fingerprint_format
+                        )
+                    );
+                }
+                rendered += &text::render_queries_filter(filter);
+                print_text(&rendered, out);
+            }
+            #[cfg(feature = "xml")]
+            Format::Xml => serialize_rows_and_print(nodes, format, out, "queries by node"),
+            // CSV плоский: по строке на запрос узла, URL узла в первой колонке
+            Format::Csv => {
+                let rows: Vec<_> = nodes
+                    .iter()
+                    .flat_map(|node| {
+                        node.queries.iter().map(|query| NodeRow {
+                            node: &node.node,
+                            query,
+                            rate: rate.then(|| query.rate()),
+                        })
+                    })
+                    .collect();
+                serialize_and_print(&rows, format, out, "queries by node")
+            }
+            _ if rate => {
+                let rated: Vec<_> = nodes
+                    .iter()
+                    .map(|node| {
+                        let queries: Vec<_> = node
+                            .queries
+                            .iter()
+                            .map(|query| RatedQuery {
+                                query,
+                                rate: query.rate(),
+                            })
+                            .collect();
+                        (node.node.as_str(), queries)
+                    })
+                    .collect();
+                serialize_and_print(&ByNode(&rated), format, out, "queries by node")
+            }
+            _ => {
+                let rows: Vec<_> = nodes
+                    .iter()
+                    .map(|node| (node.node.as_str(), &node.queries))
+                    .collect();
+                serialize_and_print(&ByNode(&rows), format, out, "queries by node")
+            }
+        }
+    }
+}
+
+/// Prints the queries of all nodes ranked together, see `queries --by-node --node-column`.
+///
+/// Text output adds a Node column in front of the selected ones, structured formats
+/// print rows with a `node` field.
+///
+/// - `rows`: Queries with the URL of their node, typically sorted by weight.
+/// - `columns`, `rate`: As in [`print_top_queries`].
+/// - `filter`: The effective filter, summarized in a text footer.
+/// - `out`: Output formats and destinations.
+pub fn print_node_queries(
+    rows: &[NodeQuery],
+    columns: &[QueryColumn],
+    rate: bool,
+    filter: &QueriesFilter,
+    out: &Output,
+) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => {
+                let rendered = format!(
+                    "{}{}",
+                    text::render_node_queries_table(
+                        rows,
+                        columns,
+                        rate,
+                        out.raw_impact,
+                        out.fingerprint_format
+                    ),
+                    text::render_queries_filter(filter),
+                );
+                print_text(&rendered, out);
+            }
+            _ => {
+                let rows: Vec<_> = rows
+                    .iter()
+                    .map(|row| NodeRow {
+                        node: &row.node,
+                        query: &row.query,
+                        rate: rate.then(|| row.query.rate()),
+                    })
+                    .collect();
+                serialize_rows_and_print(&rows, format, out, "queries by node")
+            }
+        }
+    }
+}
+
+// Запрос с URL своего узла, плоская строка `--by-node` вывода
+#[derive(Serialize)]
+struct NodeRow<'a> {
+    node: &'a str,
+    #[serde(flatten)]
+    query: &'a QueryLog,
+    #[serde(flatten)]
+    rate: Option<QueryRate>,
+}
+
+// Объект URL узла → строки, в порядке узлов
+struct ByNode<'a, T>(&'a [(&'a str, T)]);
+
+impl<T: Serialize> Serialize for ByNode<'_, T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(node, rows)| (node, rows)))
     }
 }
 
+// Запрос вместе с весами в час, для `queries --rate`
+#[derive(Serialize)]
+struct RatedQuery<'a> {
+    #[serde(flatten)]
+    query: &'a QueryLog,
+    #[serde(flatten)]
+    rate: QueryRate,
+}
+
+// Структурированный вывод `queries`: строки, либо объект с ними, разрезами
+// и весами, если они не по умолчанию
+fn serialize_queries<T: Serialize>(
+    queries: &[T],
+    rollups: &Rollups,
+    filter: &QueriesFilter,
+    format: Format,
+    out: &Output,
+) {
+    let impact_weights = (!filter.impact_weights.is_default()).then_some(&filter.impact_weights);
+    if rollups.is_empty() && impact_weights.is_none() {
+        return serialize_rows_and_print(queries, format, out, "top queries");
+    }
+
+    #[derive(Serialize)]
+    struct QueriesWrapper<'a, T> {
+        queries: &'a [T],
+        #[serde(flatten)]
+        rollups: &'a Rollups,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        impact_weights: Option<&'a ImpactWeights>,
+    }
+    let wrapper = QueriesWrapper {
+        queries,
+        rollups,
+        impact_weights,
+    };
+    serialize_and_print(&wrapper, format, out, "top queries")
+}
+
+// Структурированный вывод команды `total`
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct TotalQueries<'a> {
+    #[serde(flatten)]
+    total: &'a QueryLogTotal,
+    nodes_queried: usize,
+    impact_weights: ImpactWeights,
+}
+
 /// Prints the total weights of queries aggregated over a period of time.
 ///
 /// - `queries`: A reference to the total query statistics.
-/// - `format`: Output format (Text, JSON, or YAML).
-pub fn print_total_queries(queries: &QueryLogTotal, format: Format) {
-    match format {
-        Format::Text => text::print_total_queries_table(queries),
-        Format::Json | Format::Yaml => serialize_and_print(queries, format, "total queries"),
+/// - `nodes_queried`: Number of ClickHouse nodes the totals were aggregated from.
+/// - `filter`: The effective filter, summarized in a text footer.
+/// - `out`: Output formats and destinations.
+pub fn print_total_queries(
+    queries: &QueryLogTotal,
+    nodes_queried: usize,
+    filter: &QueriesFilter,
+    out: &Output,
+) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => {
+                let rendered = format!(
+                    "{}Nodes queried: {nodes_queried}\n{}",
+                    text::render_total_queries_table(queries, out.raw_impact),
+                    text::render_queries_filter(filter),
+                );
+                print_text(&rendered, out);
+            }
+            Format::Prometheus => write_serialized(
+                prometheus::render_total(queries, nodes_queried),
+                format,
+                out,
+                "total queries",
+            ),
+            _ => {
+                let wrapper = TotalQueries {
+                    total: queries,
+                    nodes_queried,
+                    impact_weights: filter.impact_weights,
+                };
+                serialize_and_print(&wrapper, format, out, "total queries")
+            }
+        }
+    }
+}
+
+/// Prints the records of `total --history`, oldest first.
+///
+/// Text output adds a sparkline of the total impact, structured formats print the
+/// records as they are.
+pub fn print_history(records: &[HistoryRecord], out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_history(records, out.raw_impact), out),
+            _ => serialize_rows_and_print(records, format, out, "history"),
+        }
+    }
+}
+
+/// Prints the time series of `total --breakdown-by`, oldest bucket first.
+///
+/// Text output adds a sparkline of the total impact and the filter footer,
+/// structured formats print the buckets as they are.
+pub fn print_total_buckets(buckets: &[TotalBucket], filter: &QueriesFilter, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => {
+                let rendered = format!(
+                    "{}{}",
+                    text::render_total_buckets(buckets, out.raw_impact),
+                    text::render_queries_filter(filter),
+                );
+                print_text(&rendered, out);
+            }
+            _ => serialize_rows_and_print(buckets, format, out, "total buckets"),
+        }
+    }
+}
+
+/// Prints the execution times of an `--iterations` run.
+pub fn print_benchmark(benchmark: &Benchmark, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_benchmark(benchmark), out),
+            _ => serialize_and_print(benchmark, format, out, "benchmark"),
+        }
+    }
+}
+
+/// Prints the per-node totals of `total --compare-nodes`.
+///
+/// Text output marks outliers and explains the `factor` in a footer, structured
+/// formats print the rows as they are.
+///
+/// - `nodes`: Totals of every node, compared with the median node.
+/// - `factor`: Deviation from the median flagging a node as an outlier.
+/// - `filter`: The effective filter, summarized in a text footer.
+/// - `out`: Output formats and destinations.
+pub fn print_node_totals(nodes: &[NodeTotal], factor: f64, filter: &QueriesFilter, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => {
+                let rendered = format!(
+                    "{}Outliers deviate from the median node by more than {factor}x\n{}",
+                    text::render_node_totals_table(nodes, out.raw_impact),
+                    text::render_queries_filter(filter),
+                );
+                print_text(&rendered, out);
+            }
+            _ => serialize_rows_and_print(nodes, format, out, "node totals"),
+        }
+    }
+}
+
+/// Prints the occurrences since the previous poll of `errors --watch --diff`.
+///
+/// Text output flags new and spiking codes, structured formats print the rows
+/// as they are.
+pub fn print_error_deltas(deltas: &[ErrorDelta], filter: &ErrorsFilter, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => {
+                let rendered = format!(
+                    "{}{}",
+                    text::render_error_deltas_table(deltas),
+                    text::render_errors_filter(filter),
+                );
+                print_text(&rendered, out)
+            }
+            _ => serialize_rows_and_print(deltas, format, out, "error deltas"),
+        }
     }
 }
 
 /// Prints the most frequent errors observed in `system.errors`.
 ///
+/// With `below_min_count`, text output adds a footer with the codes dropped by
+/// `--min-count`, structured formats wrap both in an object with `errors` and
+/// `below_min_count` keys.
+///
 /// - `errors`: A slice of aggregated errors.
-/// - `format`: Output format (Text, JSON, or YAML).
-pub fn print_top_errors(errors: &[Error], format: Format) {
-    match format {
-        Format::Text => text::print_errors_table(errors),
-        Format::Json | Format::Yaml => serialize_and_print(errors, format, "top errors"),
+/// - `below_min_count`: Totals of the codes dropped by `--min-count`, if set.
+/// - `filter`: The effective filter, summarized in a text footer.
+/// - `out`: Output formats and destinations.
+pub fn print_top_errors(
+    errors: &[Error],
+    below_min_count: Option<&BelowMinCount>,
+    filter: &ErrorsFilter,
+    out: &Output,
+) {
+    for &format in &out.formats {
+        match (format, below_min_count) {
+            (Format::Text, None) => {
+                let rendered = format!(
+                    "{}{}",
+                    text::render_errors_table(errors),
+                    text::render_errors_filter(filter),
+                );
+                print_text(&rendered, out)
+            }
+            (Format::Text, Some(below)) => {
+                let rendered = format!(
+                    "{}{}{}",
+                    text::render_errors_table(errors),
+                    text::render_below_min_count(below),
+                    text::render_errors_filter(filter),
+                );
+                print_text(&rendered, out);
+            }
+            (Format::Prometheus, _) => {
+                write_serialized(prometheus::render_errors(errors), format, out, "top errors")
+            }
+            (_, None) => serialize_rows_and_print(errors, format, out, "top errors"),
+            (_, Some(below_min_count)) => {
+                #[derive(Serialize)]
+                struct ErrorsWrapper<'a> {
+                    errors: &'a [Error],
+                    below_min_count: &'a BelowMinCount,
+                }
+                let wrapper = ErrorsWrapper {
+                    errors,
+                    below_min_count,
+                };
+                serialize_and_print(&wrapper, format, out, "top errors")
+            }
+        }
+    }
+}
+
+/// Prints the combined cluster overview of the `health` command.
+///
+/// Text output renders stacked sections, structured formats a single object
+/// with `queries`, `errors` and `total` keys, or with `--output-dir` one file per key.
+///
+/// - `health`: The overview to print.
+/// - `filter`: The effective filter, summarized in a text footer.
+/// - `out`: Output formats and destinations.
+pub fn print_health(health: &Health, filter: &QueriesFilter, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => {
+                let rendered = format!(
+                    "Top queries:\n{}\nTop errors:\n{}\nTotal:\n{}{}",
+                    text::render_weighted_queries_table(
+                        &health.queries,
+                        &QueryColumn::DEFAULT,
+                        false,
+                        out.raw_impact,
+                        out.fingerprint_format,
+                    ),
+                    text::render_errors_table(&health.errors),
+                    text::render_total_queries_table(&health.total, out.raw_impact),
+                    text::render_queries_filter(filter),
+                );
+                print_text(&rendered, out);
+            }
+            _ if out.dir.is_some() => {
+                let [queries, errors, total] = Health::SECTIONS;
+                serialize_rows_and_print(&health.queries, format, &out.section(queries), queries);
+                serialize_rows_and_print(&health.errors, format, &out.section(errors), errors);
+                serialize_and_print(&health.total, format, &out.section(total), total);
+            }
+            _ => serialize_and_print(health, format, out, "health"),
+        }
+    }
+}
+
+/// Prints the merges in progress of the `merges` command.
+///
+/// - `merges`: Merges of every node, typically sorted by the `--sort-by` metric.
+/// - `out`: Output formats and destinations.
+pub fn print_merges(merges: &[Merge], out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_merges_table(merges), out),
+            _ => serialize_rows_and_print(merges, format, out, "merges"),
+        }
+    }
+}
+
+/// Prints the unfinished mutations of the `mutations` command.
+///
+/// - `mutations`: Mutations of every node, typically sorted by the `--sort-by` metric.
+/// - `out`: Output formats and destinations.
+pub fn print_mutations(mutations: &[Mutation], out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_mutations_table(mutations), out),
+            _ => serialize_rows_and_print(mutations, format, out, "mutations"),
+        }
+    }
+}
+
+/// Prints every replica of `replication --by-node`.
+///
+/// - `replicas`: Replicas of every node, typically sorted by delay.
+/// - `max_lag`: The `--max-lag` threshold, shown under the text table.
+/// - `out`: Output formats and destinations.
+pub fn print_replica_lags(replicas: &[ReplicaLag], max_lag: Duration, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_replica_lags_table(replicas, max_lag), out),
+            _ => serialize_rows_and_print(replicas, format, out, "replicas"),
+        }
+    }
+}
+
+/// Prints the replicated tables of the `replication` command.
+///
+/// - `tables`: Replicas aggregated per table, typically sorted by delay.
+/// - `max_lag`: The `--max-lag` threshold, shown under the text table.
+/// - `out`: Output formats and destinations.
+pub fn print_replicated_tables(tables: &[ReplicatedTable], max_lag: Duration, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_replicated_tables_table(tables, max_lag), out),
+            _ => serialize_rows_and_print(tables, format, out, "replicated tables"),
+        }
+    }
+}
+
+/// Prints the table storage of the `parts` command.
+///
+/// - `parts`: Tables summed across nodes, typically sorted by compressed size.
+/// - `out`: Output formats and destinations.
+pub fn print_parts(parts: &[Part], out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_parts_table(parts), out),
+            _ => serialize_rows_and_print(parts, format, out, "parts"),
+        }
+    }
+}
+
+/// Prints settings compared across cluster nodes.
+///
+/// - `diff`: Settings with their per-host values.
+/// - `out`: Output formats and destinations.
+pub fn print_settings_diff(diff: &SettingsDiff, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_settings_diff_table(diff), out),
+            _ => serialize_and_print(diff, format, out, "settings diff"),
+        }
+    }
+}
+
+/// Prints the latency of every node probed by the `ping` command.
+///
+/// - `pings`: Probe results of every node.
+/// - `out`: Output formats and destinations.
+pub fn print_ping(pings: &[NodePing], out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_ping_table(pings), out),
+            _ => serialize_rows_and_print(pings, format, out, "ping"),
+        }
     }
 }
 
 /// Prints a list of available context profile names.
 ///
 /// - `names`: Slice of context profile names.
-/// - `format`: Output format (Text, JSON, or YAML).
-pub fn print_context_list(names: &[String], format: Format) {
-    match format {
-        Format::Text => text::print_context_names_table(names),
-        Format::Json | Format::Yaml => {
-            #[derive(Serialize)]
-            struct ListWrapper<'a> {
-                profiles: &'a [String],
+/// - `out`: Output formats and destinations.
+pub fn print_context_list(names: &[String], out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_context_names_table(names), out),
+            _ => {
+                #[derive(Serialize)]
+                struct ListWrapper<'a> {
+                    profiles: &'a [String],
+                }
+                let wrapper = ListWrapper { profiles: names };
+                serialize_and_print(&wrapper, format, out, "context list")
             }
-            let wrapper = ListWrapper { profiles: names };
-            serialize_and_print(&wrapper, format, "context list")
         }
     }
 }
@@ -91,17 +795,19 @@ pub fn print_context_list(names: &[String], format: Format) {
 /// Prints the name of the currently active context profile, or an empty message if none is set.
 ///
 /// - `active`: Name of the active profile, or `None`.
-/// - `format`: Output format (Text, JSON, or YAML).
-pub fn print_context_current(active: Option<&str>, format: Format) {
-    match format {
-        Format::Text => text::print_context_current(active),
-        Format::Json | Format::Yaml => {
-            #[derive(Serialize)]
-            struct CurrentWrapper<'a> {
-                current: Option<&'a str>,
+/// - `out`: Output formats and destinations.
+pub fn print_context_current(active: Option<&str>, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_context_current(active), out),
+            _ => {
+                #[derive(Serialize)]
+                struct CurrentWrapper<'a> {
+                    current: Option<&'a str>,
+                }
+                let wrapper = CurrentWrapper { current: active };
+                serialize_and_print(&wrapper, format, out, "context current")
             }
-            let wrapper = CurrentWrapper { current: active };
-            serialize_and_print(&wrapper, format, "context current")
         }
     }
 }
@@ -109,19 +815,50 @@ pub fn print_context_current(active: Option<&str>, format: Format) {
 /// Prints the file path to the context configuration.
 ///
 /// - `path`: Path to the `config.toml`.
-/// - `format`: Output format (Text, JSON, or YAML).
-pub fn print_context_config_path(path: &std::path::PathBuf, format: Format) {
-    match format {
-        Format::Text => text::print_context_config_path(path),
-        Format::Json | Format::Yaml => {
-            #[derive(Serialize)]
-            struct ConfigPathWrapper<'a> {
-                config_path: &'a str,
+/// - `local_path`: Project-local config merged over it, if any.
+/// - `out`: Output formats and destinations.
+pub fn print_context_config_path(
+    path: &std::path::Path,
+    local_path: Option<&std::path::Path>,
+    out: &Output,
+) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_context_config_path(path, local_path), out),
+            _ => {
+                #[derive(Serialize)]
+                struct ConfigPathWrapper<'a> {
+                    config_path: &'a str,
+                    #[serde(skip_serializing_if = "Option::is_none")]
+                    local_config_path: Option<&'a str>,
+                }
+                let local_path = local_path.map(|p| p.to_string_lossy());
+                let wrapper = ConfigPathWrapper {
+                    config_path: &path.to_string_lossy(),
+                    local_config_path: local_path.as_deref(),
+                };
+                serialize_and_print(&wrapper, format, out, "context config-path")
+            }
+        }
+    }
+}
+
+/// Prints the changes a `context` command would make under `--dry-run`.
+///
+/// - `changes`: Human readable changes, in the order they would be applied.
+/// - `out`: Output formats and destinations.
+pub fn print_context_dry_run(changes: &[String], out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_context_dry_run(changes), out),
+            _ => {
+                #[derive(Serialize)]
+                struct DryRunWrapper<'a> {
+                    changes: &'a [String],
+                }
+                let wrapper = DryRunWrapper { changes };
+                serialize_and_print(&wrapper, format, out, "context dry-run")
             }
-            let wrapper = ConfigPathWrapper {
-                config_path: &path.to_string_lossy(),
-            };
-            serialize_and_print(&wrapper, format, "context config-path")
         }
     }
 }
@@ -129,10 +866,57 @@ pub fn print_context_config_path(path: &std::path::PathBuf, format: Format) {
 /// Prints a detailed description of a single context profile.
 ///
 /// - `profile`: The profile to print
-/// - `format`: Output format (Text, JSON, or YAML).
-pub fn print_context_profile(profile: &PrintableContextProfile, format: Format) {
-    match format {
-        Format::Text => text::print_context_profile(profile),
-        Format::Json | Format::Yaml => serialize_and_print(&profile, format, "context profile"),
+/// - `out`: Output formats and destinations.
+pub fn print_context_profile(profile: &PrintableContextProfile, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_context_profile(profile), out),
+            _ => serialize_and_print(&profile, format, out, "context profile"),
+        }
+    }
+}
+
+/// Prints the clickcheck version, with `verbose` also the build metadata.
+///
+/// Structured formats always include the full [`BuildInfo`].
+///
+/// - `info`: Build metadata of the running binary.
+/// - `verbose`: Show the build metadata in text output.
+/// - `out`: Output formats and destinations.
+pub fn print_version(info: &BuildInfo, verbose: bool, out: &Output) {
+    for &format in &out.formats {
+        match format {
+            Format::Text => print_text(&text::render_version(info, verbose), out),
+            _ => serialize_and_print(info, format, out, "version"),
+        }
+    }
+}
+
+/// Prints the JSON Schema of the JSON output of a command, derived from its models.
+///
+/// `queries` and `errors` are described without `--top-users`, `--group-by-table`
+/// and `--min-count`, which wrap the rows in an object.
+///
+/// - `kind`: The command whose output to describe.
+/// - `out`: Output destination, the schema itself is always JSON.
+#[cfg(feature = "schema")]
+pub fn print_schema(kind: SchemaKind, out: &Output) {
+    let schema = match kind {
+        SchemaKind::Queries => schemars::schema_for!(Vec<QueryLog>),
+        SchemaKind::Errors => schemars::schema_for!(Vec<Error>),
+        SchemaKind::Total => schemars::schema_for!(TotalQueries<'static>),
+        SchemaKind::Inspect => schemars::schema_for!(QueryLogExtended),
+    };
+    serialize_and_print(&schema, Format::Json, out, "schema")
+}
+
+/// Prints the `--timings` summary to stderr.
+///
+/// Always rendered as a text table, independent of the selected output format,
+/// so it never mixes into structured stdout output.
+pub fn print_timings(timings: &Timings) {
+    if timings.stages.is_empty() {
+        return;
     }
+    eprint!("{}", text::render_timings_table(timings));
 }
@@ -0,0 +1,72 @@
+//! Renders structured output as RFC 4180 CSV.
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Serializes `data` as CSV with a header row of field names.
+///
+/// A list becomes one record per element, any other value a single record.
+/// Nested lists and objects (e.g. `users`) are written as compact JSON in their cell,
+/// `null` as an empty cell. An empty list renders as an empty document.
+pub fn to_csv<T: Serialize + ?Sized>(data: &T) -> Result<String, serde_json::Error> {
+    let records = match serde_json::to_value(data)? {
+        Value::Array(values) => values.into_iter().map(into_record).collect(),
+        value => vec![into_record(value)],
+    };
+
+    // Заголовок — объединение полей всех записей в порядке появления,
+    // так как поля с skip_serializing_if есть не у каждой записи
+    let mut header: Vec<String> = Vec::new();
+    for record in &records {
+        for key in record.keys() {
+            if !header.contains(key) {
+                header.push(key.clone());
+            }
+        }
+    }
+    if header.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut csv = String::new();
+    push_record(&mut csv, header.iter().map(String::as_str));
+    for record in &records {
+        let cells: Vec<String> = header
+            .iter()
+            .map(|key| record.get(key).map(cell).unwrap_or_default())
+            .collect();
+        push_record(&mut csv, cells.iter().map(String::as_str));
+    }
+    Ok(csv)
+}
+
+fn into_record(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        value => Map::from_iter([("value".to_string(), value)]),
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+// Строки разделяются CRLF, как требует RFC 4180
+fn push_record<'a>(csv: &mut String, fields: impl Iterator<Item = &'a str>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            csv.push(',');
+        }
+        if field.contains([',', '"', '\r', '\n']) {
+            csv.push('"');
+            csv.push_str(&field.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(field);
+        }
+    }
+    csv.push_str("\r\n");
+}
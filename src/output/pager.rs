@@ -0,0 +1,47 @@
+//! Pipes long text output through a pager.
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+const DEFAULT_PAGER: &str = "less -RFX";
+
+/// Prints `rendered` to stdout, through `$PAGER` (or `less -RFX`) when paging is `enabled`,
+/// stdout is a terminal and the text doesn't fit on one screen.
+///
+/// Falls back to plain printing if the pager can't be started.
+pub fn page(rendered: &str, enabled: bool) {
+    if enabled
+        && std::io::stdout().is_terminal()
+        && exceeds_terminal(rendered)
+        && spawn_pager(rendered).is_ok()
+    {
+        return;
+    }
+    print!("{rendered}");
+}
+
+fn exceeds_terminal(rendered: &str) -> bool {
+    termsize::get().is_some_and(|size| rendered.lines().count() >= usize::from(size.rows))
+}
+
+fn spawn_pager(rendered: &str) -> std::io::Result<()> {
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+    let mut parts = pager.split_whitespace();
+    let program = parts.next().unwrap_or(DEFAULT_PAGER);
+
+    let mut child = Command::new(program)
+        .args(parts)
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user may quit the pager before reading everything,
+        // a broken pipe here is expected and not an error.
+        let _ = stdin.write_all(rendered.as_bytes());
+    }
+    child.wait()?;
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+//! Renders results in the Prometheus text exposition format, e.g. for the node_exporter
+//! textfile collector.
+//!
+//! Every metric is a gauge of the last run, see
+//! https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format
+use crate::model::{Error, QueryLog, QueryLogTotal};
+
+/// Gauges of the `total` command, without labels.
+pub fn render_total(total: &QueryLogTotal, nodes_queried: usize) -> String {
+    let mut metrics = String::new();
+    for (name, help, value) in [
+        (
+            "clickcheck_queries_count",
+            "Number of SELECT queries in the analyzed period.",
+            total.queries_count,
+        ),
+        (
+            "clickcheck_io_impact",
+            "I/O impact of all queries.",
+            total.io_impact,
+        ),
+        (
+            "clickcheck_network_impact",
+            "Network impact of all queries.",
+            total.network_impact,
+        ),
+        (
+            "clickcheck_cpu_impact",
+            "CPU impact of all queries.",
+            total.cpu_impact,
+        ),
+        (
+            "clickcheck_memory_impact",
+            "Memory impact of all queries.",
+            total.memory_impact,
+        ),
+        (
+            "clickcheck_time_impact",
+            "Time impact of all queries.",
+            total.time_impact,
+        ),
+        (
+            "clickcheck_total_impact",
+            "Total impact of all queries.",
+            total.total_impact,
+        ),
+        (
+            "clickcheck_nodes_queried",
+            "Number of ClickHouse nodes the totals were aggregated from.",
+            nodes_queried as u64,
+        ),
+    ] {
+        push_family(&mut metrics, name, help, [(Vec::new(), value)]);
+    }
+    metrics
+}
+
+/// Gauges of the `queries` command, labeled by the hex `fingerprint`.
+pub fn render_queries(queries: &[QueryLog]) -> String {
+    type Impact = fn(&QueryLog) -> u64;
+    let impacts: [(&str, &str, Impact); 6] = [
+        (
+            "clickcheck_query_total_impact",
+            "Total impact of the query fingerprint.",
+            |q| q.total_impact,
+        ),
+        (
+            "clickcheck_query_io_impact",
+            "I/O impact of the query fingerprint.",
+            |q| q.io_impact,
+        ),
+        (
+            "clickcheck_query_network_impact",
+            "Network impact of the query fingerprint.",
+            |q| q.network_impact,
+        ),
+        (
+            "clickcheck_query_cpu_impact",
+            "CPU impact of the query fingerprint.",
+            |q| q.cpu_impact,
+        ),
+        (
+            "clickcheck_query_memory_impact",
+            "Memory impact of the query fingerprint.",
+            |q| q.memory_impact,
+        ),
+        (
+            "clickcheck_query_time_impact",
+            "Time impact of the query fingerprint.",
+            |q| q.time_impact,
+        ),
+    ];
+
+    let mut metrics = String::new();
+    for (name, help, impact) in impacts {
+        let samples = queries.iter().map(|query| {
+            let fingerprint = format!("{:#x}", query.normalized_query_hash);
+            (vec![("fingerprint", fingerprint)], impact(query))
+        });
+        push_family(&mut metrics, name, help, samples);
+    }
+    metrics
+}
+
+/// Gauges of the `errors` command, labeled by error `code` and `name`.
+pub fn render_errors(errors: &[Error]) -> String {
+    let labels = |error: &Error| {
+        vec![
+            ("code", error.code.to_string()),
+            ("name", error.name.clone()),
+        ]
+    };
+
+    let mut metrics = String::new();
+    push_family(
+        &mut metrics,
+        "clickcheck_error_count",
+        "Occurrences of the ClickHouse error code, summed across nodes.",
+        errors.iter().map(|error| (labels(error), error.count)),
+    );
+    push_family(
+        &mut metrics,
+        "clickcheck_error_last_time_seconds",
+        "Unix time of the latest occurrence of the ClickHouse error code.",
+        errors.iter().map(|error| {
+            let last = u64::try_from(error.last_error_time.unix_timestamp()).unwrap_or_default();
+            (labels(error), last)
+        }),
+    );
+    metrics
+}
+
+// Одно семейство метрик: строки HELP и TYPE, затем по строке на сэмпл
+fn push_family(
+    metrics: &mut String,
+    name: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (Vec<(&'static str, String)>, u64)>,
+) {
+    metrics.push_str(&format!("# HELP {name} {}\n", escape_help(help)));
+    metrics.push_str(&format!("# TYPE {name} gauge\n"));
+    for (labels, value) in samples {
+        metrics.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<_> = labels
+                .iter()
+                .map(|(label, value)| format!("{label}=\"{}\"", escape_label_value(value)))
+                .collect();
+            metrics.push_str(&format!("{{{}}}", labels.join(",")));
+        }
+        metrics.push_str(&format!(" {value}\n"));
+    }
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
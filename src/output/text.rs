@@ -6,13 +6,52 @@ use time::format_description::well_known::Rfc3339;
 
 const MAX_COLUMN_LEN: usize = 50;
 
+/// Format an impact for display.
+///
+/// Impacts are unitless scores, so instead of byte units they are abbreviated with
+/// k/M/B/T suffixes (e.g. `1.23M`), or with `raw` shown exactly with grouped
+/// digits (e.g. `1,234,567`).
+pub fn format_impact(impact: u64, raw: bool) -> String {
+    const SUFFIXES: [&str; 6] = ["k", "M", "B", "T", "P", "E"];
+
+    if raw {
+        return group_digits(impact);
+    }
+    if impact < 1000 {
+        return impact.to_string();
+    }
+    let mut value = impact as f64;
+    let mut suffix = "";
+    for next in SUFFIXES {
+        // 999_999 округляется до 1.00M, а не до 1000.00k
+        if value < 999.995 {
+            break;
+        }
+        value /= 1000.0;
+        suffix = next;
+    }
+    format!("{value:.2}{suffix}")
+}
+
+// Разделяет разряды запятыми: 1234567 -> 1,234,567
+fn group_digits(value: u64) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
 /// Clean and shorten string for display in tables.
 /// - Removes newlines and trims whitespace
 /// - Truncates to `max_len` and appends ellipsis if too long
 fn compact_str(s: &str, max_len: usize) -> String {
     let mut compact = s
-        .replace('\n', " ") // убрать переносы строк
-        .replace('\t', " ") // убрать табы
+        .replace(['\n', '\t'], " ") // убрать переносы строк и табы
         .split_whitespace() // разбить по пробелам
         .collect::<Vec<_>>() // собрать в вектор
         .join(" ");
@@ -25,80 +64,245 @@ fn compact_str(s: &str, max_len: usize) -> String {
     compact
 }
 
-/// Print a slice of [`model::QueryLog`] in an ASCII table,
-/// showing only the most important columns.
-pub fn print_weighted_queries_table(logs: &[model::QueryLog]) {
+/// Render a slice of [`model::QueryLog`] in an ASCII table,
+/// showing only the selected `columns`, in their order.
+///
+/// With `rate`, impact columns show impacts per hour, see [`model::QueryLog::rate`].
+/// Impacts are formatted by [`format_impact`].
+pub fn render_weighted_queries_table(
+    logs: &[model::QueryLog],
+    columns: &[model::QueryColumn],
+    rate: bool,
+    raw_impact: bool,
+    fingerprint_format: model::FingerprintFormat,
+) -> String {
+    let logs: Vec<_> = logs.iter().collect();
+    render_queries_table(&logs, None, columns, rate, raw_impact, fingerprint_format)
+}
+
+/// Like [`render_weighted_queries_table`], with a leading Node column of the
+/// [`model::NodeQuery`] URLs.
+pub fn render_node_queries_table(
+    rows: &[model::NodeQuery],
+    columns: &[model::QueryColumn],
+    rate: bool,
+    raw_impact: bool,
+    fingerprint_format: model::FingerprintFormat,
+) -> String {
+    let logs: Vec<_> = rows.iter().map(|row| &row.query).collect();
+    let nodes: Vec<_> = rows.iter().map(|row| row.node.as_str()).collect();
+    render_queries_table(
+        &logs,
+        Some(&nodes),
+        columns,
+        rate,
+        raw_impact,
+        fingerprint_format,
+    )
+}
+
+// Таблица запросов, `nodes` добавляет первую колонку с URL узла каждой строки
+fn render_queries_table(
+    logs: &[&model::QueryLog],
+    nodes: Option<&[&str]>,
+    columns: &[model::QueryColumn],
+    rate: bool,
+    raw_impact: bool,
+    fingerprint_format: model::FingerprintFormat,
+) -> String {
+    use model::QueryColumn;
+
     let mut table = AsciiTable::default();
-    table.column(0).set_header("Fingerprint");
-    table.column(1).set_header("Query");
-    table.column(2).set_header("Total Impact");
-    table.column(3).set_header("IO Impact");
-    table.column(4).set_header("CPU Impact");
-    table.column(5).set_header("Memory Impact");
-    table.column(6).set_header("Time Impact");
-    table.column(7).set_header("Network Impact");
+    let offset = usize::from(nodes.is_some());
+    if nodes.is_some() {
+        table.column(0).set_header("Node");
+    }
+    for (i, column) in columns.iter().enumerate() {
+        let header = match column {
+            QueryColumn::Fingerprint => "Fingerprint",
+            QueryColumn::Query => "Query",
+            QueryColumn::TotalImpact if rate => "Total Impact/h",
+            QueryColumn::TotalImpact => "Total Impact",
+            QueryColumn::IOImpact if rate => "IO Impact/h",
+            QueryColumn::IOImpact => "IO Impact",
+            QueryColumn::CPUImpact if rate => "CPU Impact/h",
+            QueryColumn::CPUImpact => "CPU Impact",
+            QueryColumn::MemoryImpact if rate => "Memory Impact/h",
+            QueryColumn::MemoryImpact => "Memory Impact",
+            QueryColumn::TimeImpact if rate => "Time Impact/h",
+            QueryColumn::TimeImpact => "Time Impact",
+            QueryColumn::NetworkImpact if rate => "Network Impact/h",
+            QueryColumn::NetworkImpact => "Network Impact",
+            QueryColumn::Users => "Users",
+            QueryColumn::ExceptionCode => "Exception",
+            QueryColumn::FirstSeen => "First Seen",
+            QueryColumn::LastSeen => "Last Seen",
+        };
+        table.column(i + offset).set_header(header);
+    }
 
-    let data: Vec<_> = logs
+    let data: Vec<Vec<String>> = logs
         .iter()
-        .map(|l| {
-            let hash = format!("{:#x}", l.normalized_query_hash);
-            let io_impact: String = format_size(l.io_impact, DECIMAL);
-            let network_impact: String = format_size(l.network_impact, DECIMAL);
-            let cpu_impact: String = format_size(l.cpu_impact, DECIMAL);
-            let memory_impact: String = format_size(l.memory_impact, DECIMAL);
-            let time_impact: String = format_size(l.time_impact, DECIMAL);
-            let total_impact: String = format_size(l.total_impact, DECIMAL);
+        .enumerate()
+        .map(|(row, l)| {
+            let impacts = match rate {
+                true => {
+                    let r = l.rate();
+                    [
+                        r.total_impact_per_hour,
+                        r.io_impact_per_hour,
+                        r.cpu_impact_per_hour,
+                        r.memory_impact_per_hour,
+                        r.time_impact_per_hour,
+                        r.network_impact_per_hour,
+                    ]
+                }
+                false => [
+                    l.total_impact,
+                    l.io_impact,
+                    l.cpu_impact,
+                    l.memory_impact,
+                    l.time_impact,
+                    l.network_impact,
+                ],
+            };
+            let [total, io, cpu, memory, time, network] =
+                impacts.map(|v| format_impact(v, raw_impact));
+            let node = nodes.map(|nodes| nodes[row].to_string());
+            node.into_iter()
+                .chain(columns.iter().map(|column| match column {
+                    QueryColumn::Fingerprint => fingerprint_format.format(l.normalized_query_hash),
+                    QueryColumn::Query => compact_str(l.display_query(), MAX_COLUMN_LEN),
+                    QueryColumn::TotalImpact => total.clone(),
+                    QueryColumn::IOImpact => io.clone(),
+                    QueryColumn::CPUImpact => cpu.clone(),
+                    QueryColumn::MemoryImpact => memory.clone(),
+                    QueryColumn::TimeImpact => time.clone(),
+                    QueryColumn::NetworkImpact => network.clone(),
+                    QueryColumn::Users => l.users.len().to_string(),
+                    QueryColumn::ExceptionCode => l.exception_code.to_string(),
+                    QueryColumn::FirstSeen => format_time(l.min_event_time),
+                    QueryColumn::LastSeen => format_time(l.max_event_time),
+                }))
+                .collect()
+        })
+        .collect();
+    table.format(data)
+}
+
+/// Render a slice of [`model::UserImpact`] in an ASCII table.
+pub fn render_user_impacts_table(users: &[model::UserImpact], raw_impact: bool) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("User");
+    table.column(1).set_header("Fingerprints");
+    table.column(2).set_header("Impact");
 
+    let data: Vec<_> = users
+        .iter()
+        .map(|u| {
             vec![
-                hash.to_string(),
-                compact_str(&l.query, MAX_COLUMN_LEN),
-                total_impact,
-                io_impact,
-                cpu_impact,
-                memory_impact,
-                time_impact,
-                network_impact,
+                compact_str(&u.user, MAX_COLUMN_LEN),
+                u.fingerprints.to_string(),
+                format_impact(u.impact, raw_impact),
             ]
         })
         .collect();
-    table.print(data);
+    table.format(data)
 }
 
-/// Print [`model::QueryLogExtended`] in human readable format.
-pub fn print_query_extended(query: &model::QueryLogExtended) {
-    let hash = format!("{:#x}", query.normalized_query_hash);
+/// Render [`model::QueryLogExtended`] in human readable format.
+pub fn render_query_extended(
+    query: &model::QueryLogExtended,
+    fingerprint_format: model::FingerprintFormat,
+) -> String {
+    let hash = fingerprint_format.format(query.normalized_query_hash);
     let total_duration =
         humantime::format_duration(Duration::from_millis(query.total_query_duration_ms));
     let read_bytes = format_size(query.total_read_bytes, DECIMAL);
+    let result_bytes = format_size(query.total_result_bytes, DECIMAL);
     let memory = format_size(query.total_memory_usage, DECIMAL);
     let user_time = humantime::format_duration(Duration::from_micros(query.total_user_time_us));
     let system_time = humantime::format_duration(Duration::from_micros(query.total_system_time_us));
     let net_recv = format_size(query.total_network_receive_bytes, DECIMAL);
     let net_send = format_size(query.total_network_send_bytes, DECIMAL);
+    let sample_duration =
+        humantime::format_duration(Duration::from_millis(query.sample_query_duration_ms));
+
+    let query_text = if query.query_truncated {
+        let size = format_size(query.query_bytes, DECIMAL);
+        format!("Query text (truncated, {size} in full, see --truncate-query-bytes):")
+    } else {
+        "Query text:".to_string()
+    };
+
+    let lines = [
+        format!("Query fingerprint: {}", hash),
+        format!("{query_text}\n{}", query.display_query()),
+        format!(
+            "Events time range: {} - {}",
+            query.min_event_time.format(&Rfc3339).unwrap_or_default(),
+            query.max_event_time.format(&Rfc3339).unwrap_or_default()
+        ),
+        format!("Total duration: {}", total_duration),
+        format!("Read rows: {}", query.total_read_rows),
+        format!("Read bytes: {}", read_bytes),
+        format!("Result rows: {}", query.total_result_rows),
+        format!("Result bytes: {}", result_bytes),
+        format!("Memory usage: {}", memory),
+        format!("User CPU time: {}", user_time),
+        format!("System CPU time: {}", system_time),
+        format!("Network received: {}", net_recv),
+        format!("Network sent: {}", net_send),
+        format!("Users: {}", query.users.join(", ")),
+        format!("Databases: {}", query.databases.join(", ")),
+        format!("Tables: {}", query.tables.join(", ")),
+        format!("Exception code: {}", query.exception_code),
+        format!(
+            "Sample query id (slowest, {}): {}",
+            sample_duration, query.sample_query_id
+        ),
+    ];
+    join_lines(&lines)
+}
+
+/// Render [`model::ImpactTerm`]s of `inspect --explain-impact` in an ASCII table,
+/// with the total impact in a footer.
+pub fn render_impact_breakdown(terms: &[model::ImpactTerm], raw_impact: bool) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Category");
+    table.column(1).set_header("Metric");
+    table.column(2).set_header("Value");
+    table.column(3).set_header("Weight");
+    table.column(4).set_header("Weighted");
+    table.column(5).set_header("Share");
+
+    let data: Vec<_> = terms
+        .iter()
+        .map(|t| {
+            vec![
+                t.category.to_string(),
+                t.metric.to_string(),
+                t.value.to_string(),
+                format!("x{}", group_digits(t.weight)),
+                format_impact(t.weighted, raw_impact),
+                format!("{:.1}%", t.share * 100.0),
+            ]
+        })
+        .collect();
 
-    println!("Query fingerprint: {}", hash);
-    println!("Query text:\n{}", query.query);
-    println!(
-        "Events time range: {} - {}",
-        query.min_event_time.format(&Rfc3339).unwrap_or_default(),
-        query.max_event_time.format(&Rfc3339).unwrap_or_default()
-    );
-    println!("Total duration: {}", total_duration);
-    println!("Read rows: {}", query.total_read_rows);
-    println!("Read bytes: {}", read_bytes);
-    println!("Memory usage: {}", memory);
-    println!("User CPU time: {}", user_time);
-    println!("System CPU time: {}", system_time);
-    println!("Network received: {}", net_recv);
-    println!("Network sent: {}", net_send);
-
-    println!("Users: {}", query.users.join(", "));
-    println!("Databases: {}", query.databases.join(", "));
-    println!("Tables: {}", query.tables.join(", "));
-}
-
-/// Print a slice of [`model::QueryLogTotal`] in an ASCII table.
-pub fn print_total_queries_table(l: &model::QueryLogTotal) {
+    let total = terms
+        .iter()
+        .map(|t| t.weighted)
+        .fold(0, u64::saturating_add);
+    format!(
+        "{}Total impact: {}\n",
+        table.format(data),
+        format_impact(total, raw_impact)
+    )
+}
+
+/// Render a slice of [`model::QueryLogTotal`] in an ASCII table.
+pub fn render_total_queries_table(l: &model::QueryLogTotal, raw_impact: bool) -> String {
     let mut table = AsciiTable::default();
     table.column(0).set_header("Select count");
     table.column(1).set_header("Total Impact");
@@ -108,12 +312,12 @@ pub fn print_total_queries_table(l: &model::QueryLogTotal) {
     table.column(5).set_header("Time Impact");
     table.column(6).set_header("Network Impact");
 
-    let network_impact: String = format_size(l.network_impact, DECIMAL);
-    let io_impact: String = format_size(l.io_impact, DECIMAL);
-    let cpu_impact: String = format_size(l.cpu_impact, DECIMAL);
-    let memory_impact: String = format_size(l.memory_impact, DECIMAL);
-    let time_impact: String = format_size(l.time_impact, DECIMAL);
-    let total_impact: String = format_size(l.total_impact, DECIMAL);
+    let network_impact: String = format_impact(l.network_impact, raw_impact);
+    let io_impact: String = format_impact(l.io_impact, raw_impact);
+    let cpu_impact: String = format_impact(l.cpu_impact, raw_impact);
+    let memory_impact: String = format_impact(l.memory_impact, raw_impact);
+    let time_impact: String = format_impact(l.time_impact, raw_impact);
+    let total_impact: String = format_impact(l.total_impact, raw_impact);
 
     let data = vec![vec![
         l.queries_count.to_string(),
@@ -124,21 +328,125 @@ pub fn print_total_queries_table(l: &model::QueryLogTotal) {
         time_impact,
         network_impact,
     ]];
-    table.print(data);
+    table.format(data)
+}
+
+/// Render the `total --history` records in an ASCII table, followed by a sparkline
+/// of the total impact.
+pub fn render_history(records: &[model::HistoryRecord], raw_impact: bool) -> String {
+    let rows: Vec<_> = records.iter().map(|r| (r.recorded_at, &r.total)).collect();
+    render_total_series("Recorded At", &rows, raw_impact)
+}
+
+/// Render the time series of `total --breakdown-by` in an ASCII table with a sparkline.
+pub fn render_total_buckets(buckets: &[model::TotalBucket], raw_impact: bool) -> String {
+    let rows: Vec<_> = buckets.iter().map(|b| (b.bucket, &b.total)).collect();
+    render_total_series("Bucket", &rows, raw_impact)
 }
 
-/// Print a slice of [`model::Error`] in an ASCII table.
-pub fn print_errors_table(errs: &[model::Error]) {
+fn render_total_series(
+    time_header: &str,
+    rows: &[(time::OffsetDateTime, &model::QueryLogTotal)],
+    raw_impact: bool,
+) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header(time_header);
+    table.column(1).set_header("Select count");
+    table.column(2).set_header("Total Impact");
+    table.column(3).set_header("IO Impact");
+    table.column(4).set_header("CPU Impact");
+    table.column(5).set_header("Memory Impact");
+    table.column(6).set_header("Time Impact");
+    table.column(7).set_header("Network Impact");
+
+    let data: Vec<_> = rows
+        .iter()
+        .map(|(time, total)| {
+            vec![
+                format_time(*time),
+                total.queries_count.to_string(),
+                format_impact(total.total_impact, raw_impact),
+                format_impact(total.io_impact, raw_impact),
+                format_impact(total.cpu_impact, raw_impact),
+                format_impact(total.memory_impact, raw_impact),
+                format_impact(total.time_impact, raw_impact),
+                format_impact(total.network_impact, raw_impact),
+            ]
+        })
+        .collect();
+
+    let mut rendered = table.format(data);
+    if !rows.is_empty() {
+        let impacts: Vec<u64> = rows.iter().map(|(_, total)| total.total_impact).collect();
+        rendered += &format!("Total impact: {}\n", sparkline(&impacts));
+    }
+    rendered
+}
+
+// Рисует значения блочными символами, от минимума до максимума
+fn sparkline(values: &[u64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let min = values.iter().copied().min().unwrap_or(0);
+    let max = values.iter().copied().max().unwrap_or(0);
+    values
+        .iter()
+        .map(|&value| {
+            let level = match max - min {
+                0 => 0,
+                range => {
+                    ((value - min) as u128 * (BARS.len() - 1) as u128 / range as u128) as usize
+                }
+            };
+            BARS[level]
+        })
+        .collect()
+}
+
+/// Render the per-node totals of `total --compare-nodes` in an ASCII table.
+pub fn render_node_totals_table(nodes: &[model::NodeTotal], raw_impact: bool) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Node");
+    table.column(1).set_header("Select count");
+    table.column(2).set_header("vs median");
+    table.column(3).set_header("Total Impact");
+    table.column(4).set_header("vs median");
+    table.column(5).set_header("Outlier");
+
+    let ratio = |ratio: Option<f64>| ratio.map_or("-".to_string(), |r| format!("{r:.2}x"));
+    let data: Vec<_> = nodes
+        .iter()
+        .map(|n| {
+            vec![
+                compact_str(&n.node, MAX_COLUMN_LEN),
+                n.queries_count.to_string(),
+                ratio(n.queries_ratio),
+                format_impact(n.total_impact, raw_impact),
+                ratio(n.impact_ratio),
+                if n.outlier { "yes" } else { "" }.to_string(),
+            ]
+        })
+        .collect();
+    table.format(data)
+}
+
+/// Render a slice of [`model::Error`] in an ASCII table.
+pub fn render_errors_table(errs: &[model::Error]) -> String {
     let mut table = AsciiTable::default();
     table.column(0).set_header("Code");
     table.column(1).set_header("Name");
     table.column(2).set_header("Count");
-    table.column(3).set_header("Last Seen");
-    table.column(4).set_header("Message");
+    table.column(3).set_header("Per Hour");
+    table.column(4).set_header("First Seen");
+    table.column(5).set_header("Last Seen");
+    table.column(6).set_header("Message");
 
     let data: Vec<_> = errs
         .iter()
         .map(|e| {
+            let first_seen = e
+                .first_error_time
+                .and_then(|time| time.format(&Rfc3339).ok())
+                .unwrap_or_else(|| "-".into());
             let last_seen = e
                 .last_error_time
                 .format(&Rfc3339)
@@ -147,48 +455,578 @@ pub fn print_errors_table(errs: &[model::Error]) {
                 e.code.to_string(),
                 e.name.to_string(),
                 e.count.to_string(),
+                e.errors_per_hour
+                    .map_or_else(|| "-".into(), |rate| format!("{rate:.1}")),
+                first_seen,
                 last_seen,
                 compact_str(&e.error_message, MAX_COLUMN_LEN),
             ]
         })
         .collect();
-    table.print(data);
+    table.format(data)
+}
+
+/// Render [`model::Merge`]s in an ASCII table.
+pub fn render_merges_table(merges: &[model::Merge]) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Host");
+    table.column(1).set_header("Table");
+    table.column(2).set_header("Kind");
+    table.column(3).set_header("Elapsed");
+    table.column(4).set_header("Progress");
+    table.column(5).set_header("Parts");
+    table.column(6).set_header("Size");
+    table.column(7).set_header("Memory");
+
+    let data: Vec<_> = merges
+        .iter()
+        .map(|m| {
+            // Секунды без дробной части, миллисекунды здесь только шум
+            let elapsed = Duration::from_secs(m.elapsed.max(0.0) as u64);
+            vec![
+                m.host.clone(),
+                format!("{}.{}", m.database, m.table),
+                if m.is_mutation { "mutation" } else { "merge" }.to_string(),
+                humantime::format_duration(elapsed).to_string(),
+                format!("{:.1}%", m.progress * 100.0),
+                m.num_parts.to_string(),
+                format_size(m.total_size_bytes_compressed, DECIMAL),
+                format_size(m.memory_usage, DECIMAL),
+            ]
+        })
+        .collect();
+    table.format(data)
+}
+
+/// Render [`model::Mutation`]s in an ASCII table.
+pub fn render_mutations_table(mutations: &[model::Mutation]) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Host");
+    table.column(1).set_header("Table");
+    table.column(2).set_header("Mutation");
+    table.column(3).set_header("Age");
+    table.column(4).set_header("Parts to do");
+    table.column(5).set_header("Latest fail reason");
+
+    let now = time::OffsetDateTime::now_utc();
+    let data: Vec<_> = mutations
+        .iter()
+        .map(|m| {
+            // Секунды без дробной части, как у merges
+            let age = Duration::from_secs((now - m.create_time).whole_seconds().max(0) as u64);
+            vec![
+                m.host.clone(),
+                format!("{}.{}", m.database, m.table),
+                m.mutation_id.clone(),
+                humantime::format_duration(age).to_string(),
+                m.parts_to_do.to_string(),
+                compact_str(&m.latest_fail_reason, MAX_COLUMN_LEN),
+            ]
+        })
+        .collect();
+    table.format(data)
+}
+
+/// Render [`model::ReplicaLag`]s in an ASCII table, with the `max_lag` threshold
+/// in a footer.
+pub fn render_replica_lags_table(replicas: &[model::ReplicaLag], max_lag: Duration) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Host");
+    table.column(1).set_header("Table");
+    table.column(2).set_header("Leader");
+    table.column(3).set_header("Readonly");
+    table.column(4).set_header("Delay");
+    table.column(5).set_header("Queue");
+    table.column(6).set_header("Inserts in queue");
+    table.column(7).set_header("Lagging");
+
+    let flag = |value: bool| if value { "yes" } else { "" }.to_string();
+    let data: Vec<_> = replicas
+        .iter()
+        .map(
+            |model::ReplicaLag {
+                 replica: r,
+                 lagging,
+             }| {
+                vec![
+                    r.host.clone(),
+                    format!("{}.{}", r.database, r.table),
+                    flag(r.is_leader),
+                    flag(r.is_readonly),
+                    humantime::format_duration(Duration::from_secs(r.absolute_delay)).to_string(),
+                    r.queue_size.to_string(),
+                    r.inserts_in_queue.to_string(),
+                    flag(*lagging),
+                ]
+            },
+        )
+        .collect();
+    format!("{}{}", table.format(data), render_max_lag(max_lag))
+}
+
+/// Render [`model::ReplicatedTable`]s in an ASCII table, with the `max_lag` threshold
+/// in a footer.
+pub fn render_replicated_tables_table(
+    tables: &[model::ReplicatedTable],
+    max_lag: Duration,
+) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Table");
+    table.column(1).set_header("Replicas");
+    table.column(2).set_header("Readonly");
+    table.column(3).set_header("Max delay");
+    table.column(4).set_header("Queue");
+    table.column(5).set_header("Inserts in queue");
+    table.column(6).set_header("Lagging");
+
+    let data: Vec<_> = tables
+        .iter()
+        .map(|t| {
+            vec![
+                format!("{}.{}", t.database, t.table),
+                t.replicas.to_string(),
+                t.readonly_replicas.to_string(),
+                humantime::format_duration(Duration::from_secs(t.max_delay)).to_string(),
+                t.queue_size.to_string(),
+                t.inserts_in_queue.to_string(),
+                t.lagging_replicas.to_string(),
+            ]
+        })
+        .collect();
+    format!("{}{}", table.format(data), render_max_lag(max_lag))
+}
+
+fn render_max_lag(max_lag: Duration) -> String {
+    format!(
+        "\nLagging: delayed more than {}\n",
+        humantime::format_duration(max_lag)
+    )
+}
+
+/// Render [`model::Part`]s in an ASCII table.
+pub fn render_parts_table(parts: &[model::Part]) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Table");
+    table.column(1).set_header("Parts");
+    table.column(2).set_header("Rows");
+    table.column(3).set_header("Compressed");
+    table.column(4).set_header("Uncompressed");
+    table.column(5).set_header("Ratio");
+
+    let data: Vec<_> = parts
+        .iter()
+        .map(|p| {
+            vec![
+                format!("{}.{}", p.database, p.table),
+                p.parts.to_string(),
+                p.rows.to_string(),
+                format_size(p.bytes_compressed, DECIMAL),
+                format_size(p.bytes_uncompressed, DECIMAL),
+                format!("{:.2}", p.compression_ratio),
+            ]
+        })
+        .collect();
+    table.format(data)
+}
+
+/// Render [`model::ErrorDelta`]s of `errors --watch --diff` in an ASCII table.
+pub fn render_error_deltas_table(deltas: &[model::ErrorDelta]) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Code");
+    table.column(1).set_header("Name");
+    table.column(2).set_header("New");
+    table.column(3).set_header("Count");
+    table.column(4).set_header("Flag");
+
+    let data: Vec<_> = deltas
+        .iter()
+        .map(|d| {
+            let flag = match (d.new, d.spike) {
+                (true, _) => "new",
+                (false, true) => "spike",
+                (false, false) => "",
+            };
+            vec![
+                d.code.to_string(),
+                d.name.to_string(),
+                d.delta.to_string(),
+                d.count.to_string(),
+                flag.to_string(),
+            ]
+        })
+        .collect();
+    table.format(data)
+}
+
+/// Render the client and server execution times of `--iterations` in an ASCII table.
+pub fn render_benchmark(benchmark: &model::Benchmark) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Timing");
+    table.column(1).set_header("Samples");
+    table.column(2).set_header("Min ms");
+    table.column(3).set_header("Median ms");
+    table.column(4).set_header("Max ms");
+
+    let rows = [
+        ("client (wall-clock)", &benchmark.client),
+        ("server (query_log)", &benchmark.server),
+    ];
+    let data: Vec<_> = rows
+        .iter()
+        .map(|(timing, stats)| match stats {
+            Some(stats) => vec![
+                timing.to_string(),
+                stats.samples.to_string(),
+                format!("{:.1}", stats.min_ms),
+                format!("{:.1}", stats.median_ms),
+                format!("{:.1}", stats.max_ms),
+            ],
+            None => vec![
+                timing.to_string(),
+                "missing".into(),
+                "-".into(),
+                "-".into(),
+                "-".into(),
+            ],
+        })
+        .collect();
+    format!(
+        "Iterations: {}\n{}",
+        benchmark.iterations,
+        table.format(data)
+    )
+}
+
+/// Render the footer with codes dropped by `errors --min-count`.
+pub fn render_below_min_count(below: &model::BelowMinCount) -> String {
+    format!(
+        "Excluded by --min-count {}: {} codes, {} occurrences\n",
+        below.min_count, below.codes, below.occurrences
+    )
+}
+
+/// Render [`model::BuildInfo`], only the version unless `verbose`.
+pub fn render_version(info: &model::BuildInfo, verbose: bool) -> String {
+    let version = format!("clickcheck {}", info.version);
+    if !verbose {
+        return join_lines(&[version]);
+    }
+    let features = if info.features.is_empty() {
+        "none".to_string()
+    } else {
+        info.features.join(", ")
+    };
+    let lines = [
+        version,
+        format!("Git commit: {}", info.git_commit),
+        format!("Build date: {}", info.build_date),
+        format!("Rustc: {}", info.rustc),
+        format!("Features: {features}"),
+    ];
+    join_lines(&lines)
+}
+
+/// Render a slice of [`model::TableImpact`] in an ASCII table.
+pub fn render_table_impacts_table(tables: &[model::TableImpact], raw_impact: bool) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Table");
+    table.column(1).set_header("Fingerprints");
+    table.column(2).set_header("Impact");
+
+    let data: Vec<_> = tables
+        .iter()
+        .map(|t| {
+            vec![
+                compact_str(&t.table, MAX_COLUMN_LEN),
+                t.fingerprints.to_string(),
+                format_impact(t.impact, raw_impact),
+            ]
+        })
+        .collect();
+    table.format(data)
 }
 
-pub fn print_context_names_table(names: &[String]) {
+/// Render a slice of [`model::DatabaseImpact`] in an ASCII table.
+pub fn render_database_impacts_table(
+    databases: &[model::DatabaseImpact],
+    raw_impact: bool,
+) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Database");
+    table.column(1).set_header("Fingerprints");
+    table.column(2).set_header("Impact");
+
+    let data: Vec<_> = databases
+        .iter()
+        .map(|d| {
+            vec![
+                compact_str(&d.database, MAX_COLUMN_LEN),
+                d.fingerprints.to_string(),
+                format_impact(d.impact, raw_impact),
+            ]
+        })
+        .collect();
+    table.format(data)
+}
+
+/// Render the effective [`model::QueriesFilter`] as a one line footer, omitting unset fields.
+pub fn render_queries_filter(filter: &model::QueriesFilter) -> String {
+    let from = filter
+        .start_time()
+        .map(format_time)
+        .unwrap_or_else(|| "beginning".to_string());
+    let to = filter
+        .to
+        .map(format_time)
+        .unwrap_or_else(|| "now".to_string());
+
+    let mut parts = vec![format!("{from} .. {to}")];
+    if !filter.users.is_empty() {
+        parts.push(format!("users: {}", filter.users.join(", ")));
+    }
+    if !filter.exception_codes.is_empty() {
+        let codes: Vec<_> = filter.exception_codes.iter().map(i32::to_string).collect();
+        parts.push(format!("exception codes: {}", codes.join(", ")));
+    }
+    match filter.status {
+        model::QueryStatus::Ok => parts.push("status: ok".to_string()),
+        model::QueryStatus::Failed => parts.push("status: failed".to_string()),
+        model::QueryStatus::All => {}
+    }
+    if !filter.exclude_users.is_empty() {
+        parts.push(format!(
+            "excluded users: {}",
+            filter.exclude_users.join(", ")
+        ));
+    }
+    if !filter.databases.is_empty() {
+        parts.push(format!("databases: {}", filter.databases.join(", ")));
+    }
+    if !filter.tables.is_empty() {
+        parts.push(format!("tables: {}", filter.tables.join(", ")));
+    }
+    if let Some(duration) = filter.min_query_duration {
+        let duration = humantime::format_duration(duration);
+        parts.push(format!("min duration: {duration}"));
+    }
+    if let Some(rows) = filter.min_read_rows {
+        parts.push(format!("min read rows: {rows}"));
+    }
+    if let Some(data) = filter.min_read_data {
+        let data = format_size(data.as_u64(), DECIMAL);
+        parts.push(format!("min read data: {data}"));
+    }
+    if let Some(users) = filter.min_distinct_users {
+        parts.push(format!("min distinct users: {users}"));
+    }
+    let mut footer = format!("Filter: {}\n", parts.join(", "));
+    if let Some(factor) = filter.log_sampling_factor {
+        footer +=
+            &format!("Extrapolated: metrics multiplied by {factor} (--log-sampling-factor)\n");
+    }
+    if let Some(expr) = &filter.impact_expr {
+        footer += &format!("Impact: {expr} (--impact-expr)\n");
+    }
+    if !filter.impact_weights.is_default() {
+        let weights = filter.impact_weights;
+        footer += &format!(
+            "Weights: io {}, network {}, cpu {}, memory {}, time {} (--weight-*)\n",
+            weights.io, weights.network, weights.cpu, weights.memory, weights.time
+        );
+    }
+    footer
+}
+
+/// Render the effective [`model::ErrorsFilter`] as a one line footer, omitting unset fields.
+pub fn render_errors_filter(filter: &model::ErrorsFilter) -> String {
+    let mut parts = Vec::new();
+    if let Some(last) = filter.last {
+        let since = time::OffsetDateTime::now_utc() - last;
+        parts.push(format!("last seen since {}", format_time(since)));
+    }
+    if !filter.code.is_empty() {
+        let codes: Vec<String> = filter.code.iter().map(i32::to_string).collect();
+        parts.push(format!("codes: {}", codes.join(", ")));
+    }
+    if !filter.exclude_code.is_empty() {
+        let codes: Vec<String> = filter.exclude_code.iter().map(i32::to_string).collect();
+        parts.push(format!("excluded codes: {}", codes.join(", ")));
+    }
+    if let Some(min_count) = filter.min_count {
+        parts.push(format!("min count: {min_count}"));
+    }
+    if let Some(since) = filter.new_since {
+        parts.push(format!("first seen since {}", format_time(since)));
+    }
+    if parts.is_empty() {
+        return String::new();
+    }
+    format!("Filter: {}\n", parts.join(", "))
+}
+
+fn format_time(time: time::OffsetDateTime) -> String {
+    time.replace_nanosecond(0)
+        .unwrap_or(time)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+/// Render [`model::SettingsDiff`] in an ASCII table with a column per host.
+pub fn render_settings_diff_table(diff: &model::SettingsDiff) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Setting");
+    for (i, host) in diff.hosts.iter().enumerate() {
+        table.column(i + 1).set_header(host);
+    }
+
+    let data: Vec<_> = diff
+        .settings
+        .iter()
+        .map(|s| {
+            let mut row = vec![s.name.clone()];
+            row.extend(diff.hosts.iter().map(|host| {
+                s.values
+                    .get(host)
+                    .map(|v| compact_str(v, MAX_COLUMN_LEN))
+                    .unwrap_or_else(|| "-".into())
+            }));
+            row
+        })
+        .collect();
+    table.format(data)
+}
+
+/// Render the [`model::NodePing`] of every node in an ASCII table, latencies in milliseconds.
+pub fn render_ping_table(pings: &[model::NodePing]) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Node");
+    table.column(1).set_header("Status");
+    table.column(2).set_header("Received");
+    table.column(3).set_header("Min ms");
+    table.column(4).set_header("Avg ms");
+    table.column(5).set_header("P95 ms");
+
+    let ms = |latency: Option<f64>| latency.map_or("-".to_string(), |ms| format!("{ms:.1}"));
+    let data: Vec<_> = pings
+        .iter()
+        .map(|p| {
+            vec![
+                compact_str(&p.node, MAX_COLUMN_LEN),
+                match &p.error {
+                    Some(error) => compact_str(error, MAX_COLUMN_LEN),
+                    None => "ok".to_string(),
+                },
+                format!("{}/{}", p.received, p.sent),
+                ms(p.min_latency_ms),
+                ms(p.avg_latency_ms),
+                ms(p.p95_latency_ms),
+            ]
+        })
+        .collect();
+    table.format(data)
+}
+
+/// Render [`model::Timings`] stages in an ASCII table to stderr, with the
+/// [`model::Backpressure`] of per-node streaming stages.
+pub fn render_timings_table(timings: &model::Timings) -> String {
+    let mut table = AsciiTable::default();
+    table.column(0).set_header("Stage");
+    table.column(1).set_header("Elapsed");
+    let backpressure = timings.stages.iter().any(|t| t.backpressure.is_some());
+    if backpressure {
+        table.column(2).set_header("Rows");
+        table.column(3).set_header("Channel Full");
+        table.column(4).set_header("Blocked");
+    }
+
+    let data: Vec<_> = timings
+        .stages
+        .iter()
+        .map(|t| {
+            let mut row = vec![t.stage.clone(), format!("{:.3?}", t.elapsed)];
+            if backpressure {
+                row.extend(match t.backpressure {
+                    Some(b) => [
+                        b.rows.to_string(),
+                        b.full.to_string(),
+                        format!("{:.3?}", b.blocked),
+                    ],
+                    None => Default::default(),
+                });
+            }
+            row
+        })
+        .collect();
+    table.format(data)
+}
+
+pub fn render_context_names_table(names: &[String]) -> String {
     let mut table = AsciiTable::default();
     table.column(0).set_header("Name");
 
     let data: Vec<_> = names.iter().map(|n| vec![n]).collect();
-    table.print(data);
+    table.format(data)
 }
 
-pub fn print_context_current(active: Option<&str>) {
+pub fn render_context_current(active: Option<&str>) -> String {
     if let Some(name) = active {
-        println!("{name}");
+        format!("{name}\n")
     } else {
-        println!("No active context set");
+        "No active context set\n".to_string()
     }
 }
 
-pub fn print_context_config_path(path: &std::path::PathBuf) {
-    println!("{}", path.display());
+pub fn render_context_config_path(
+    path: &std::path::Path,
+    local_path: Option<&std::path::Path>,
+) -> String {
+    match local_path {
+        Some(local_path) => format!("{}\n{} (local)\n", path.display(), local_path.display()),
+        None => format!("{}\n", path.display()),
+    }
 }
 
-pub fn print_context_profile(profile: &model::PrintableContextProfile) {
-    println!("Profile:");
-    println!("  URLs: {}", profile.urls.join(", "));
-    println!("  User: {}", profile.user);
-    println!(
-        "  Password: {}",
-        if profile.password.is_empty() {
-            "(empty)"
-        } else {
-            &profile.password
-        }
-    );
-    println!(
-        "  Accept invalid certificate: {}",
-        profile.accept_invalid_certificate
-    );
+pub fn render_context_dry_run(changes: &[String]) -> String {
+    let mut lines = vec!["Dry run, nothing was changed. Would:".to_string()];
+    lines.extend(changes.iter().map(|change| format!("  - {change}")));
+    join_lines(&lines)
+}
+
+pub fn render_context_profile(profile: &model::PrintableContextProfile) -> String {
+    let lines = [
+        "Profile:".to_string(),
+        format!("  URLs: {}", profile.urls.join(", ")),
+        format!("  User: {}", profile.user),
+        format!(
+            "  Password: {}",
+            if profile.password.is_empty() {
+                "(empty)"
+            } else {
+                profile.password
+            }
+        ),
+        format!(
+            "  Accept invalid certificate: {}",
+            profile.accept_invalid_certificate
+        ),
+        format!(
+            "  TLS server name: {}",
+            profile.tls_server_name.unwrap_or("(URL host)")
+        ),
+        format!(
+            "  Labels: {}",
+            profile
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    ];
+    join_lines(&lines)
+}
+
+/// Join rendered lines, terminating each one with a newline.
+fn join_lines(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("{line}\n")).collect()
 }
@@ -0,0 +1,224 @@
+//! Interactive terminal UI for `queries --tui`.
+//!
+//! Shows the ranked query groups in a navigable table. Pressing `Enter` streams the
+//! selected fingerprint from ClickHouse (like the `inspect` command) and shows the
+//! [`QueryLogExtended`] details in a pane below the table.
+//!
+//! Key bindings:
+//! - `↑`/`↓` or `k`/`j`: move the selection
+//! - `Enter`: inspect the selected fingerprint
+//! - `s`: cycle the [`QueriesSortBy`] metric
+//! - `q`/`Esc`: quit
+//!
+//! Only the rendering layer lives here, data is fetched through the existing
+//! [`client`], [`analyzer`] and [`command`](crate::command) helpers.
+use crate::analyzer;
+use crate::client;
+use crate::command;
+use crate::model::{self, QueriesSortBy, QueryLog, QueryLogExtended};
+use crate::output;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::time::Duration;
+
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+struct App {
+    all_queries: Vec<QueryLog>,
+    queries: Vec<QueryLog>,
+    limit: usize,
+    sort_by: QueriesSortBy,
+    /// Text display options (`--raw-impact`, `--fingerprint-format`).
+    out: model::Output,
+    state: TableState,
+    detail: Detail,
+}
+
+enum Detail {
+    Hint,
+    Loading(u64),
+    Loaded(Box<QueryLogExtended>),
+    Failed(String),
+}
+
+/// Runs the interactive UI until the user quits, restoring the terminal afterwards.
+pub async fn run(
+    client: &client::Client,
+    queries: Vec<QueryLog>,
+    req: model::TopQueriesRequest,
+    timings: &mut model::Timings,
+) -> Result<(), String> {
+    let mut app = App::new(queries, req.limit, req.sort_by, req.out.clone());
+    let mut terminal = ratatui::init();
+    let result = app
+        .event_loop(&mut terminal, client, &req.filter, timings)
+        .await;
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn new(
+        all_queries: Vec<QueryLog>,
+        limit: usize,
+        sort_by: QueriesSortBy,
+        out: model::Output,
+    ) -> Self {
+        let mut app = Self {
+            queries: Vec::new(),
+            all_queries,
+            limit,
+            sort_by,
+            out,
+            state: TableState::default(),
+            detail: Detail::Hint,
+        };
+        app.rank();
+        app
+    }
+
+    fn rank(&mut self) {
+        self.queries = analyzer::rank_queries(&self.all_queries, self.limit, self.sort_by.clone());
+        self.state.select((!self.queries.is_empty()).then_some(0));
+    }
+
+    async fn event_loop(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        client: &client::Client,
+        filter: &model::QueriesFilter,
+        timings: &mut model::Timings,
+    ) -> Result<(), String> {
+        loop {
+            terminal
+                .draw(|frame| self.draw(frame))
+                .map_err(|e| format!("draw tui error: {e}"))?;
+
+            if let Detail::Loading(fingerprint) = self.detail {
+                let filter = model::QueriesFilter {
+                    truncate_query_bytes: Some(model::DEFAULT_TRUNCATE_QUERY_BYTES),
+                    ..filter.clone()
+                };
+                self.detail =
+                    match command::fetch_query_extended(client, fingerprint, filter, timings).await
+                    {
+                        Ok(Some(query)) => Detail::Loaded(Box::new(query)),
+                        Ok(None) => Detail::Failed(format!(
+                            "Fingerprint {} not found",
+                            self.out.fingerprint_format.format(fingerprint)
+                        )),
+                        Err(e) => Detail::Failed(e.to_string()),
+                    };
+                continue;
+            }
+
+            if !event::poll(EVENT_POLL_INTERVAL)
+                .map_err(|e| format!("read tui event error: {e}"))?
+            {
+                continue;
+            }
+            let Event::Key(key) =
+                event::read().map_err(|e| format!("read tui event error: {e}"))?
+            else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.state.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.state.select_previous(),
+                KeyCode::Char('s') => {
+                    self.sort_by = next_sort_by(&self.sort_by);
+                    self.rank();
+                }
+                KeyCode::Enter => {
+                    if let Some(query) = self.state.selected().and_then(|i| self.queries.get(i)) {
+                        self.detail = Detail::Loading(query.normalized_query_hash);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, detail_area] =
+            Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(frame.area());
+
+        let header = Row::new(["Fingerprint", "Query", sort_by_title(&self.sort_by)])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        let rows = self.queries.iter().map(|q| {
+            Row::new([
+                self.out.fingerprint_format.format(q.normalized_query_hash),
+                q.display_query()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                output::format_impact(analyzer::sort_value(q, &self.sort_by), self.out.raw_impact),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(20),
+                Constraint::Fill(1),
+                Constraint::Length(16),
+            ],
+        )
+        .header(header)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Queries (↑/↓ select, Enter inspect, s sort, q quit) "),
+        )
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, list_area, &mut self.state);
+
+        let detail = match &self.detail {
+            Detail::Hint => "Press Enter to inspect the selected fingerprint.".to_string(),
+            Detail::Loading(fingerprint) => {
+                format!(
+                    "Loading {}…",
+                    self.out.fingerprint_format.format(*fingerprint)
+                )
+            }
+            Detail::Loaded(query) => {
+                output::render_query_extended_text(query, self.out.fingerprint_format)
+            }
+            Detail::Failed(error) => format!("Error: {error}"),
+        };
+        let detail = Paragraph::new(detail)
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL).title(" Details "));
+        frame.render_widget(detail, detail_area);
+    }
+}
+
+fn next_sort_by(sort_by: &QueriesSortBy) -> QueriesSortBy {
+    match sort_by {
+        QueriesSortBy::TotalImpact => QueriesSortBy::IOImpact,
+        QueriesSortBy::IOImpact => QueriesSortBy::CPUImpact,
+        QueriesSortBy::CPUImpact => QueriesSortBy::MemoryImpact,
+        QueriesSortBy::MemoryImpact => QueriesSortBy::TimeImpact,
+        QueriesSortBy::TimeImpact => QueriesSortBy::NetworkImpact,
+        QueriesSortBy::NetworkImpact => QueriesSortBy::TotalImpact,
+    }
+}
+
+fn sort_by_title(sort_by: &QueriesSortBy) -> &'static str {
+    match sort_by {
+        QueriesSortBy::TotalImpact => "Total Impact",
+        QueriesSortBy::IOImpact => "IO Impact",
+        QueriesSortBy::CPUImpact => "CPU Impact",
+        QueriesSortBy::MemoryImpact => "Memory Impact",
+        QueriesSortBy::TimeImpact => "Time Impact",
+        QueriesSortBy::NetworkImpact => "Network Impact",
+    }
+}